@@ -0,0 +1,103 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use kv::{EvictionPolicy, MemTable, Storage};
+use std::thread;
+
+/// 并发访问的线程数，模拟大量客户端
+const THREADS: usize = 8;
+/// 每个线程每轮执行的命令数
+const OPS_PER_THREAD: usize = 10_000;
+/// 少量的热点table
+const TABLES: usize = 4;
+/// 每个table中的key数量
+const KEYS: usize = 1_000;
+/// 淘汰测试中内存上限能容纳的key数量
+const EVICT_KEYS: usize = 100_000;
+/// 淘汰测试每轮写入的新key数量
+const EVICT_OPS: usize = 1_000;
+
+fn prefilled() -> MemTable {
+    let store = MemTable::new();
+    for t in 0..TABLES {
+        for k in 0..KEYS {
+            store
+                .set(&format!("t{}", t), format!("k{}", k), k as i64)
+                .unwrap();
+        }
+    }
+    store
+}
+
+/// 每个线程按照read_ratio%的比例混合执行get和set
+fn run_workload(store: &MemTable, tables: &[String], keys: &[String], read_ratio: u64) {
+    thread::scope(|s| {
+        for i in 0..THREADS {
+            s.spawn(move || {
+                // 简单的线性同余随机数，保证每轮的访问序列一样
+                let mut seed = i as u64 + 1;
+                for _ in 0..OPS_PER_THREAD {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    let n = (seed >> 33) as usize;
+                    let table = &tables[n % TABLES];
+                    let key = &keys[n / TABLES % KEYS];
+                    if (seed >> 16) % 100 < read_ratio {
+                        store.get(table, key).unwrap();
+                    } else {
+                        store.set(table, key.as_str(), n as i64).unwrap();
+                    }
+                }
+            });
+        }
+    });
+}
+
+fn mixed_workload(c: &mut Criterion) {
+    let tables: Vec<_> = (0..TABLES).map(|t| format!("t{}", t)).collect();
+    let keys: Vec<_> = (0..KEYS).map(|k| format!("k{}", k)).collect();
+
+    let mut group = c.benchmark_group("mixed_workload");
+    group.throughput(Throughput::Elements((THREADS * OPS_PER_THREAD) as u64));
+    for read_ratio in [50, 90, 99] {
+        let store = prefilled();
+        group.bench_with_input(
+            BenchmarkId::new("read_percent", read_ratio),
+            &read_ratio,
+            |b, &ratio| b.iter(|| run_workload(&store, &tables, &keys, ratio)),
+        );
+    }
+    group.finish();
+}
+
+fn hgetall(c: &mut Criterion) {
+    let store = prefilled();
+    c.bench_function("get_all", |b| b.iter(|| store.get_all("t0").unwrap()));
+}
+
+/// 内存达到上限之后，每次写入新key都要采样淘汰一个key
+fn evicting_set(c: &mut Criterion) {
+    let fill = |store: &MemTable| {
+        for k in 0..EVICT_KEYS {
+            store.set("t0", format!("k{}", k), k as i64).unwrap();
+        }
+    };
+    let unlimited = MemTable::new();
+    fill(&unlimited);
+    let store = MemTable::with_memory_limit(unlimited.used_memory(), EvictionPolicy::Lru);
+    fill(&store);
+
+    let mut group = c.benchmark_group("evicting_set");
+    group.sample_size(10);
+    group.throughput(Throughput::Elements(EVICT_OPS as u64));
+    let mut next = EVICT_KEYS;
+    group.bench_function("lru", |b| {
+        b.iter(|| {
+            for _ in 0..EVICT_OPS {
+                store.set("t0", format!("k{}", next), next as i64).unwrap();
+                next += 1;
+            }
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, mixed_workload, hgetall, evicting_set);
+criterion_main!(benches);
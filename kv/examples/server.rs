@@ -1,35 +1,90 @@
-use anyhow::Result;
-use async_prost::AsyncProstStream;
-use futures::prelude::*;
-use kv::{CommandRequest, CommandResponse, MemTable, Service};
-use tokio::net::TcpListener;
-use tracing::info;
+use anyhow::{anyhow, Result};
+use kv::{
+    init_tracing, ClusterConfig, ClusterHandle, KvServer, ServerConfig, Service, StorageConfig,
+    TracingConfig,
+};
+use std::{collections::HashMap, time::Duration};
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+    // 设置KV_OTLP_ENDPOINT时把span导出到collector，需要打开otel feature
+    let config = TracingConfig {
+        otlp_endpoint: std::env::var("KV_OTLP_ENDPOINT").ok(),
+        ..Default::default()
+    };
+    let _guard = init_tracing(&config)?;
 
-    let addr = "127.0.0.1:9527";
-    let listener = TcpListener::bind(addr).await?;
-    info!("Start listening on {}", addr);
+    let service = Service::with_storage_configs(&storage_configs()?, Default::default());
+    // 设置KV_ADMIN_ADDR时开启web管理界面，比如127.0.0.1:9528
+    let config = ServerConfig {
+        admin_addr: std::env::var("KV_ADMIN_ADDR").ok(),
+        ..Default::default()
+    };
+    let server = KvServer::new(config, service.clone());
+    // 设置KV_NODE_ID和KV_PEERS时以集群模式运行，比如KV_PEERS=1=127.0.0.1:9600,2=127.0.0.1:9601
+    // KV_PEERS中可以有以后才加入的节点，KV_MEMBERS指定初始成员，默认是KV_PEERS中所有的节点
+    // 设置KV_JOIN时作为新节点启动，等待leader把它加入集群
+    let server = match cluster_config()? {
+        Some(config) => server.with_cluster(ClusterHandle::bind(config, service).await?),
+        None => server,
+    };
+    server.run().await?;
 
-    let service: Service = Service::new(MemTable::new());
-
-    loop {
-        let (stream, addr) = listener.accept().await?;
-        info!("Client: {:?} connected", addr);
-
-        let svc = service.clone();
-
-        tokio::spawn(async move {
-            let mut stream =
-                AsyncProstStream::<_, CommandRequest, CommandResponse, _>::from(stream).for_async();
+    Ok(())
+}
 
-            while let Some(Ok(cmd)) = stream.next().await {
-                let resp = svc.execute(cmd);
-                stream.send(resp).await.unwrap();
-            }
-            info!("Client {:?} disconnected", addr);
-        });
+// KV_DATABASES指定数据库的个数，默认是1
+// KV_STORAGE为每个数据库设置内存上限和淘汰策略，比如KV_STORAGE=0=67108864:lru,1=1048576:noeviction
+// 没有设置的数据库不限制内存
+fn storage_configs() -> Result<Vec<StorageConfig>> {
+    let count = match std::env::var("KV_DATABASES") {
+        Ok(count) => count.parse()?,
+        Err(_) => 1,
+    };
+    if count == 0 {
+        return Err(anyhow!("KV_DATABASES must be at least 1"));
     }
+    let mut configs = vec![StorageConfig::default(); count];
+    if let Ok(storage) = std::env::var("KV_STORAGE") {
+        for db in storage.split(',') {
+            let (id, config) = db
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid storage config: {}", db))?;
+            let id: usize = id.trim().parse()?;
+            *configs
+                .get_mut(id)
+                .ok_or_else(|| anyhow!("database {} does not exist", id))? = config.parse()?;
+        }
+    }
+    Ok(configs)
+}
+
+fn cluster_config() -> Result<Option<ClusterConfig>> {
+    let (id, peers) = match (std::env::var("KV_NODE_ID"), std::env::var("KV_PEERS")) {
+        (Ok(id), Ok(peers)) => (id.parse()?, peers),
+        _ => return Ok(None),
+    };
+    let peers = peers
+        .split(',')
+        .map(|peer| {
+            let (id, addr) = peer
+                .split_once('=')
+                .ok_or_else(|| anyhow!("invalid peer: {}", peer))?;
+            Ok((id.trim().parse()?, addr.trim().to_owned()))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+    let members = match (std::env::var("KV_JOIN"), std::env::var("KV_MEMBERS")) {
+        (Ok(_), _) => Vec::new(),
+        (_, Ok(members)) => members
+            .split(',')
+            .map(|id| id.trim().parse())
+            .collect::<Result<_, _>>()?,
+        _ => peers.keys().copied().collect(),
+    };
+    Ok(Some(ClusterConfig {
+        id,
+        members,
+        peers,
+        tick_interval: Duration::from_millis(50),
+    }))
 }
@@ -0,0 +1,22 @@
+#![no_main]
+
+use kv::{CommandRequest, ServerStream};
+use libfuzzer_sys::fuzz_target;
+use tokio::io::AsyncWriteExt;
+
+// 服务器从连接上读到任意的字节，都只会返回错误或者断开，不会panic
+fuzz_target!(|data: &[u8]| {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    rt.block_on(async {
+        let (mut client, server) = tokio::io::duplex(data.len().max(1));
+        client.write_all(data).await.unwrap();
+        drop(client);
+
+        let mut stream: ServerStream<_> = ServerStream::new(server);
+        while let Some(res) = stream.recv().await {
+            let _: Result<CommandRequest, _> = res;
+        }
+    });
+});
@@ -0,0 +1,22 @@
+#![no_main]
+
+use kv::{dispatch, CommandRequest, MemTable};
+use libfuzzer_sys::fuzz_target;
+use prost::Message;
+
+// 任意的字节解码成CommandRequest不会panic，解码成功的请求重新编码后结果不变，执行时也不会panic
+fuzz_target!(|data: &[u8]| {
+    let cmd = match CommandRequest::decode(data) {
+        Ok(cmd) => cmd,
+        Err(_) => return,
+    };
+    // 值里可能有NaN，比较编码之后的字节
+    let encoded = cmd.encode_to_vec();
+    let decoded = CommandRequest::decode(encoded.as_slice()).unwrap();
+    assert_eq!(decoded.encode_to_vec(), encoded);
+
+    // Eval会执行任意的lua脚本，交给脚本自己的测试
+    if cmd.name() != "Eval" {
+        dispatch(cmd, &MemTable::new());
+    }
+});
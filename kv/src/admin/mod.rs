@@ -0,0 +1,488 @@
+use crate::{dump::typed_json, *};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use bytes::Bytes;
+use serde_json::{json, Map, Value as JsonValue};
+use std::{collections::HashMap, sync::Arc};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// 管理界面的页面，所有的数据都通过下面的/api读写
+const INDEX_HTML: &str = include_str!("index.html");
+
+/// web管理界面的路由，读写数据都转换成已有的命令交给service执行，
+/// 集群模式下交给集群执行，和客户端的命令一样经过raft复制
+pub fn admin_router<Store>(
+    service: Service<Store>,
+    stats: Arc<ServerStats>,
+    cluster: Option<ClusterHandle>,
+) -> Router
+where
+    Store: Storage + Send + Sync + 'static,
+{
+    Router::new()
+        .route("/", get(|| async { Html(INDEX_HTML) }))
+        .route("/api/databases", get(databases))
+        .route("/api/tables", get(tables))
+        .route("/api/tables/:table", get(table))
+        .route(
+            "/api/tables/:table/:key",
+            get(get_key).put(set_key).delete(del_key),
+        )
+        .route("/api/stats", get(server_stats))
+        .route("/api/slowlog", get(slow_log))
+        .with_state(AdminState {
+            service,
+            stats,
+            cluster,
+        })
+}
+
+/// 在listener上提供管理界面，出错时只记录日志
+pub async fn serve_admin(listener: TcpListener, router: Router) {
+    let res = async {
+        axum::Server::from_tcp(listener.into_std()?)
+            .map_err(|e| KvError::Internal(e.to_string()))?
+            .serve(router.into_make_service())
+            .await
+            .map_err(|e| KvError::Internal(e.to_string()))
+    };
+    if let Err(e) = res.await {
+        warn!("Admin console stopped with error: {}", e);
+    }
+}
+
+struct AdminState<Store> {
+    service: Service<Store>,
+    stats: Arc<ServerStats>,
+    cluster: Option<ClusterHandle>,
+}
+
+impl<Store> Clone for AdminState<Store> {
+    fn clone(&self) -> Self {
+        Self {
+            service: self.service.clone(),
+            stats: Arc::clone(&self.stats),
+            cluster: self.cluster.clone(),
+        }
+    }
+}
+
+/// 请求的query参数，所有的/api都可以用?db=选择数据库，默认是第0个
+type Params = Query<HashMap<String, String>>;
+
+impl<Store: Storage> AdminState<Store> {
+    fn select(&self, params: &HashMap<String, String>) -> Result<Service<Store>, AdminError> {
+        let db = match params.get("db") {
+            Some(db) => db
+                .parse()
+                .map_err(|_| AdminError(StatusCode::BAD_REQUEST, format!("invalid db: {}", db)))?,
+            None => 0,
+        };
+        self.service
+            .select(db)
+            .map_err(|e| AdminError(StatusCode::BAD_REQUEST, e.to_string()))
+    }
+
+    /// 在所选的数据库上执行命令，集群只复制第0个数据库
+    async fn execute(
+        &self,
+        params: &HashMap<String, String>,
+        cmd: CommandRequest,
+    ) -> Result<CommandResponse, AdminError> {
+        let cluster = match &self.cluster {
+            Some(cluster) => cluster,
+            None => return check(self.select(params)?.execute(cmd)),
+        };
+        if params.get("db").is_some_and(|db| db != "0") {
+            return Err(AdminError(
+                StatusCode::BAD_REQUEST,
+                "select is not supported in cluster mode".into(),
+            ));
+        }
+        check(cluster.execute(cmd).await)
+    }
+}
+
+type AdminResult = Result<Json<JsonValue>, AdminError>;
+
+/// 返回给浏览器的错误：{"error": "..."}
+struct AdminError(StatusCode, String);
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+async fn databases<Store: Storage>(State(state): State<AdminState<Store>>) -> AdminResult {
+    let count = match state.cluster {
+        Some(_) => 1,
+        None => state.service.databases(),
+    };
+    Ok(Json(json!(count)))
+}
+
+async fn tables<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Query(params): Params,
+) -> AdminResult {
+    let res = state.execute(&params, CommandRequest::new_tables()).await?;
+    let mut tables: Vec<_> = res
+        .values
+        .iter()
+        .filter_map(|v| match &v.value {
+            Some(value::Value::String(s)) => Some(s.as_str()),
+            _ => None,
+        })
+        .collect();
+    tables.sort_unstable();
+    Ok(Json(json!(tables)))
+}
+
+async fn table<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Path(table): Path<String>,
+    Query(params): Params,
+) -> AdminResult {
+    let mut res = state
+        .execute(&params, CommandRequest::new_hgetall(table))
+        .await?;
+    res.pairs.sort_by(|a, b| a.key.cmp(&b.key));
+    let pairs = res
+        .pairs
+        .iter()
+        .map(|p| json!({ "key": p.key, "value": value_to_json(p.value.as_ref()) }))
+        .collect();
+    Ok(Json(JsonValue::Array(pairs)))
+}
+
+async fn get_key<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Path((table, key)): Path<(String, String)>,
+    Query(params): Params,
+) -> AdminResult {
+    let res = state
+        .execute(&params, CommandRequest::new_hget(table, key))
+        .await?;
+    Ok(Json(value_to_json(res.values.first())))
+}
+
+/// 请求的body和返回的值格式一样：{"type": "string", "value": "..."}
+async fn set_key<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Path((table, key)): Path<(String, String)>,
+    Query(params): Params,
+    Json(body): Json<JsonValue>,
+) -> AdminResult {
+    let value = json_to_value(&body).map_err(|e| AdminError(StatusCode::BAD_REQUEST, e))?;
+    let res = state
+        .execute(&params, CommandRequest::new_hset(table, key, value))
+        .await?;
+    Ok(Json(value_to_json(res.values.first())))
+}
+
+async fn del_key<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Path((table, key)): Path<(String, String)>,
+    Query(params): Params,
+) -> AdminResult {
+    let res = state
+        .execute(&params, CommandRequest::new_hdel(table, key))
+        .await?;
+    Ok(Json(value_to_json(res.values.first())))
+}
+
+/// 连接和命令的统计是整个服务器的，存储的信息是所选数据库的
+async fn server_stats<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Query(params): Params,
+) -> AdminResult {
+    let info = state.execute(&params, CommandRequest::new_info()).await?;
+    let storage: Map<_, _> = info
+        .pairs
+        .iter()
+        .map(|p| {
+            let value = value_to_json(p.value.as_ref());
+            (p.key.clone(), value["value"].clone())
+        })
+        .collect();
+    let commands: Vec<_> = state
+        .service
+        .command_stats()
+        .into_iter()
+        .map(|(name, s)| {
+            json!({
+                "command": name,
+                "calls": s.calls,
+                "errors": s.errors,
+                "avg_us": s.total_us / s.calls.max(1),
+            })
+        })
+        .collect();
+    Ok(Json(json!({
+        "connections": {
+            "active": state.stats.active_connections(),
+            "total": state.stats.total_connections(),
+            "rejected": state.stats.rejected_connections(),
+        },
+        "commands": commands,
+        "storage": storage,
+    })))
+}
+
+async fn slow_log<Store: Storage>(
+    State(state): State<AdminState<Store>>,
+    Query(params): Params,
+) -> AdminResult {
+    let count = params
+        .get("count")
+        .and_then(|c| c.parse().ok())
+        .unwrap_or(0);
+    // 慢命令是本节点记录的，不需要经过集群
+    let res = check(
+        state
+            .service
+            .execute(CommandRequest::new_slow_log(count, false)),
+    )?;
+    let entries = res
+        .slow_logs
+        .iter()
+        .map(|e| {
+            json!({
+                "id": e.id,
+                "timestamp": e.timestamp,
+                "duration_us": e.duration_us,
+                "command": e.command,
+                "table": e.table,
+                "key": e.key,
+            })
+        })
+        .collect();
+    Ok(Json(JsonValue::Array(entries)))
+}
+
+// 命令失败时转换成对应状态码的响应
+fn check(res: CommandResponse) -> Result<CommandResponse, AdminError> {
+    match StatusCode::from_u16(res.status as _) {
+        Ok(status) if status.is_success() => Ok(res),
+        Ok(status) => Err(AdminError(status, res.message)),
+        Err(_) => Err(AdminError(StatusCode::INTERNAL_SERVER_ERROR, res.message)),
+    }
+}
+
+/// 带类型的JSON表示，binary用十六进制的字符串表示
+fn value_to_json(v: Option<&Value>) -> JsonValue {
+    let (ty, value) = typed_json(v);
+    json!({ "type": ty, "value": value })
+}
+
+fn json_to_value(v: &JsonValue) -> Result<Value, String> {
+    let value = &v["value"];
+    let invalid = || format!("invalid {} value: {}", v["type"], value);
+    match v["type"].as_str() {
+        Some("string") => value.as_str().map(Value::from).ok_or_else(invalid),
+        Some("binary") => {
+            let hex = value.as_str().ok_or_else(invalid)?;
+            if hex.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            (0..hex.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                .collect::<Option<Vec<_>>>()
+                .map(|b| Bytes::from(b).into())
+                .ok_or_else(invalid)
+        }
+        Some("integer") => value.as_i64().map(Value::from).ok_or_else(invalid),
+        Some("float") => value.as_f64().map(Value::from).ok_or_else(invalid),
+        Some("bool") => value.as_bool().map(Value::from).ok_or_else(invalid),
+        _ => Err(format!("unknown value type: {}", v["type"])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{body::Body, http::Request};
+    use std::time::Duration;
+    use tokio::time;
+    use tower::ServiceExt;
+
+    async fn call(router: &Router, method: &str, uri: &str, body: JsonValue) -> (u16, JsonValue) {
+        let req = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let res = router.clone().oneshot(req).await.unwrap();
+        let status = res.status().as_u16();
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    fn router() -> Router {
+        let service =
+            Service::with_databases(vec![MemTable::new(), MemTable::new()], Default::default());
+        admin_router(service, Arc::default(), None)
+    }
+
+    #[tokio::test]
+    async fn admin_should_edit_and_browse_tables() {
+        let router = router();
+        let body = json!({ "type": "binary", "value": "00ff" });
+        let (status, _) = call(&router, "PUT", "/api/tables/t1/k1", body).await;
+        assert_eq!(status, 200);
+        let body = json!({ "type": "string", "value": r#"{"a":1}"# });
+        call(&router, "PUT", "/api/tables/t1/k2", body).await;
+
+        let (_, tables) = call(&router, "GET", "/api/tables", JsonValue::Null).await;
+        assert_eq!(tables, json!(["t1"]));
+        let (_, pairs) = call(&router, "GET", "/api/tables/t1", JsonValue::Null).await;
+        assert_eq!(
+            pairs,
+            json!([
+                { "key": "k1", "value": { "type": "binary", "value": "00ff" } },
+                { "key": "k2", "value": { "type": "string", "value": r#"{"a":1}"# } },
+            ])
+        );
+
+        let (status, old) = call(&router, "DELETE", "/api/tables/t1/k1", JsonValue::Null).await;
+        assert_eq!(status, 200);
+        assert_eq!(old, json!({ "type": "binary", "value": "00ff" }));
+        let (status, res) = call(&router, "GET", "/api/tables/t1/k1", JsonValue::Null).await;
+        assert_eq!(status, 404);
+        assert!(res["error"].as_str().unwrap().contains("Not found"));
+    }
+
+    #[tokio::test]
+    async fn admin_should_select_database() {
+        let router = router();
+        let (_, count) = call(&router, "GET", "/api/databases", JsonValue::Null).await;
+        assert_eq!(count, json!(2));
+
+        let body = json!({ "type": "integer", "value": 1 });
+        let (status, _) = call(&router, "PUT", "/api/tables/t1/k1?db=1", body).await;
+        assert_eq!(status, 200);
+        let (_, tables) = call(&router, "GET", "/api/tables?db=1", JsonValue::Null).await;
+        assert_eq!(tables, json!(["t1"]));
+        let (_, tables) = call(&router, "GET", "/api/tables", JsonValue::Null).await;
+        assert_eq!(tables, json!([]));
+        let (_, stats) = call(&router, "GET", "/api/stats?db=1", JsonValue::Null).await;
+        assert_eq!(stats["storage"]["keys"], 1);
+
+        for uri in ["/api/tables?db=2", "/api/tables?db=x"] {
+            let (status, _) = call(&router, "GET", uri, JsonValue::Null).await;
+            assert_eq!(status, 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_should_reject_invalid_values() {
+        let router = router();
+        for body in [
+            json!({ "type": "integer", "value": "abc" }),
+            json!({ "type": "binary", "value": "0g" }),
+            json!({ "type": "json", "value": 1 }),
+        ] {
+            let (status, _) = call(&router, "PUT", "/api/tables/t1/k1", body).await;
+            assert_eq!(status, 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_should_report_stats() {
+        let router = router();
+        let body = json!({ "type": "integer", "value": 1 });
+        call(&router, "PUT", "/api/tables/t1/k1", body).await;
+
+        let (_, stats) = call(&router, "GET", "/api/stats", JsonValue::Null).await;
+        assert_eq!(stats["connections"]["active"], 0);
+        assert_eq!(stats["storage"]["keys"], 1);
+        let hset = stats["commands"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|c| c["command"] == "Hset")
+            .unwrap();
+        assert_eq!(hset["calls"], 1);
+    }
+
+    #[tokio::test]
+    async fn admin_should_execute_through_cluster() {
+        let mut listeners = Vec::new();
+        let mut peers = HashMap::new();
+        for id in 1..=3 {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            peers.insert(id, listener.local_addr().unwrap().to_string());
+            listeners.push((id, listener));
+        }
+        let nodes: Vec<_> = listeners
+            .into_iter()
+            .map(|(id, listener)| {
+                let config = ClusterConfig {
+                    id,
+                    members: vec![1, 2, 3],
+                    peers: peers.clone(),
+                    tick_interval: Duration::from_millis(10),
+                };
+                let service = Service::new(MemTable::new());
+                let cluster = ClusterHandle::start(config, listener, service.clone());
+                let router = admin_router(service.clone(), Arc::default(), Some(cluster));
+                (service, router)
+            })
+            .collect();
+
+        // 只有leader能写入，follower返回421，不会直接写到本地
+        let body = json!({ "type": "string", "value": "v1" });
+        let mut leader = None;
+        for _ in 0..200 {
+            for (i, (_, router)) in nodes.iter().enumerate() {
+                let (status, _) = call(router, "PUT", "/api/tables/t1/k1", body.clone()).await;
+                if status == 200 {
+                    leader = Some(i);
+                    break;
+                }
+                assert_eq!(status, 421);
+            }
+            if leader.is_some() {
+                break;
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        let leader = leader.expect("no leader elected");
+        let follower = &nodes[(leader + 1) % 3].1;
+        let (status, _) = call(follower, "PUT", "/api/tables/t1/k2", body.clone()).await;
+        assert_eq!(status, 421);
+
+        // 写入经过raft复制到所有节点
+        for (service, _) in &nodes {
+            let mut res = service.execute(CommandRequest::new_hget("t1", "k1"));
+            for _ in 0..100 {
+                if res.status == 200 {
+                    break;
+                }
+                time::sleep(Duration::from_millis(20)).await;
+                res = service.execute(CommandRequest::new_hget("t1", "k1"));
+            }
+            assert_eq!(res.values, vec!["v1".into()]);
+            let res = service.execute(CommandRequest::new_hget("t1", "k2"));
+            assert_eq!(res.status, 404);
+        }
+
+        let router = &nodes[leader].1;
+        let (_, count) = call(router, "GET", "/api/databases", JsonValue::Null).await;
+        assert_eq!(count, json!(1));
+        let (_, value) = call(router, "GET", "/api/tables/t1/k1", JsonValue::Null).await;
+        assert_eq!(value, json!({ "type": "string", "value": "v1" }));
+        let (status, res) = call(router, "GET", "/api/tables?db=1", JsonValue::Null).await;
+        assert_eq!(status, 400);
+        assert!(res["error"].as_str().unwrap().contains("cluster mode"));
+    }
+}
@@ -0,0 +1,86 @@
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use kv::*;
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter},
+    path::PathBuf,
+};
+use tracing::info;
+
+/// 导出kv服务器中的所有数据，或者把导出的数据恢复到kv服务器
+#[derive(Parser, Debug)]
+#[clap(version = "1.0", author = "JoJo")]
+struct Opts {
+    /// kv服务器的地址
+    #[clap(short, long, default_value = "127.0.0.1:9527")]
+    addr: String,
+    /// 导出或者恢复的数据库
+    #[clap(long, default_value = "0")]
+    db: u32,
+    #[clap(subcommand)]
+    subcmd: SubCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum SubCommand {
+    Dump(Dump),
+    Restore(Restore),
+}
+
+/// 导出所有的table到文件
+#[derive(Parser, Debug)]
+struct Dump {
+    /// 导出的文件
+    output: PathBuf,
+    /// 导出成方便阅读的JSON，JSON文件不能用来restore
+    #[clap(long)]
+    json: bool,
+}
+
+/// 从dump文件中恢复数据
+#[derive(Parser, Debug)]
+struct Restore {
+    /// dump文件
+    input: PathBuf,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let opts = Opts::parse();
+    let mut client = KvClient::connect(&opts.addr).await?;
+    if opts.db != 0 {
+        check(&client.execute(&CommandRequest::new_select(opts.db)).await?)?;
+    }
+
+    match opts.subcmd {
+        SubCommand::Dump(args) => {
+            let data = client.dump().await?;
+            let writer = BufWriter::new(File::create(&args.output)?);
+            let count = match args.json {
+                true => {
+                    serde_json::to_writer_pretty(writer, &to_json(&data))?;
+                    data.count()
+                }
+                false => write_dump(&data, writer)?,
+            };
+            info!("Dumped {} items to {:?}", count, args.output);
+        }
+        SubCommand::Restore(args) => {
+            let reader = BufReader::new(File::open(&args.input)?);
+            let count = client.restore(reader).await?;
+            info!("Restored {} items from {:?}", count, args.input);
+        }
+    }
+
+    Ok(())
+}
+
+fn check(res: &CommandResponse) -> Result<()> {
+    match res.status {
+        200 => Ok(()),
+        status => Err(anyhow!("server error {}: {}", status, res.message)),
+    }
+}
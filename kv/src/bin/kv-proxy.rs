@@ -0,0 +1,36 @@
+use anyhow::Result;
+use clap::Parser;
+use kv::*;
+
+/// 按一致性哈希把请求转发到多个kv服务器的代理
+#[derive(Parser, Debug)]
+#[clap(version = "1.0", author = "JoJo")]
+struct Opts {
+    /// 代理监听的地址
+    #[clap(short, long, default_value = "127.0.0.1:9526")]
+    addr: String,
+    /// 后端kv服务器的地址，可以指定多次
+    #[clap(short, long = "node", required = true)]
+    nodes: Vec<String>,
+    /// 按table和key分片，默认只按table分片
+    #[clap(long)]
+    by_key: bool,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let opts = Opts::parse();
+    let config = ProxyConfig {
+        addr: opts.addr,
+        nodes: opts.nodes,
+        sharding: match opts.by_key {
+            true => Sharding::TableKey,
+            false => Sharding::Table,
+        },
+        ..Default::default()
+    };
+    KvProxy::new(config).run().await?;
+    Ok(())
+}
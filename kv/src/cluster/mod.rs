@@ -0,0 +1,637 @@
+use crate::command_request::RequestData;
+use crate::*;
+use std::collections::HashMap;
+use tokio::sync::oneshot;
+use tracing::warn;
+
+mod raft;
+mod transport;
+
+pub use raft::{
+    EntryData, Envelope, LogEntry, NodeId, ProposeError, Raft, RaftMessage, Role, Snapshot,
+    ELECTION_TICKS, HEARTBEAT_TICKS,
+};
+pub use transport::{ClusterConfig, ClusterHandle, RaftStream};
+
+/// 每应用多少条日志压缩一次
+pub const SNAPSHOT_THRESHOLD: u64 = 1024;
+
+/// 集群中的一个kv节点：修改存储的命令先写入raft日志，提交之后再应用到Service，
+/// 读命令通过read index确认leader身份之后直接读本地的存储，保证线性一致。
+/// ClusterNode不做任何IO，上层定时调用tick()，把take_messages()取出的消息发送给其它节点，
+/// 收到的消息交给step()处理。ClusterHandle在后台任务中通过TCP驱动ClusterNode。
+/// 每应用SNAPSHOT_THRESHOLD条日志，把存储导出成快照并压缩日志。
+/// raft的状态和数据一样只保存在内存中，集群模式不保证节点崩溃之后可以恢复：
+/// 重启的节点要先用remove_node()删除原来的id，再用新的id重新加入集群
+pub struct ClusterNode<Store = MemTable> {
+    raft: Raft,
+    service: Service<Store>,
+    snapshot_threshold: u64,
+    // 等待提交的请求，key是日志的index，value是日志的term和响应的channel
+    proposals: HashMap<u64, (u64, oneshot::Sender<CommandResponse>)>,
+    next_read_id: u64,
+    // 等待确认leader身份的读请求
+    pending_reads: HashMap<u64, (CommandRequest, oneshot::Sender<CommandResponse>)>,
+    // 已经确认leader身份，等待日志应用到index的读请求
+    ready_reads: Vec<(u64, CommandRequest, oneshot::Sender<CommandResponse>)>,
+}
+
+impl<Store: Storage> ClusterNode<Store> {
+    /// 创建集群的初始节点
+    pub fn new(
+        id: NodeId,
+        members: impl IntoIterator<Item = NodeId>,
+        service: Service<Store>,
+    ) -> Self {
+        Self::with_raft(Raft::new(id, members), service)
+    }
+
+    /// 创建一个要加入集群的节点，需要在leader上调用add_node()把它加入集群
+    pub fn joining(id: NodeId, service: Service<Store>) -> Self {
+        Self::with_raft(Raft::new_joining(id), service)
+    }
+
+    fn with_raft(raft: Raft, service: Service<Store>) -> Self {
+        Self {
+            raft,
+            service,
+            snapshot_threshold: SNAPSHOT_THRESHOLD,
+            proposals: HashMap::new(),
+            next_read_id: 0,
+            pending_reads: HashMap::new(),
+            ready_reads: Vec::new(),
+        }
+    }
+
+    pub fn raft(&self) -> &Raft {
+        &self.raft
+    }
+
+    pub fn service(&self) -> &Service<Store> {
+        &self.service
+    }
+
+    /// 执行命令，命令提交（或者读请求确认）之后通过channel返回响应
+    pub fn execute(&mut self, cmd: CommandRequest) -> oneshot::Receiver<CommandResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.execute_with(cmd, tx);
+        rx
+    }
+
+    fn execute_with(&mut self, cmd: CommandRequest, tx: oneshot::Sender<CommandResponse>) {
+        if is_streaming(&cmd) {
+            let err = KvError::InvalidCommand(
+                "streaming command is not supported in cluster mode".into(),
+            );
+            return reply(tx, err.into());
+        }
+        match is_read(&cmd) {
+            true => {
+                let id = self.next_read_id;
+                self.next_read_id += 1;
+                match self.raft.read_index(id) {
+                    Ok(()) => {
+                        self.pending_reads.insert(id, (cmd, tx));
+                    }
+                    Err(e) => reply(tx, KvError::from(e).into()),
+                }
+            }
+            false => self.propose(EntryData::Command(cmd), tx),
+        }
+        self.advance();
+    }
+
+    /// 把节点加入集群，成员变更提交之后返回
+    pub fn add_node(&mut self, id: NodeId) -> oneshot::Receiver<CommandResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.change_members(EntryData::AddNode(id), tx);
+        rx
+    }
+
+    /// 把节点从集群中删除，成员变更提交之后返回
+    pub fn remove_node(&mut self, id: NodeId) -> oneshot::Receiver<CommandResponse> {
+        let (tx, rx) = oneshot::channel();
+        self.change_members(EntryData::RemoveNode(id), tx);
+        rx
+    }
+
+    fn change_members(&mut self, data: EntryData, tx: oneshot::Sender<CommandResponse>) {
+        self.propose(data, tx);
+        self.advance();
+    }
+
+    fn propose(&mut self, data: EntryData, tx: oneshot::Sender<CommandResponse>) {
+        match self.raft.propose(data) {
+            Ok((index, term)) => {
+                self.proposals.insert(index, (term, tx));
+            }
+            Err(e) => reply(tx, KvError::from(e).into()),
+        }
+    }
+
+    /// 推进一个时间单位
+    pub fn tick(&mut self) {
+        self.raft.tick();
+        self.advance();
+    }
+
+    /// 处理其它节点发来的消息
+    pub fn step(&mut self, envelope: Envelope) {
+        if envelope.to != self.raft.id() {
+            warn!("Node {} got message for {}", self.raft.id(), envelope.to);
+            return;
+        }
+        self.raft.step(envelope.from, envelope.msg);
+        self.advance();
+    }
+
+    /// 取出要发送给其它节点的消息
+    pub fn take_messages(&mut self) -> Vec<Envelope> {
+        self.raft.take_messages()
+    }
+
+    // 应用已经提交的日志，处理可以返回的读请求
+    fn advance(&mut self) {
+        if let Some(snapshot) = self.raft.take_snapshot() {
+            self.install_snapshot(snapshot);
+        }
+        for entry in self.raft.take_committed() {
+            let res = match entry.data {
+                EntryData::Command(cmd) => self.service.execute(cmd),
+                _ => CommandResponse::ok(),
+            };
+            if let Some((term, tx)) = self.proposals.remove(&entry.index) {
+                // 日志被新的leader覆盖了，请求没有执行
+                match term == entry.term {
+                    true => reply(tx, res),
+                    false => reply(tx, KvError::NotLeader(self.raft.leader_id()).into()),
+                }
+            }
+        }
+        self.maybe_compact();
+
+        for (id, index) in self.raft.take_ready_reads() {
+            if let Some((cmd, tx)) = self.pending_reads.remove(&id) {
+                self.ready_reads.push((index, cmd, tx));
+            }
+        }
+        // 不再是leader时，没有确认的读请求不会再被确认了
+        if !self.raft.is_leader() {
+            for (_, (_, tx)) in self.pending_reads.drain() {
+                reply(tx, KvError::NotLeader(self.raft.leader_id()).into());
+            }
+        }
+
+        let applied = self.raft.last_applied();
+        let (ready, waiting) = std::mem::take(&mut self.ready_reads)
+            .into_iter()
+            .partition(|(index, _, _)| *index <= applied);
+        self.ready_reads = waiting;
+        for (_, cmd, tx) in ready {
+            reply(tx, self.service.execute(cmd));
+        }
+    }
+
+    // 用leader发来的快照替换本地的数据
+    fn install_snapshot(&mut self, snapshot: Snapshot) {
+        let res = self
+            .service
+            .clear()
+            .and_then(|_| restore(&self.service, &snapshot.data[..]));
+        if let Err(e) = res {
+            warn!("Node {} failed to install snapshot: {}", self.raft.id(), e);
+        }
+        // 快照覆盖的请求没法知道是否执行了
+        let covered: Vec<_> = self
+            .proposals
+            .keys()
+            .copied()
+            .filter(|index| *index <= snapshot.index)
+            .collect();
+        for index in covered {
+            if let Some((_, tx)) = self.proposals.remove(&index) {
+                reply(tx, KvError::NotLeader(self.raft.leader_id()).into());
+            }
+        }
+    }
+
+    // 应用的日志足够多时，把存储导出成快照，压缩已经应用的日志
+    fn maybe_compact(&mut self) {
+        let applied = self.raft.last_applied();
+        if applied - self.raft.snapshot().index < self.snapshot_threshold {
+            return;
+        }
+        match self.service.snapshot() {
+            Ok(data) => self.raft.compact(applied, data.into()),
+            Err(e) => warn!("Node {} failed to create snapshot: {}", self.raft.id(), e),
+        }
+    }
+}
+
+// 请求方可能已经不再等待响应了，忽略发送的错误
+fn reply(tx: oneshot::Sender<CommandResponse>, res: CommandResponse) {
+    tx.send(res).ok();
+}
+
+/// 不修改存储的命令不需要写入raft日志
+fn is_read(cmd: &CommandRequest) -> bool {
+    matches!(
+        cmd.request_data,
+        Some(
+            RequestData::Hget(_)
+                | RequestData::Hgetall(_)
+                | RequestData::Hscan(_)
+                | RequestData::Hexist(_)
+                | RequestData::Info(_)
+                | RequestData::Tables(_)
+                | RequestData::Zrange(_)
+                | RequestData::Zrank(_)
+                | RequestData::Lrange(_)
+                | RequestData::GetChunk(_)
+                | RequestData::SlowLog(_)
+        )
+    )
+}
+
+/// 流式命令的结果依赖本地的订阅或者阻塞等待，没法通过日志复制，集群模式下不支持
+fn is_streaming(cmd: &CommandRequest) -> bool {
+    matches!(
+        cmd.request_data,
+        Some(RequestData::Watch(_) | RequestData::Brpop(_))
+    )
+}
+
+impl From<ProposeError> for KvError {
+    fn from(e: ProposeError) -> Self {
+        match e {
+            ProposeError::NotLeader(leader) => KvError::NotLeader(leader),
+            ProposeError::ConfChangeInProgress => KvError::ConfChangeInProgress,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prost::Message;
+    use std::collections::{BTreeMap, HashSet};
+    use std::time::Duration;
+
+    /// 在进程内模拟的网络，可以把节点分成互相不通的分区
+    struct Network {
+        nodes: BTreeMap<NodeId, ClusterNode>,
+        partitions: Vec<HashSet<NodeId>>,
+    }
+
+    impl Network {
+        fn new(n: u64) -> Self {
+            let ids: Vec<_> = (1..=n).collect();
+            let nodes = ids
+                .iter()
+                .map(|id| {
+                    let node = ClusterNode::new(*id, ids.clone(), Service::new(MemTable::new()));
+                    (*id, node)
+                })
+                .collect();
+            Self {
+                nodes,
+                partitions: Vec::new(),
+            }
+        }
+
+        fn node(&mut self, id: NodeId) -> &mut ClusterNode {
+            self.nodes.get_mut(&id).unwrap()
+        }
+
+        fn partition(&mut self, groups: &[&[NodeId]]) {
+            self.partitions = groups.iter().map(|g| g.iter().copied().collect()).collect();
+        }
+
+        fn heal(&mut self) {
+            self.partitions.clear();
+        }
+
+        fn connected(&self, a: NodeId, b: NodeId) -> bool {
+            self.partitions.is_empty()
+                || self
+                    .partitions
+                    .iter()
+                    .any(|g| g.contains(&a) && g.contains(&b))
+        }
+
+        fn deliver(&mut self) {
+            self.deliver_with(|_| {});
+        }
+
+        // 投递消息直到没有新的消息，每条消息投递之前先交给f检查
+        fn deliver_with(&mut self, mut f: impl FnMut(&Envelope)) {
+            loop {
+                let messages: Vec<_> = self
+                    .nodes
+                    .values_mut()
+                    .flat_map(|n| n.take_messages())
+                    .collect();
+                if messages.is_empty() {
+                    break;
+                }
+                for envelope in messages {
+                    if self.connected(envelope.from, envelope.to) {
+                        f(&envelope);
+                        if let Some(node) = self.nodes.get_mut(&envelope.to) {
+                            node.step(envelope);
+                        }
+                    }
+                }
+            }
+        }
+
+        fn tick(&mut self, n: u32) {
+            for _ in 0..n {
+                for node in self.nodes.values_mut() {
+                    node.tick();
+                }
+                self.deliver();
+            }
+        }
+
+        /// 等待ids中的节点选出一个leader
+        fn wait_leader(&mut self, ids: &[NodeId]) -> NodeId {
+            for _ in 0..ELECTION_TICKS * 20 {
+                self.tick(1);
+                let leaders: Vec<_> = ids
+                    .iter()
+                    .copied()
+                    .filter(|id| self.nodes[id].raft().is_leader())
+                    .collect();
+                if leaders.len() == 1 {
+                    return leaders[0];
+                }
+            }
+            panic!("no leader elected among {:?}", ids);
+        }
+
+        fn execute(&mut self, id: NodeId, cmd: CommandRequest) -> CommandResponse {
+            let rx = self.node(id).execute(cmd);
+            self.wait(rx)
+        }
+
+        fn wait(&mut self, mut rx: oneshot::Receiver<CommandResponse>) -> CommandResponse {
+            for _ in 0..ELECTION_TICKS * 20 {
+                self.deliver();
+                if let Ok(res) = rx.try_recv() {
+                    return res;
+                }
+                self.tick(1);
+            }
+            panic!("request timed out");
+        }
+
+        // 直接读取节点本地的存储
+        fn local_get(&self, id: NodeId, key: &str) -> CommandResponse {
+            self.nodes[&id]
+                .service()
+                .execute(CommandRequest::new_hget("t1", key))
+        }
+    }
+
+    #[test]
+    fn single_node_cluster_should_work() {
+        let mut net = Network::new(1);
+        let leader = net.wait_leader(&[1]);
+
+        let res = net.execute(leader, CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = net.execute(leader, CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &["v1".into()], &[]);
+    }
+
+    #[test]
+    fn writes_should_be_replicated_to_all_nodes() {
+        let mut net = Network::new(3);
+        let leader = net.wait_leader(&[1, 2, 3]);
+
+        let res = net.execute(leader, CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+        net.tick(HEARTBEAT_TICKS);
+        for id in 1..=3 {
+            assert_res_ok(net.local_get(id, "k1"), &["v1".into()], &[]);
+        }
+    }
+
+    #[test]
+    fn followers_should_redirect_to_leader() {
+        let mut net = Network::new(3);
+        let leader = net.wait_leader(&[1, 2, 3]);
+        net.tick(HEARTBEAT_TICKS);
+        let follower = (1..=3).find(|id| *id != leader).unwrap();
+
+        let res = net.execute(follower, CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert_res_error(res, 421, &format!("Some({})", leader));
+        let res = net.execute(follower, CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 421, "Not leader");
+    }
+
+    #[test]
+    fn partitioned_leader_should_not_commit_or_serve_reads() {
+        let mut net = Network::new(3);
+        let old = net.wait_leader(&[1, 2, 3]);
+        net.execute(old, CommandRequest::new_hset("t1", "k1", "v1".into()));
+        let others: Vec<_> = (1..=3).filter(|id| *id != old).collect();
+
+        // leader被隔离之后，写入的数据不能提交，读请求也不能确认
+        net.partition(&[&[old], &others]);
+        let write = net
+            .node(old)
+            .execute(CommandRequest::new_hset("t1", "k1", "lost".into()));
+        let mut read = net.node(old).execute(CommandRequest::new_hget("t1", "k1"));
+
+        let new = net.wait_leader(&others);
+        let res = net.execute(new, CommandRequest::new_hset("t1", "k1", "v2".into()));
+        assert_res_ok(res, &["v1".into()], &[]);
+        assert!(read.try_recv().is_err());
+
+        // 网络恢复之后，旧的leader退回follower，没有提交的日志被覆盖
+        net.heal();
+        assert_res_error(net.wait(write), 421, "Not leader");
+        net.tick(HEARTBEAT_TICKS * 2);
+        assert!(!net.nodes[&old].raft().is_leader());
+        for id in 1..=3 {
+            assert_res_ok(net.local_get(id, "k1"), &["v2".into()], &[]);
+        }
+    }
+
+    #[test]
+    fn streaming_commands_should_be_rejected() {
+        let mut net = Network::new(1);
+        let leader = net.wait_leader(&[1]);
+        let last = net.nodes[&leader].raft().last_index();
+
+        let res = net.execute(leader, CommandRequest::new_watch("t1", ""));
+        assert_res_error(res, 400, "not supported in cluster mode");
+        let res = net.execute(
+            leader,
+            CommandRequest::new_brpop("l1", Duration::from_secs(1)),
+        );
+        assert_res_error(res, 400, "not supported in cluster mode");
+        // 没有写入日志
+        assert_eq!(net.nodes[&leader].raft().last_index(), last);
+    }
+
+    #[test]
+    fn stale_append_entries_should_not_move_commit_index_back() {
+        let mut raft = Raft::new(2, [1, 2, 3]);
+        let entry = |index| LogEntry {
+            term: 1,
+            index,
+            data: EntryData::Noop,
+        };
+        let append = |entries: Vec<LogEntry>, leader_commit| RaftMessage::AppendEntries {
+            term: 1,
+            prev_log_index: 1,
+            prev_log_term: 0,
+            entries,
+            leader_commit,
+            read_seq: 0,
+        };
+
+        raft.step(1, append(vec![entry(2), entry(3)], 3));
+        assert_eq!(raft.take_committed().len(), 3);
+        // 晚到的消息只带了部分日志，commit_index不能因此后退
+        raft.step(1, append(vec![entry(2)], 4));
+        assert_eq!(raft.commit_index(), 3);
+        assert!(raft.take_committed().is_empty());
+    }
+
+    #[test]
+    fn append_entries_should_be_limited_by_bytes() {
+        let mut net = Network::new(3);
+        let leader = net.wait_leader(&[1, 2, 3]);
+        net.tick(HEARTBEAT_TICKS);
+        let lagging = (1..=3).find(|id| *id != leader).unwrap();
+        let others: Vec<_> = (1..=3).filter(|id| *id != lagging).collect();
+
+        // 落后的节点要追上的日志远远超过一帧
+        net.partition(&[&others, &[lagging]]);
+        let value = "v".repeat(900 * 1024);
+        for i in 0..10 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), value.clone().into());
+            assert_eq!(net.execute(leader, cmd).status, 200);
+        }
+        net.heal();
+
+        // 手动投递消息，检查发给落后节点的每个AppendEntries
+        let mut batches = Vec::new();
+        for _ in 0..ELECTION_TICKS * 20 {
+            net.node(leader).tick();
+            net.deliver_with(|envelope| {
+                if let RaftMessage::AppendEntries { entries, .. } = &envelope.msg {
+                    if envelope.to == lagging && !entries.is_empty() {
+                        let len = RaftEnvelope::from(envelope.clone()).encoded_len();
+                        assert!(len < MAX_FRAME_LENGTH);
+                        batches.push(entries.len());
+                    }
+                }
+            });
+            if net.local_get(lagging, "k9").status == 200 {
+                break;
+            }
+        }
+        assert!(batches.len() > 1);
+        assert_res_ok(net.local_get(lagging, "k9"), &[value.into()], &[]);
+    }
+
+    #[test]
+    fn lagging_node_should_catch_up_with_snapshot() {
+        let mut net = Network::new(3);
+        for node in net.nodes.values_mut() {
+            node.snapshot_threshold = 8;
+        }
+        let leader = net.wait_leader(&[1, 2, 3]);
+        net.tick(HEARTBEAT_TICKS);
+        let lagging = (1..=3).find(|id| *id != leader).unwrap();
+        let others: Vec<_> = (1..=3).filter(|id| *id != lagging).collect();
+        net.execute(leader, CommandRequest::new_hset("t1", "old", "v1".into()));
+        net.execute(leader, CommandRequest::new_hset("t1", "gone", "v1".into()));
+        net.tick(HEARTBEAT_TICKS);
+        assert_res_ok(net.local_get(lagging, "gone"), &["v1".into()], &[]);
+
+        // 落后的节点需要的日志被压缩掉了
+        net.partition(&[&others, &[lagging]]);
+        net.execute(leader, CommandRequest::new_hset("t1", "old", "v2".into()));
+        net.execute(leader, CommandRequest::new_hdel("t1", "gone"));
+        net.execute(
+            leader,
+            CommandRequest::new_lpush("l1", vec!["a".into(), "b".into()]),
+        );
+        let value = "v".repeat(900 * 1024);
+        for i in 0..10 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), value.clone().into());
+            assert_eq!(net.execute(leader, cmd).status, 200);
+        }
+        let compacted = net.nodes[&leader].raft().snapshot().index;
+        assert!(compacted > net.nodes[&lagging].raft().last_index());
+        net.heal();
+
+        // 快照分块发送，每块都不超过一帧
+        let mut chunks = 0;
+        for _ in 0..ELECTION_TICKS * 20 {
+            net.node(leader).tick();
+            net.deliver_with(|envelope| {
+                if let RaftMessage::InstallSnapshot { .. } = &envelope.msg {
+                    let len = RaftEnvelope::from(envelope.clone()).encoded_len();
+                    assert!(len < MAX_FRAME_LENGTH);
+                    chunks += 1;
+                }
+            });
+            if net.local_get(lagging, "k9").status == 200 {
+                break;
+            }
+        }
+        assert!(chunks > 1);
+        let raft = net.nodes[&lagging].raft();
+        assert!(raft.snapshot().index >= compacted);
+        assert_eq!(raft.members().len(), 3);
+
+        // 本地旧的数据被快照替换
+        assert_res_ok(net.local_get(lagging, "old"), &["v2".into()], &[]);
+        assert_eq!(net.local_get(lagging, "gone").status, 404);
+        assert_res_ok(net.local_get(lagging, "k9"), &[value.into()], &[]);
+        let res = net.nodes[&lagging]
+            .service()
+            .execute(CommandRequest::new_lrange("l1", 0, -1));
+        assert_res_ok(res, &["b".into(), "a".into()], &[]);
+
+        // 安装快照之后，新的日志正常复制
+        let res = net.execute(leader, CommandRequest::new_hset("t1", "k10", "v".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+        net.tick(HEARTBEAT_TICKS);
+        assert_res_ok(net.local_get(lagging, "k10"), &["v".into()], &[]);
+    }
+
+    #[test]
+    fn membership_changes_should_work() {
+        let mut net = Network::new(3);
+        let leader = net.wait_leader(&[1, 2, 3]);
+        net.execute(leader, CommandRequest::new_hset("t1", "k1", "v1".into()));
+
+        // 新加入的节点会收到之前所有的日志
+        let node = ClusterNode::joining(4, Service::new(MemTable::new()));
+        net.nodes.insert(4, node);
+        let rx = net.node(leader).add_node(4);
+        assert_res_ok(net.wait(rx), &[], &[]);
+        net.tick(HEARTBEAT_TICKS * 2);
+        assert_eq!(net.nodes[&4].raft().members().len(), 4);
+        assert_res_ok(net.local_get(4, "k1"), &["v1".into()], &[]);
+
+        // 删除leader之后，剩下的节点选出新的leader
+        let rx = net.node(leader).remove_node(leader);
+        assert_res_ok(net.wait(rx), &[], &[]);
+        let others: Vec<_> = (1..=4).filter(|id| *id != leader).collect();
+        let new = net.wait_leader(&others);
+        let res = net.execute(new, CommandRequest::new_hset("t1", "k2", "v2".into()));
+        assert_res_ok(res, &[Value::default()], &[]);
+        net.tick(HEARTBEAT_TICKS * 2);
+        assert!(!net.nodes[&leader].raft().is_leader());
+        assert_eq!(net.nodes[&4].raft().members().len(), 3);
+    }
+}
@@ -0,0 +1,975 @@
+use crate::{CommandRequest, MAX_FRAME_LENGTH};
+use bytes::Bytes;
+use prost::Message;
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use tracing::{debug, info};
+
+/// 节点的id
+pub type NodeId = u64;
+
+/// 多少个tick没有收到leader的消息就开始选举，实际的超时在[ELECTION_TICKS, 2 * ELECTION_TICKS)之间随机
+pub const ELECTION_TICKS: u32 = 10;
+/// leader每隔多少个tick发送一次心跳
+pub const HEARTBEAT_TICKS: u32 = 3;
+/// 一个AppendEntries消息最多带多少条日志
+const MAX_ENTRIES: usize = 64;
+/// 一个AppendEntries消息中日志的总大小上限，单条日志超过时单独发送，也是快照每个分块的大小
+const MAX_APPEND_BYTES: usize = MAX_FRAME_LENGTH / 2;
+
+/// 日志中的内容
+#[derive(Clone, Debug, PartialEq)]
+pub enum EntryData {
+    /// 集群的初始成员，所有初始节点的第一条日志都是相同的Members
+    Members(BTreeSet<NodeId>),
+    /// 新的leader当选后写入的空日志，用来提交之前term的日志
+    Noop,
+    /// 修改存储的命令
+    Command(CommandRequest),
+    /// 增加一个节点
+    AddNode(NodeId),
+    /// 删除一个节点
+    RemoveNode(NodeId),
+}
+
+impl EntryData {
+    /// 是否会改变集群的成员
+    pub fn is_conf_change(&self) -> bool {
+        matches!(
+            self,
+            Self::Members(_) | Self::AddNode(_) | Self::RemoveNode(_)
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LogEntry {
+    pub term: u64,
+    pub index: u64,
+    pub data: EntryData,
+}
+
+impl LogEntry {
+    /// 编码后大概的大小，term、index和字段头按32字节估算
+    fn size(&self) -> usize {
+        32 + match &self.data {
+            EntryData::Command(cmd) => cmd.encoded_len(),
+            EntryData::Members(members) => members.len() * 10,
+            _ => 0,
+        }
+    }
+}
+
+/// 压缩日志得到的快照，data是应用到第index条日志之后上层导出的数据
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Snapshot {
+    pub index: u64,
+    pub term: u64,
+    /// 第index条日志时的集群成员
+    pub members: BTreeSet<NodeId>,
+    pub data: Bytes,
+}
+
+/// 节点之间的消息
+#[derive(Clone, Debug, PartialEq)]
+pub enum RaftMessage {
+    RequestVote {
+        term: u64,
+        last_log_index: u64,
+        last_log_term: u64,
+    },
+    Vote {
+        term: u64,
+        granted: bool,
+    },
+    AppendEntries {
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+        // 用于确认leader身份的序号，follower会原样返回
+        read_seq: u64,
+    },
+    AppendResponse {
+        term: u64,
+        success: bool,
+        // 成功时是已经匹配的最后一条日志，失败时是leader可以重试的位置
+        match_index: u64,
+        read_seq: u64,
+    },
+    // follower需要的日志已经压缩时，leader分块发送快照
+    InstallSnapshot {
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+        members: BTreeSet<NodeId>,
+        offset: u64,
+        data: Bytes,
+        done: bool,
+    },
+    // 快照还没有收完时的回复，收完之后回复AppendResponse
+    SnapshotResponse {
+        term: u64,
+        last_index: u64,
+        received: u64,
+    },
+}
+
+impl RaftMessage {
+    pub fn term(&self) -> u64 {
+        match self {
+            Self::RequestVote { term, .. }
+            | Self::Vote { term, .. }
+            | Self::AppendEntries { term, .. }
+            | Self::AppendResponse { term, .. }
+            | Self::InstallSnapshot { term, .. }
+            | Self::SnapshotResponse { term, .. } => *term,
+        }
+    }
+}
+
+/// 发给某个节点的消息
+#[derive(Clone, Debug, PartialEq)]
+pub struct Envelope {
+    pub from: NodeId,
+    pub to: NodeId,
+    pub msg: RaftMessage,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+/// Raft的错误，由上层转换成KvError
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ProposeError {
+    /// 当前节点不是leader，带上已知的leader
+    NotLeader(Option<NodeId>),
+    /// 上一个成员变更还没有提交
+    ConfChangeInProgress,
+}
+
+// 等待确认leader身份的读请求
+struct ReadState {
+    id: u64,
+    index: u64,
+    seq: u64,
+    acks: HashSet<NodeId>,
+}
+
+/// Raft的状态机，本身不做任何IO：
+/// 上层调用tick()驱动时间，调用step()处理收到的消息，
+/// 然后通过take_messages()取出要发送的消息，通过take_committed()取出可以应用的日志。
+/// term、投票和日志都只保存在内存中，节点重启之后不能用原来的id恢复，
+/// 否则可能在同一个term中投出两票，或者丢掉已经确认过的日志
+pub struct Raft {
+    id: NodeId,
+    // 根据日志中的成员变更计算出的当前成员
+    members: BTreeSet<NodeId>,
+    term: u64,
+    voted_for: Option<NodeId>,
+    // 第i条日志保存在log[i - snapshot.index - 1]，之前的日志已经压缩到快照中
+    log: Vec<LogEntry>,
+    snapshot: Snapshot,
+    // leader给每个follower发送快照时，对方已经收到的字节数
+    snapshot_sent: HashMap<NodeId, usize>,
+    // follower正在接收的快照：(index, 已经收到的数据)
+    receiving: Option<(u64, Vec<u8>)>,
+    // 已经收完，等待上层安装的快照
+    installed: Option<Snapshot>,
+    commit_index: u64,
+    last_applied: u64,
+    role: Role,
+    leader_id: Option<NodeId>,
+    election_elapsed: u32,
+    election_timeout: u32,
+    heartbeat_elapsed: u32,
+    votes: HashSet<NodeId>,
+    next_index: HashMap<NodeId, u64>,
+    match_index: HashMap<NodeId, u64>,
+    // 当前term的第一条日志，提交之后leader的commit_index才是最新的
+    term_start_index: u64,
+    read_seq: u64,
+    pending_reads: Vec<ReadState>,
+    ready_reads: Vec<(u64, u64)>,
+    messages: Vec<Envelope>,
+}
+
+impl Raft {
+    /// 创建一个节点，members是集群的初始成员
+    pub fn new(id: NodeId, members: impl IntoIterator<Item = NodeId>) -> Self {
+        let members: BTreeSet<_> = members.into_iter().collect();
+        // 初始成员作为已经提交的第一条日志，这样新加入的节点也能从日志中得到完整的成员
+        let log = match members.is_empty() {
+            true => Vec::new(),
+            false => vec![LogEntry {
+                term: 0,
+                index: 1,
+                data: EntryData::Members(members.clone()),
+            }],
+        };
+        let mut raft = Self {
+            id,
+            members,
+            term: 0,
+            voted_for: None,
+            commit_index: log.len() as _,
+            log,
+            snapshot: Snapshot::default(),
+            snapshot_sent: HashMap::new(),
+            receiving: None,
+            installed: None,
+            last_applied: 0,
+            role: Role::Follower,
+            leader_id: None,
+            election_elapsed: 0,
+            election_timeout: 0,
+            heartbeat_elapsed: 0,
+            votes: HashSet::new(),
+            next_index: HashMap::new(),
+            match_index: HashMap::new(),
+            term_start_index: 0,
+            read_seq: 0,
+            pending_reads: Vec::new(),
+            ready_reads: Vec::new(),
+            messages: Vec::new(),
+        };
+        raft.reset_election_timeout();
+        raft
+    }
+
+    /// 创建一个要加入已有集群的节点，在收到包含自己的AddNode日志之前不会发起选举
+    pub fn new_joining(id: NodeId) -> Self {
+        Self::new(id, [])
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    pub fn term(&self) -> u64 {
+        self.term
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn is_leader(&self) -> bool {
+        self.role == Role::Leader
+    }
+
+    pub fn leader_id(&self) -> Option<NodeId> {
+        self.leader_id
+    }
+
+    pub fn members(&self) -> &BTreeSet<NodeId> {
+        &self.members
+    }
+
+    pub fn commit_index(&self) -> u64 {
+        self.commit_index
+    }
+
+    pub fn last_applied(&self) -> u64 {
+        self.last_applied
+    }
+
+    pub fn last_index(&self) -> u64 {
+        self.snapshot.index + self.log.len() as u64
+    }
+
+    /// 最近一次压缩的快照，没有压缩过时index为0
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+
+    /// 第index条日志的term，index为0时返回0，已经压缩的日志只能得到快照最后一条的term
+    pub fn term_at(&self, index: u64) -> Option<u64> {
+        match index.cmp(&self.snapshot.index) {
+            Ordering::Less => None,
+            Ordering::Equal => Some(self.snapshot.term),
+            Ordering::Greater => self.log.get(self.pos(index)).map(|e| e.term),
+        }
+    }
+
+    fn last_term(&self) -> u64 {
+        self.log.last().map_or(self.snapshot.term, |e| e.term)
+    }
+
+    // 第index条日志在log中的位置
+    fn pos(&self, index: u64) -> usize {
+        (index - self.snapshot.index - 1) as usize
+    }
+
+    fn quorum(&self) -> usize {
+        self.members.len() / 2 + 1
+    }
+
+    fn reset_election_timeout(&mut self) {
+        self.election_elapsed = 0;
+        self.election_timeout = rand::thread_rng().gen_range(ELECTION_TICKS..2 * ELECTION_TICKS);
+    }
+
+    /// 推进一个时间单位
+    pub fn tick(&mut self) {
+        match self.role {
+            Role::Leader => {
+                self.heartbeat_elapsed += 1;
+                if self.heartbeat_elapsed >= HEARTBEAT_TICKS {
+                    self.heartbeat_elapsed = 0;
+                    self.broadcast_append();
+                }
+            }
+            _ => {
+                self.election_elapsed += 1;
+                // 不在集群中的节点（还没有加入或者已经被删除）不能发起选举
+                if self.election_elapsed >= self.election_timeout && self.members.contains(&self.id)
+                {
+                    self.campaign();
+                }
+            }
+        }
+    }
+
+    /// leader写入一条新的日志，返回日志的(index, term)
+    pub fn propose(&mut self, data: EntryData) -> Result<(u64, u64), ProposeError> {
+        if self.role != Role::Leader {
+            return Err(ProposeError::NotLeader(self.leader_id));
+        }
+        // 一次只允许一个成员变更，没有提交之前不能开始下一个
+        if data.is_conf_change()
+            && self.log[self.pos(self.commit_index + 1)..]
+                .iter()
+                .any(|e| e.data.is_conf_change())
+        {
+            return Err(ProposeError::ConfChangeInProgress);
+        }
+
+        let index = self.append(data);
+        self.match_index.insert(self.id, index);
+        self.maybe_commit();
+        self.broadcast_append();
+        Ok((index, self.term))
+    }
+
+    /// 发起一个线性一致的读请求，leader身份得到多数节点确认后，
+    /// 可以在take_ready_reads()中取出，等到日志应用到返回的index之后就可以读取本地的存储
+    pub fn read_index(&mut self, id: u64) -> Result<(), ProposeError> {
+        if self.role != Role::Leader {
+            return Err(ProposeError::NotLeader(self.leader_id));
+        }
+        // 当前term的日志还没有提交时，commit_index可能落后于之前的leader
+        let index = self.commit_index.max(self.term_start_index);
+        self.read_seq += 1;
+        let mut acks = HashSet::new();
+        if self.members.contains(&self.id) {
+            acks.insert(self.id);
+        }
+        self.pending_reads.push(ReadState {
+            id,
+            index,
+            seq: self.read_seq,
+            acks,
+        });
+        self.check_reads();
+        self.broadcast_append();
+        Ok(())
+    }
+
+    /// 取出已经确认leader身份的读请求：(id, index)
+    pub fn take_ready_reads(&mut self) -> Vec<(u64, u64)> {
+        std::mem::take(&mut self.ready_reads)
+    }
+
+    /// 取出等待发送的消息
+    pub fn take_messages(&mut self) -> Vec<Envelope> {
+        std::mem::take(&mut self.messages)
+    }
+
+    /// 把第index条以及之前的日志压缩成快照，data是这些日志应用之后上层导出的数据，
+    /// 只能压缩已经应用的日志
+    pub fn compact(&mut self, index: u64, data: Bytes) {
+        if index <= self.snapshot.index || index > self.last_applied {
+            return;
+        }
+        let end = self.pos(index + 1);
+        let members = apply_members(self.snapshot.members.clone(), &self.log[..end]);
+        let term = self.log[end - 1].term;
+        self.log.drain(..end);
+        debug!("Node {} compacts log to {}", self.id, index);
+        self.snapshot = Snapshot {
+            index,
+            term,
+            members,
+            data,
+        };
+        // 正在发送的旧快照不再有效，从新快照的开头重新发送
+        self.snapshot_sent.clear();
+    }
+
+    /// 取出从leader收到的快照，上层要用快照中的数据替换本地的存储，之后才能应用新的日志
+    pub fn take_snapshot(&mut self) -> Option<Snapshot> {
+        self.installed.take()
+    }
+
+    /// 取出已经提交但还没有应用的日志
+    pub fn take_committed(&mut self) -> Vec<LogEntry> {
+        let start = self.pos(self.last_applied + 1);
+        let end = self.pos(self.commit_index + 1);
+        self.last_applied = self.commit_index;
+        let entries = self.log[start..end].to_vec();
+
+        // 被删除的leader在删除自己的日志提交之后退出
+        if self.role == Role::Leader && !self.members.contains(&self.id) {
+            let removed = entries
+                .iter()
+                .any(|e| e.data == EntryData::RemoveNode(self.id));
+            if removed {
+                info!("Node {} removed from cluster, stepping down", self.id);
+                self.become_follower(self.term, None);
+            }
+        }
+        entries
+    }
+
+    /// 处理其它节点发来的消息
+    pub fn step(&mut self, from: NodeId, msg: RaftMessage) {
+        if msg.term() > self.term {
+            let leader = match msg {
+                RaftMessage::AppendEntries { .. } | RaftMessage::InstallSnapshot { .. } => {
+                    Some(from)
+                }
+                _ => None,
+            };
+            self.become_follower(msg.term(), leader);
+        }
+
+        match msg {
+            RaftMessage::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => self.handle_request_vote(from, term, last_log_index, last_log_term),
+            RaftMessage::Vote { term, granted } => self.handle_vote(from, term, granted),
+            RaftMessage::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+                read_seq,
+            } => self.handle_append_entries(
+                from,
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+                read_seq,
+            ),
+            RaftMessage::AppendResponse {
+                term,
+                success,
+                match_index,
+                read_seq,
+            } => self.handle_append_response(from, term, success, match_index, read_seq),
+            RaftMessage::InstallSnapshot {
+                term,
+                last_index,
+                last_term,
+                members,
+                offset,
+                data,
+                done,
+            } => self.handle_install_snapshot(
+                from, term, last_index, last_term, members, offset, data, done,
+            ),
+            RaftMessage::SnapshotResponse {
+                term,
+                last_index,
+                received,
+            } => self.handle_snapshot_response(from, term, last_index, received),
+        }
+    }
+
+    fn send(&mut self, to: NodeId, msg: RaftMessage) {
+        self.messages.push(Envelope {
+            from: self.id,
+            to,
+            msg,
+        });
+    }
+
+    fn peers(&self) -> Vec<NodeId> {
+        self.members
+            .iter()
+            .copied()
+            .filter(|id| *id != self.id)
+            .collect()
+    }
+
+    fn append(&mut self, data: EntryData) -> u64 {
+        let index = self.last_index() + 1;
+        let conf_change = data.is_conf_change();
+        self.log.push(LogEntry {
+            term: self.term,
+            index,
+            data,
+        });
+        // 成员变更在写入日志时就生效，而不是提交之后
+        if conf_change {
+            self.rebuild_members();
+        }
+        index
+    }
+
+    fn rebuild_members(&mut self) {
+        let members = apply_members(self.snapshot.members.clone(), &self.log);
+
+        if self.role == Role::Leader {
+            let next = self.last_index() + 1;
+            for id in members.difference(&self.members) {
+                self.next_index.insert(*id, next);
+                self.match_index.insert(*id, 0);
+            }
+        }
+        self.members = members;
+    }
+
+    fn campaign(&mut self) {
+        self.term += 1;
+        self.role = Role::Candidate;
+        self.leader_id = None;
+        self.voted_for = Some(self.id);
+        self.votes = HashSet::from([self.id]);
+        self.reset_election_timeout();
+        debug!("Node {} starts election for term {}", self.id, self.term);
+
+        if self.votes.len() >= self.quorum() {
+            self.become_leader();
+            return;
+        }
+        let msg = RaftMessage::RequestVote {
+            term: self.term,
+            last_log_index: self.last_index(),
+            last_log_term: self.last_term(),
+        };
+        for peer in self.peers() {
+            self.send(peer, msg.clone());
+        }
+    }
+
+    fn become_follower(&mut self, term: u64, leader: Option<NodeId>) {
+        if term > self.term {
+            self.term = term;
+            self.voted_for = None;
+        }
+        // 换了leader之后，之前没有收完的快照不会再继续发送了
+        if leader != self.leader_id {
+            self.receiving = None;
+        }
+        self.role = Role::Follower;
+        self.leader_id = leader;
+        self.pending_reads.clear();
+        self.reset_election_timeout();
+    }
+
+    fn become_leader(&mut self) {
+        info!("Node {} becomes leader for term {}", self.id, self.term);
+        self.role = Role::Leader;
+        self.leader_id = Some(self.id);
+        self.heartbeat_elapsed = 0;
+        let next = self.last_index() + 1;
+        self.next_index = self.peers().into_iter().map(|id| (id, next)).collect();
+        self.match_index = self.peers().into_iter().map(|id| (id, 0)).collect();
+        self.snapshot_sent.clear();
+
+        self.term_start_index = self.append(EntryData::Noop);
+        self.match_index.insert(self.id, self.term_start_index);
+        self.maybe_commit();
+        self.broadcast_append();
+    }
+
+    fn handle_request_vote(&mut self, from: NodeId, term: u64, last_index: u64, last_term: u64) {
+        let up_to_date = (last_term, last_index) >= (self.last_term(), self.last_index());
+        let granted = term == self.term && self.voted_for.is_none_or(|id| id == from) && up_to_date;
+        if granted {
+            self.voted_for = Some(from);
+            self.reset_election_timeout();
+        }
+        self.send(
+            from,
+            RaftMessage::Vote {
+                term: self.term,
+                granted,
+            },
+        );
+    }
+
+    fn handle_vote(&mut self, from: NodeId, term: u64, granted: bool) {
+        if self.role != Role::Candidate || term != self.term || !granted {
+            return;
+        }
+        if self.members.contains(&from) {
+            self.votes.insert(from);
+        }
+        if self.votes.len() >= self.quorum() {
+            self.become_leader();
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_append_entries(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        prev_log_index: u64,
+        prev_log_term: u64,
+        entries: Vec<LogEntry>,
+        leader_commit: u64,
+        read_seq: u64,
+    ) {
+        if term < self.term {
+            let msg = RaftMessage::AppendResponse {
+                term: self.term,
+                success: false,
+                match_index: 0,
+                read_seq,
+            };
+            self.send(from, msg);
+            return;
+        }
+        // 同一个term中只会有一个leader，candidate收到之后退回follower
+        if self.role != Role::Follower || self.leader_id != Some(from) {
+            self.become_follower(term, Some(from));
+        }
+        self.reset_election_timeout();
+
+        // 快照中的日志都已经提交，一定和leader的一致，让leader从快照之后开始发送
+        if prev_log_index < self.snapshot.index {
+            let msg = RaftMessage::AppendResponse {
+                term: self.term,
+                success: true,
+                match_index: self.snapshot.index,
+                read_seq,
+            };
+            self.send(from, msg);
+            return;
+        }
+
+        if self.term_at(prev_log_index) != Some(prev_log_term) {
+            // 让leader从更早的位置重试
+            let hint = self.last_index().min(prev_log_index.saturating_sub(1));
+            let msg = RaftMessage::AppendResponse {
+                term: self.term,
+                success: false,
+                match_index: hint,
+                read_seq,
+            };
+            self.send(from, msg);
+            return;
+        }
+
+        let last_new = prev_log_index + entries.len() as u64;
+        let mut conf_changed = false;
+        for entry in entries {
+            match self.term_at(entry.index) {
+                Some(t) if t == entry.term => continue,
+                Some(_) => {
+                    // 冲突的日志以及之后的日志都要删除
+                    debug!("Node {} truncates log from {}", self.id, entry.index);
+                    self.log.truncate(self.pos(entry.index));
+                    conf_changed = true;
+                }
+                None => {}
+            }
+            conf_changed |= entry.data.is_conf_change();
+            self.log.push(entry);
+        }
+        if conf_changed {
+            self.rebuild_members();
+        }
+
+        // 乱序到达的旧消息可能只带了一部分日志，commit_index只能前进
+        self.commit_index = self.commit_index.max(leader_commit.min(last_new));
+        let msg = RaftMessage::AppendResponse {
+            term: self.term,
+            success: true,
+            match_index: last_new,
+            read_seq,
+        };
+        self.send(from, msg);
+    }
+
+    fn handle_append_response(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        success: bool,
+        match_index: u64,
+        read_seq: u64,
+    ) {
+        if self.role != Role::Leader || term != self.term {
+            return;
+        }
+
+        // 不管日志是否匹配，回复都说明对方承认当前的leader
+        for read in self.pending_reads.iter_mut() {
+            if read.seq <= read_seq && self.members.contains(&from) {
+                read.acks.insert(from);
+            }
+        }
+        self.check_reads();
+
+        if success {
+            self.snapshot_sent.remove(&from);
+            let matched = self.match_index.entry(from).or_insert(0);
+            *matched = (*matched).max(match_index);
+            let next = *matched + 1;
+            self.next_index.insert(from, next);
+            if self.maybe_commit() {
+                self.broadcast_append();
+            } else if next <= self.last_index() {
+                self.send_append(from);
+            }
+        } else {
+            let next = self.next_index.get(&from).copied().unwrap_or(1);
+            let next = (match_index + 1).min(next.saturating_sub(1)).max(1);
+            self.next_index.insert(from, next);
+            self.send_append(from);
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn handle_install_snapshot(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        last_index: u64,
+        last_term: u64,
+        members: BTreeSet<NodeId>,
+        offset: u64,
+        data: Bytes,
+        done: bool,
+    ) {
+        if term < self.term {
+            self.reply_snapshot(from, last_index, 0);
+            return;
+        }
+        if self.role != Role::Follower || self.leader_id != Some(from) {
+            self.become_follower(term, Some(from));
+        }
+        self.reset_election_timeout();
+
+        // 已经提交的日志和leader的一致，不需要再安装快照
+        if last_index <= self.commit_index {
+            self.receiving = None;
+            let msg = RaftMessage::AppendResponse {
+                term: self.term,
+                success: true,
+                match_index: self.commit_index,
+                read_seq: 0,
+            };
+            self.send(from, msg);
+            return;
+        }
+
+        if offset == 0
+            && self
+                .receiving
+                .as_ref()
+                .is_none_or(|(i, _)| *i != last_index)
+        {
+            self.receiving = Some((last_index, Vec::new()));
+        }
+        let buf = match &mut self.receiving {
+            Some((index, buf)) if *index == last_index => buf,
+            _ => return self.reply_snapshot(from, last_index, 0),
+        };
+        // 只接受紧接着已经收到的数据的分块，重复或者乱序的分块直接忽略
+        let accepted = offset as usize == buf.len();
+        if accepted {
+            buf.extend_from_slice(&data);
+        }
+        if !(accepted && done) {
+            let received = buf.len() as u64;
+            return self.reply_snapshot(from, last_index, received);
+        }
+        let data = std::mem::take(buf).into();
+        self.receiving = None;
+
+        self.install_snapshot(Snapshot {
+            index: last_index,
+            term: last_term,
+            members,
+            data,
+        });
+        let msg = RaftMessage::AppendResponse {
+            term: self.term,
+            success: true,
+            match_index: last_index,
+            read_seq: 0,
+        };
+        self.send(from, msg);
+    }
+
+    fn reply_snapshot(&mut self, to: NodeId, last_index: u64, received: u64) {
+        let msg = RaftMessage::SnapshotResponse {
+            term: self.term,
+            last_index,
+            received,
+        };
+        self.send(to, msg);
+    }
+
+    fn install_snapshot(&mut self, snapshot: Snapshot) {
+        info!("Node {} installs snapshot at {}", self.id, snapshot.index);
+        // 和快照一致的日志可以保留，否则全部丢弃
+        match self.term_at(snapshot.index) == Some(snapshot.term) {
+            true => {
+                let end = self.pos(snapshot.index + 1);
+                self.log.drain(..end);
+            }
+            false => self.log.clear(),
+        }
+        self.commit_index = snapshot.index;
+        self.last_applied = snapshot.index;
+        self.snapshot = snapshot.clone();
+        self.rebuild_members();
+        self.installed = Some(snapshot);
+    }
+
+    fn handle_snapshot_response(
+        &mut self,
+        from: NodeId,
+        term: u64,
+        last_index: u64,
+        received: u64,
+    ) {
+        if self.role != Role::Leader || term != self.term || last_index != self.snapshot.index {
+            return;
+        }
+        // 重复的回复不再发送，避免同一个分块同时有多个在路上
+        let sent = self.snapshot_sent.entry(from).or_insert(0);
+        if *sent != received as usize {
+            *sent = received as usize;
+            self.send_snapshot(from);
+        }
+    }
+
+    fn check_reads(&mut self) {
+        let quorum = self.quorum();
+        let (ready, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.pending_reads)
+            .into_iter()
+            .partition(|r| r.acks.len() >= quorum);
+        self.pending_reads = pending;
+        self.ready_reads
+            .extend(ready.into_iter().map(|r| (r.id, r.index)));
+    }
+
+    /// 根据多数节点的match_index推进commit_index，返回是否有变化
+    fn maybe_commit(&mut self) -> bool {
+        let quorum = self.quorum();
+        for index in (self.commit_index + 1..=self.last_index()).rev() {
+            // 只能直接提交当前term的日志
+            if self.term_at(index) != Some(self.term) {
+                break;
+            }
+            let count = self
+                .members
+                .iter()
+                .filter(|id| self.match_index.get(id).copied().unwrap_or(0) >= index)
+                .count();
+            if count >= quorum {
+                self.commit_index = index;
+                return true;
+            }
+        }
+        false
+    }
+
+    fn broadcast_append(&mut self) {
+        for peer in self.peers() {
+            self.send_append(peer);
+        }
+    }
+
+    fn send_append(&mut self, to: NodeId) {
+        let next = self
+            .next_index
+            .get(&to)
+            .copied()
+            .unwrap_or(self.last_index() + 1);
+        // 需要的日志已经压缩了，改为发送快照
+        if next <= self.snapshot.index {
+            return self.send_snapshot(to);
+        }
+        let prev_log_index = next - 1;
+        let mut size = 0;
+        let entries: Vec<_> = self.log[self.pos(next)..]
+            .iter()
+            .take(MAX_ENTRIES)
+            .enumerate()
+            .take_while(|(i, e)| {
+                size += e.size();
+                *i == 0 || size <= MAX_APPEND_BYTES
+            })
+            .map(|(_, e)| e.clone())
+            .collect();
+        let msg = RaftMessage::AppendEntries {
+            term: self.term,
+            prev_log_index,
+            prev_log_term: self.term_at(prev_log_index).unwrap_or(0),
+            entries,
+            leader_commit: self.commit_index,
+            read_seq: self.read_seq,
+        };
+        self.send(to, msg);
+    }
+    fn send_snapshot(&mut self, to: NodeId) {
+        let data = &self.snapshot.data;
+        let offset = self
+            .snapshot_sent
+            .get(&to)
+            .copied()
+            .unwrap_or(0)
+            .min(data.len());
+        let end = (offset + MAX_APPEND_BYTES).min(data.len());
+        let msg = RaftMessage::InstallSnapshot {
+            term: self.term,
+            last_index: self.snapshot.index,
+            last_term: self.snapshot.term,
+            members: self.snapshot.members.clone(),
+            offset: offset as u64,
+            data: data.slice(offset..end),
+            done: end == data.len(),
+        };
+        self.send(to, msg);
+    }
+}
+
+// 在members的基础上依次应用日志中的成员变更
+fn apply_members<'a>(
+    mut members: BTreeSet<NodeId>,
+    entries: impl IntoIterator<Item = &'a LogEntry>,
+) -> BTreeSet<NodeId> {
+    for entry in entries {
+        match &entry.data {
+            EntryData::Members(ids) => members = ids.clone(),
+            EntryData::AddNode(id) => {
+                members.insert(*id);
+            }
+            EntryData::RemoveNode(id) => {
+                members.remove(id);
+            }
+            _ => {}
+        }
+    }
+    members
+}
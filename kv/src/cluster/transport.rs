@@ -0,0 +1,610 @@
+use super::*;
+use crate::{raft_envelope::Msg, raft_log_entry::Data};
+use std::time::Duration;
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+    time,
+};
+use tracing::{debug, info};
+
+/// 节点之间的连接，双方都只发送RaftEnvelope
+pub type RaftStream<S> = ProstStream<S, RaftEnvelope, RaftEnvelope>;
+
+/// 发往每个节点的消息最多缓存多少条，满了之后丢弃，raft会重发
+const PEER_QUEUE_SIZE: usize = 256;
+/// 连接其它节点的超时时间
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+/// AppendEntries中除了日志以外的部分预留的空间
+const RAFT_FRAME_OVERHEAD: usize = 64 * 1024;
+
+/// 集群节点的配置
+#[derive(Clone, Debug)]
+pub struct ClusterConfig {
+    /// 当前节点的id，raft的状态不会持久化，节点重启之后要换一个新的id重新加入集群
+    pub id: NodeId,
+    /// 集群的初始成员，为空表示这是一个等待加入集群的节点
+    pub members: Vec<NodeId>,
+    /// 所有节点之间通信的地址，包括以后可能加入集群的节点
+    pub peers: HashMap<NodeId, String>,
+    /// 多久调用一次tick()
+    pub tick_interval: Duration,
+}
+
+impl ClusterConfig {
+    /// 当前节点监听的地址
+    pub fn addr(&self) -> Result<&str, KvError> {
+        self.peers
+            .get(&self.id)
+            .map(|addr| addr.as_str())
+            .ok_or_else(|| KvError::Internal(format!("no address for node {}", self.id)))
+    }
+}
+
+enum Request {
+    Execute(CommandRequest, oneshot::Sender<CommandResponse>),
+    ChangeMembers(EntryData, oneshot::Sender<CommandResponse>),
+}
+
+/// 在后台运行的集群节点，可以在多个连接之间共享。
+/// 所有的handle都drop之后后台任务退出
+#[derive(Clone)]
+pub struct ClusterHandle {
+    id: NodeId,
+    requests: mpsc::Sender<Request>,
+}
+
+impl ClusterHandle {
+    /// 监听配置中当前节点的地址，启动集群节点
+    pub async fn bind<Store>(
+        config: ClusterConfig,
+        service: Service<Store>,
+    ) -> Result<Self, KvError>
+    where
+        Store: Storage + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind(config.addr()?).await?;
+        Ok(Self::start(config, listener, service))
+    }
+
+    /// 在listener上接收其它节点的消息，启动集群节点
+    pub fn start<Store>(
+        config: ClusterConfig,
+        listener: TcpListener,
+        service: Service<Store>,
+    ) -> Self
+    where
+        Store: Storage + Send + Sync + 'static,
+    {
+        // 单条日志可能和客户端发来的最大的命令一样大
+        let max_frame_length = service.max_frame_length() + RAFT_FRAME_OVERHEAD;
+        let node = match config.members.is_empty() {
+            true => ClusterNode::joining(config.id, service),
+            false => ClusterNode::new(config.id, config.members.iter().copied(), service),
+        };
+        let (inbound_tx, inbound_rx) = mpsc::channel(PEER_QUEUE_SIZE);
+        let accept = tokio::spawn(accept_peers(listener, inbound_tx, max_frame_length));
+
+        let peers = config
+            .peers
+            .iter()
+            .filter(|(id, _)| **id != config.id)
+            .map(|(id, addr)| {
+                let (tx, rx) = mpsc::channel(PEER_QUEUE_SIZE);
+                tokio::spawn(send_to_peer(addr.clone(), rx));
+                (*id, tx)
+            })
+            .collect();
+
+        let (requests, requests_rx) = mpsc::channel(PEER_QUEUE_SIZE);
+        let driver = Driver {
+            node,
+            peers,
+            accept,
+        };
+        tokio::spawn(driver.run(requests_rx, inbound_rx, config.tick_interval));
+        info!("Cluster node {} started", config.id);
+        Self {
+            id: config.id,
+            requests,
+        }
+    }
+
+    pub fn id(&self) -> NodeId {
+        self.id
+    }
+
+    /// 执行命令，不是leader时返回421
+    pub async fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+        self.call(|tx| Request::Execute(cmd, tx)).await
+    }
+
+    /// 把节点加入集群，节点的地址需要在所有节点的配置中
+    pub async fn add_node(&self, id: NodeId) -> CommandResponse {
+        self.call(|tx| Request::ChangeMembers(EntryData::AddNode(id), tx))
+            .await
+    }
+
+    /// 把节点从集群中删除
+    pub async fn remove_node(&self, id: NodeId) -> CommandResponse {
+        self.call(|tx| Request::ChangeMembers(EntryData::RemoveNode(id), tx))
+            .await
+    }
+
+    async fn call(
+        &self,
+        f: impl FnOnce(oneshot::Sender<CommandResponse>) -> Request,
+    ) -> CommandResponse {
+        let (tx, rx) = oneshot::channel();
+        if self.requests.send(f(tx)).await.is_err() {
+            return KvError::Internal("cluster node stopped".into()).into();
+        }
+        rx.await
+            .unwrap_or_else(|_| KvError::Internal("cluster node stopped".into()).into())
+    }
+}
+
+// 后台任务持有ClusterNode，所有的调用都在这个任务中串行执行
+struct Driver<Store> {
+    node: ClusterNode<Store>,
+    peers: HashMap<NodeId, mpsc::Sender<Envelope>>,
+    accept: JoinHandle<()>,
+}
+
+impl<Store: Storage> Driver<Store> {
+    async fn run(
+        mut self,
+        mut requests: mpsc::Receiver<Request>,
+        mut inbound: mpsc::Receiver<Envelope>,
+        tick_interval: Duration,
+    ) {
+        let mut ticker = time::interval(tick_interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => self.node.tick(),
+                Some(envelope) = inbound.recv() => self.node.step(envelope),
+                req = requests.recv() => match req {
+                    Some(Request::Execute(cmd, tx)) => self.node.execute_with(cmd, tx),
+                    Some(Request::ChangeMembers(data, tx)) => self.node.change_members(data, tx),
+                    None => break,
+                },
+            }
+            self.send_messages();
+        }
+        self.accept.abort();
+        info!("Cluster node {} stopped", self.node.raft().id());
+    }
+
+    fn send_messages(&mut self) {
+        for envelope in self.node.take_messages() {
+            match self.peers.get(&envelope.to) {
+                // 队列满了说明对方太慢或者连不上，丢掉的消息raft会重发
+                Some(peer) => {
+                    peer.try_send(envelope).ok();
+                }
+                None => warn!("No address for node {}", envelope.to),
+            }
+        }
+    }
+}
+
+// 接受其它节点的连接，把收到的消息交给Driver
+async fn accept_peers(
+    listener: TcpListener,
+    inbound: mpsc::Sender<Envelope>,
+    max_frame_length: usize,
+) {
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(v) => v,
+            Err(e) => {
+                warn!("Failed to accept peer connection: {}", e);
+                continue;
+            }
+        };
+        debug!("Peer {:?} connected", addr);
+        let inbound = inbound.clone();
+        tokio::spawn(async move {
+            let mut stream = RaftStream::with_max_frame_length(stream, max_frame_length);
+            while let Some(msg) = stream.recv().await {
+                match msg.and_then(Envelope::try_from) {
+                    Ok(envelope) => {
+                        if inbound.send(envelope).await.is_err() {
+                            return;
+                        }
+                    }
+                    Err(KvError::Io(e)) => {
+                        debug!("Peer {:?} disconnected: {}", addr, e);
+                        return;
+                    }
+                    // 超长或者无法解析的消息只丢掉这一条，帧的边界没有乱，连接可以继续使用
+                    Err(e) => warn!("Invalid message from peer {:?}: {}", addr, e),
+                }
+            }
+        });
+    }
+}
+
+// 按顺序把消息发给一个节点，连接断开后在下一条消息时重连
+async fn send_to_peer(addr: String, mut rx: mpsc::Receiver<Envelope>) {
+    let mut stream = None;
+    while let Some(envelope) = rx.recv().await {
+        if stream.is_none() {
+            match time::timeout(CONNECT_TIMEOUT, TcpStream::connect(&addr)).await {
+                Ok(Ok(s)) => stream = Some(RaftStream::new(s)),
+                Ok(Err(e)) => debug!("Failed to connect to peer {}: {}", addr, e),
+                Err(_) => debug!("Connect to peer {} timed out", addr),
+            }
+        }
+        if let Some(s) = &mut stream {
+            if let Err(e) = s.send(&RaftEnvelope::from(envelope)).await {
+                debug!("Failed to send to peer {}: {}", addr, e);
+                stream = None;
+            }
+        }
+    }
+}
+
+impl From<Envelope> for RaftEnvelope {
+    fn from(e: Envelope) -> Self {
+        let msg = match e.msg {
+            RaftMessage::RequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            } => Msg::RequestVote(RaftRequestVote {
+                term,
+                last_log_index,
+                last_log_term,
+            }),
+            RaftMessage::Vote { term, granted } => Msg::Vote(RaftVote { term, granted }),
+            RaftMessage::AppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries,
+                leader_commit,
+                read_seq,
+            } => Msg::AppendEntries(RaftAppendEntries {
+                term,
+                prev_log_index,
+                prev_log_term,
+                entries: entries.into_iter().map(Into::into).collect(),
+                leader_commit,
+                read_seq,
+            }),
+            RaftMessage::AppendResponse {
+                term,
+                success,
+                match_index,
+                read_seq,
+            } => Msg::AppendResponse(RaftAppendResponse {
+                term,
+                success,
+                match_index,
+                read_seq,
+            }),
+            RaftMessage::InstallSnapshot {
+                term,
+                last_index,
+                last_term,
+                members,
+                offset,
+                data,
+                done,
+            } => Msg::InstallSnapshot(RaftInstallSnapshot {
+                term,
+                last_index,
+                last_term,
+                members: Some(RaftMembers {
+                    ids: members.into_iter().collect(),
+                }),
+                offset,
+                data,
+                done,
+            }),
+            RaftMessage::SnapshotResponse {
+                term,
+                last_index,
+                received,
+            } => Msg::SnapshotResponse(RaftSnapshotResponse {
+                term,
+                last_index,
+                received,
+            }),
+        };
+        Self {
+            from: e.from,
+            to: e.to,
+            msg: Some(msg),
+        }
+    }
+}
+
+impl TryFrom<RaftEnvelope> for Envelope {
+    type Error = KvError;
+
+    fn try_from(e: RaftEnvelope) -> Result<Self, Self::Error> {
+        let msg = match e.msg {
+            Some(Msg::RequestVote(v)) => RaftMessage::RequestVote {
+                term: v.term,
+                last_log_index: v.last_log_index,
+                last_log_term: v.last_log_term,
+            },
+            Some(Msg::Vote(v)) => RaftMessage::Vote {
+                term: v.term,
+                granted: v.granted,
+            },
+            Some(Msg::AppendEntries(v)) => RaftMessage::AppendEntries {
+                term: v.term,
+                prev_log_index: v.prev_log_index,
+                prev_log_term: v.prev_log_term,
+                entries: v
+                    .entries
+                    .into_iter()
+                    .map(LogEntry::try_from)
+                    .collect::<Result<_, _>>()?,
+                leader_commit: v.leader_commit,
+                read_seq: v.read_seq,
+            },
+            Some(Msg::AppendResponse(v)) => RaftMessage::AppendResponse {
+                term: v.term,
+                success: v.success,
+                match_index: v.match_index,
+                read_seq: v.read_seq,
+            },
+            Some(Msg::InstallSnapshot(v)) => RaftMessage::InstallSnapshot {
+                term: v.term,
+                last_index: v.last_index,
+                last_term: v.last_term,
+                members: v.members.unwrap_or_default().ids.into_iter().collect(),
+                offset: v.offset,
+                data: v.data,
+                done: v.done,
+            },
+            Some(Msg::SnapshotResponse(v)) => RaftMessage::SnapshotResponse {
+                term: v.term,
+                last_index: v.last_index,
+                received: v.received,
+            },
+            None => return Err(KvError::Internal("empty raft message".into())),
+        };
+        Ok(Self {
+            from: e.from,
+            to: e.to,
+            msg,
+        })
+    }
+}
+
+impl From<LogEntry> for RaftLogEntry {
+    fn from(e: LogEntry) -> Self {
+        let data = match e.data {
+            EntryData::Members(ids) => Data::Members(RaftMembers {
+                ids: ids.into_iter().collect(),
+            }),
+            EntryData::Noop => Data::Noop(RaftNoop {}),
+            EntryData::Command(cmd) => Data::Command(cmd),
+            EntryData::AddNode(id) => Data::AddNode(id),
+            EntryData::RemoveNode(id) => Data::RemoveNode(id),
+        };
+        Self {
+            term: e.term,
+            index: e.index,
+            data: Some(data),
+        }
+    }
+}
+
+impl TryFrom<RaftLogEntry> for LogEntry {
+    type Error = KvError;
+
+    fn try_from(e: RaftLogEntry) -> Result<Self, Self::Error> {
+        let data = match e.data {
+            Some(Data::Members(v)) => EntryData::Members(v.ids.into_iter().collect()),
+            Some(Data::Noop(_)) => EntryData::Noop,
+            Some(Data::Command(cmd)) => EntryData::Command(cmd),
+            Some(Data::AddNode(id)) => EntryData::AddNode(id),
+            Some(Data::RemoveNode(id)) => EntryData::RemoveNode(id),
+            None => return Err(KvError::Internal("empty raft log entry".into())),
+        };
+        Ok(Self {
+            term: e.term,
+            index: e.index,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn envelope_should_survive_round_trip() {
+        let entries = vec![
+            LogEntry {
+                term: 0,
+                index: 1,
+                data: EntryData::Members([1, 2, 3].into_iter().collect()),
+            },
+            LogEntry {
+                term: 1,
+                index: 2,
+                data: EntryData::Command(CommandRequest::new_hset("t1", "k1", "v1".into())),
+            },
+            LogEntry {
+                term: 1,
+                index: 3,
+                data: EntryData::AddNode(4),
+            },
+        ];
+        let envelope = Envelope {
+            from: 1,
+            to: 2,
+            msg: RaftMessage::AppendEntries {
+                term: 1,
+                prev_log_index: 0,
+                prev_log_term: 0,
+                entries,
+                leader_commit: 2,
+                read_seq: 7,
+            },
+        };
+        let pb = RaftEnvelope::from(envelope.clone());
+        assert_eq!(Envelope::try_from(pb).unwrap(), envelope);
+        assert!(Envelope::try_from(RaftEnvelope::default()).is_err());
+
+        let envelope = Envelope {
+            from: 1,
+            to: 2,
+            msg: RaftMessage::InstallSnapshot {
+                term: 2,
+                last_index: 10,
+                last_term: 1,
+                members: [1, 2, 3].into_iter().collect(),
+                offset: 4,
+                data: Bytes::from_static(b"data"),
+                done: true,
+            },
+        };
+        let pb = RaftEnvelope::from(envelope.clone());
+        assert_eq!(Envelope::try_from(pb).unwrap(), envelope);
+    }
+
+    #[tokio::test]
+    async fn peer_connection_should_survive_invalid_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, mut rx) = mpsc::channel(PEER_QUEUE_SIZE);
+        tokio::spawn(accept_peers(listener, tx, 1024));
+
+        let envelope = |entries| Envelope {
+            from: 1,
+            to: 2,
+            msg: RaftMessage::AppendEntries {
+                term: 1,
+                prev_log_index: 0,
+                prev_log_term: 0,
+                entries,
+                leader_commit: 0,
+                read_seq: 0,
+            },
+        };
+        let large = LogEntry {
+            term: 1,
+            index: 1,
+            data: EntryData::Command(CommandRequest::new_hset(
+                "t1",
+                "k1",
+                "v".repeat(2048).into(),
+            )),
+        };
+        let mut stream = RaftStream::new(TcpStream::connect(addr).await.unwrap());
+        // 超长的消息和无法解析的消息都只丢掉这一条
+        stream.send(&envelope(vec![large]).into()).await.unwrap();
+        stream.send(&RaftEnvelope::default()).await.unwrap();
+        stream.send(&envelope(vec![]).into()).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), envelope(vec![]));
+    }
+
+    /// 在本地端口上启动n个节点
+    async fn start_cluster(n: u64) -> Vec<ClusterHandle> {
+        let mut listeners = Vec::new();
+        let mut peers = HashMap::new();
+        for id in 1..=n {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            peers.insert(id, listener.local_addr().unwrap().to_string());
+            listeners.push((id, listener));
+        }
+        listeners
+            .into_iter()
+            .map(|(id, listener)| {
+                let config = ClusterConfig {
+                    id,
+                    members: (1..=n).collect(),
+                    peers: peers.clone(),
+                    tick_interval: Duration::from_millis(10),
+                };
+                ClusterHandle::start(config, listener, Service::new(MemTable::new()))
+            })
+            .collect()
+    }
+
+    /// 不断重试直到有节点成为leader并执行成功
+    async fn execute_on_leader(
+        nodes: &[ClusterHandle],
+        cmd: CommandRequest,
+    ) -> (NodeId, CommandResponse) {
+        for _ in 0..200 {
+            for node in nodes {
+                let res = node.execute(cmd.clone()).await;
+                if res.status != 421 {
+                    return (node.id(), res);
+                }
+            }
+            time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("no leader elected");
+    }
+
+    #[tokio::test]
+    async fn nodes_should_replicate_over_tcp() {
+        let nodes = start_cluster(3).await;
+        let (leader, res) =
+            execute_on_leader(&nodes, CommandRequest::new_hset("t1", "k1", "v1".into())).await;
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let leader = &nodes[leader as usize - 1];
+        let res = leader.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        let follower = nodes.iter().find(|n| n.id() != leader.id()).unwrap();
+        let res = follower.execute(CommandRequest::new_hget("t1", "k1")).await;
+        assert_res_error(res, 421, &format!("Some({})", leader.id()));
+    }
+
+    #[tokio::test]
+    async fn server_should_execute_commands_through_cluster() {
+        let service = Service::new(MemTable::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = ClusterConfig {
+            id: 1,
+            members: vec![1],
+            peers: [(1, listener.local_addr().unwrap().to_string())].into(),
+            tick_interval: Duration::from_millis(10),
+        };
+        let cluster = ClusterHandle::start(config, listener, service.clone());
+        execute_on_leader(std::slice::from_ref(&cluster), CommandRequest::new_tables()).await;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server: KvServer = KvServer::new(Default::default(), service).with_cluster(cluster);
+        tokio::spawn(async move { server.serve(listener, std::future::pending()).await });
+
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[Value::default()], &[]);
+        let res = client
+            .execute(&CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_res_ok(res, &["v1".into()], &[]);
+
+        let res = client
+            .execute(&CommandRequest::new_select(1))
+            .await
+            .unwrap();
+        assert_res_error(res, 400, "cluster mode");
+        let res = client
+            .execute(&CommandRequest::new_watch("t1", ""))
+            .await
+            .unwrap();
+        assert_res_error(res, 400, "cluster mode");
+    }
+}
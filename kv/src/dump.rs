@@ -0,0 +1,676 @@
+use crate::{command_request::RequestData, *};
+use bytes::Bytes;
+use prost::Message;
+use serde_json::{json, Map, Value as JsonValue};
+use std::{
+    io::{Read, Write},
+    mem,
+};
+
+/// dump文件开头的magic number
+const MAGIC: &[u8; 6] = b"KVDUMP";
+/// 当前dump文件的版本
+/// 版本1只有table，版本2增加了有序集合和列表，版本1的文件可以直接按版本2读取
+pub const DUMP_VERSION: u32 = 2;
+/// restore时每个Hmset命令最多包含的kv pair数量
+const RESTORE_BATCH: usize = 128;
+/// restore的每个命令和dump时分页读取的每一页，编码后的大小上限，
+/// 留出一半的余量，不会超过默认的最大帧长度
+pub const DUMP_BATCH_BYTES: usize = MAX_FRAME_LENGTH / 2;
+/// 超过这个大小的binary值用PutChunk和GetChunk分块传输，要比默认的max_value_size小
+pub const DUMP_CHUNK_SIZE: usize = 512 * 1024;
+/// dump文件中单个消息的最大长度，要能放下默认配置下最大的blob
+const MAX_MESSAGE_LENGTH: usize = 1024 * 1024 * 1024;
+
+/// 存储中所有非空的table、有序集合和列表，都按名字排序
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DumpData {
+    pub tables: Vec<(String, Vec<Kvpair>)>,
+    pub zsets: Vec<(String, Vec<ScoredMember>)>,
+    /// 列表中的值从头到尾排列
+    pub lists: Vec<(String, Vec<Value>)>,
+}
+
+impl DumpData {
+    /// kv pair、有序集合成员和列表中值的总数
+    pub fn count(&self) -> usize {
+        self.tables.iter().map(|(_, v)| v.len()).sum::<usize>()
+            + self.zsets.iter().map(|(_, v)| v.len()).sum::<usize>()
+            + self.lists.iter().map(|(_, v)| v.len()).sum::<usize>()
+    }
+}
+
+/// 写dump文件
+/// 格式为：MAGIC，DumpHeader，然后每个table是一个DumpTable后面跟着count个Kvpair，
+/// 有序集合和列表也是一个DumpTable，后面跟着count个ScoredMember或者Value，
+/// 所有的protobuf消息都带有varint的长度前缀
+pub struct DumpWriter<W> {
+    writer: W,
+}
+
+impl<W: Write> DumpWriter<W> {
+    pub fn new(mut writer: W) -> Result<Self, KvError> {
+        writer.write_all(MAGIC)?;
+        let header = DumpHeader {
+            version: DUMP_VERSION,
+        };
+        writer.write_all(&header.encode_length_delimited_to_vec())?;
+        Ok(Self { writer })
+    }
+
+    /// 写入一个table的所有kv pair
+    pub fn write_table(&mut self, name: &str, pairs: &[Kvpair]) -> Result<(), KvError> {
+        self.write_section(name, KeyKind::Hash, pairs.iter())
+    }
+
+    /// 写入一个有序集合的所有成员
+    pub fn write_zset(&mut self, key: &str, members: &[ScoredMember]) -> Result<(), KvError> {
+        self.write_section(key, KeyKind::Zset, members.iter())
+    }
+
+    /// 写入一个列表的所有值，values从头到尾排列
+    pub fn write_list(&mut self, key: &str, values: &[Value]) -> Result<(), KvError> {
+        // 倒过来写，restore时用Lpush依次插入到头部就能恢复原来的顺序
+        self.write_section(key, KeyKind::List, values.iter().rev())
+    }
+
+    fn write_section<'a, M: Message + 'a>(
+        &mut self,
+        name: &str,
+        kind: KeyKind,
+        items: impl ExactSizeIterator<Item = &'a M>,
+    ) -> Result<(), KvError> {
+        let table = DumpTable {
+            name: name.into(),
+            count: items.len() as _,
+            kind: kind as _,
+        };
+        self.writer
+            .write_all(&table.encode_length_delimited_to_vec())?;
+        for item in items {
+            self.writer
+                .write_all(&item.encode_length_delimited_to_vec())?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<W, KvError> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// DumpReader每次返回的恢复操作
+#[derive(Clone, Debug, PartialEq)]
+pub enum RestoreBatch {
+    /// 一批kv pair、有序集合成员或者列表中的值，用一个命令写入
+    Command(CommandRequest),
+    /// 超过DUMP_CHUNK_SIZE的binary值，要用PutChunk分块上传
+    Blob {
+        table: String,
+        key: String,
+        data: Bytes,
+    },
+}
+
+impl RestoreBatch {
+    /// 包含的kv pair、成员或者值的数量
+    pub fn count(&self) -> usize {
+        match self {
+            Self::Command(cmd) => match &cmd.request_data {
+                Some(RequestData::Hmset(v)) => v.pairs.len(),
+                Some(RequestData::Zadd(v)) => v.members.len(),
+                Some(RequestData::Lpush(v)) => v.values.len(),
+                _ => 0,
+            },
+            Self::Blob { .. } => 1,
+        }
+    }
+}
+
+/// 读dump文件，每次返回一个Hmset、Zadd或者Lpush命令，最多包含RESTORE_BATCH个item，
+/// 编码后的大小不超过DUMP_BATCH_BYTES，只有一个item超过时单独一批；
+/// 大的binary值单独返回，用PutChunk分块上传
+pub struct DumpReader<R> {
+    reader: R,
+    // 当前的table，以及还没有读取的kv pair数量
+    table: Option<(String, KeyKind, u64)>,
+    // 已经读出来但放不进上一批的item
+    pending: Option<Vec<u8>>,
+}
+
+impl<R: Read> DumpReader<R> {
+    pub fn new(mut reader: R) -> Result<Self, KvError> {
+        let mut magic = [0u8; 6];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(KvError::InvalidDump("bad magic number".into()));
+        }
+
+        let mut this = Self {
+            reader,
+            table: None,
+            pending: None,
+        };
+        let header: DumpHeader = this
+            .read_message()?
+            .ok_or_else(|| KvError::InvalidDump("missing header".into()))?;
+        if header.version == 0 || header.version > DUMP_VERSION {
+            return Err(KvError::InvalidDump(format!(
+                "unsupported version {}",
+                header.version
+            )));
+        }
+        Ok(this)
+    }
+
+    /// 读取一个带长度前缀的protobuf消息，文件结束时返回None
+    fn read_message<M: Message + Default>(&mut self) -> Result<Option<M>, KvError> {
+        match self.read_frame()? {
+            Some(buf) => Ok(Some(M::decode(&buf[..])?)),
+            None => Ok(None),
+        }
+    }
+
+    /// 读取一个消息的原始数据，不包括长度前缀
+    fn read_frame(&mut self) -> Result<Option<Vec<u8>>, KvError> {
+        let mut len = 0u64;
+        for i in 0..10 {
+            let mut byte = [0u8];
+            if self.reader.read(&mut byte)? == 0 {
+                return match i {
+                    0 => Ok(None),
+                    _ => Err(KvError::InvalidDump("unexpected end of file".into())),
+                };
+            }
+            len |= ((byte[0] & 0x7f) as u64) << (7 * i);
+            if byte[0] & 0x80 == 0 {
+                if len > MAX_MESSAGE_LENGTH as u64 {
+                    return Err(KvError::InvalidDump(format!(
+                        "message length {} exceeds {}",
+                        len, MAX_MESSAGE_LENGTH
+                    )));
+                }
+                // 不按长度预先分配，长度超过剩下的数据时只会读到文件结尾
+                let mut buf = Vec::new();
+                (&mut self.reader).take(len).read_to_end(&mut buf)?;
+                if buf.len() as u64 != len {
+                    return Err(KvError::InvalidDump("unexpected end of file".into()));
+                }
+                return Ok(Some(buf));
+            }
+        }
+        Err(KvError::InvalidDump("invalid length".into()))
+    }
+
+    fn next_batch(&mut self) -> Result<Option<RestoreBatch>, KvError> {
+        // 跳过空的table，找到下一个还有数据的table
+        let (name, kind, mut remaining) = loop {
+            match self.table.take() {
+                Some((name, kind, remaining)) if remaining > 0 || self.pending.is_some() => {
+                    break (name, kind, remaining)
+                }
+                _ => match self.read_message::<DumpTable>()? {
+                    Some(table) => {
+                        let kind = KeyKind::try_from(table.kind)
+                            .map_err(|e| KvError::InvalidDump(e.to_string()))?;
+                        self.table = Some((table.name, kind, table.count));
+                    }
+                    None => return Ok(None),
+                },
+            }
+        };
+
+        let items = self.read_items(&name, kind, &mut remaining)?;
+        let batch = match kind {
+            KeyKind::Hash => {
+                let mut pairs: Vec<Kvpair> = decode_all(&items)?;
+                match pairs.as_mut_slice() {
+                    [Kvpair {
+                        key,
+                        value:
+                            Some(Value {
+                                value: Some(value::Value::Binary(data)),
+                            }),
+                    }] if data.len() > DUMP_CHUNK_SIZE => RestoreBatch::Blob {
+                        table: name.clone(),
+                        key: mem::take(key),
+                        data: mem::take(data),
+                    },
+                    _ => RestoreBatch::Command(CommandRequest::new_hmset(name.as_str(), pairs)),
+                }
+            }
+            KeyKind::Zset => {
+                RestoreBatch::Command(CommandRequest::new_zadd(name.as_str(), decode_all(&items)?))
+            }
+            KeyKind::List => RestoreBatch::Command(CommandRequest::new_lpush(
+                name.as_str(),
+                decode_all(&items)?,
+            )),
+        };
+        self.table = Some((name, kind, remaining));
+        Ok(Some(batch))
+    }
+
+    /// 读出一批item的原始数据，可能要分块上传的值单独一批
+    fn read_items(
+        &mut self,
+        name: &str,
+        kind: KeyKind,
+        remaining: &mut u64,
+    ) -> Result<Vec<Vec<u8>>, KvError> {
+        let mut items = Vec::new();
+        let mut size = 0;
+        while items.len() < RESTORE_BATCH {
+            let item = match self.pending.take() {
+                Some(item) => item,
+                None if *remaining > 0 => {
+                    *remaining -= 1;
+                    self.read_frame()?
+                        .ok_or_else(|| KvError::InvalidDump(format!("{} is truncated", name)))?
+                }
+                None => break,
+            };
+            let large = kind == KeyKind::Hash && item.len() > DUMP_CHUNK_SIZE;
+            if !items.is_empty() && (large || size + item.len() > DUMP_BATCH_BYTES) {
+                self.pending = Some(item);
+                break;
+            }
+            size += item.len();
+            items.push(item);
+            if large {
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
+
+fn decode_all<M: Message + Default>(items: &[Vec<u8>]) -> Result<Vec<M>, KvError> {
+    items.iter().map(|item| Ok(M::decode(&item[..])?)).collect()
+}
+
+impl<R: Read> Iterator for DumpReader<R> {
+    type Item = Result<RestoreBatch, KvError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_batch().transpose()
+    }
+}
+
+/// 从存储中读出所有非空的table、有序集合和列表，按名字排序
+pub fn all_data(store: &impl Storage) -> Result<DumpData, KvError> {
+    let mut data = DumpData::default();
+    for name in store.get_tables()? {
+        let mut pairs = store.get_all(&name)?;
+        if pairs.is_empty() {
+            continue;
+        }
+        pairs.sort_by(|a, b| a.key.cmp(&b.key));
+        data.tables.push((name, pairs));
+    }
+    for key in store.zset_keys()? {
+        let members: Vec<_> = store
+            .zrange(&key, 0, -1)?
+            .into_iter()
+            .map(|(member, score)| ScoredMember::new(member, score))
+            .collect();
+        if !members.is_empty() {
+            data.zsets.push((key, members));
+        }
+    }
+    for key in store.list_keys()? {
+        let values = store.lrange(&key, 0, -1)?;
+        if !values.is_empty() {
+            data.lists.push((key, values));
+        }
+    }
+    data.tables.sort_by(|a, b| a.0.cmp(&b.0));
+    data.zsets.sort_by(|a, b| a.0.cmp(&b.0));
+    data.lists.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(data)
+}
+
+/// 把存储中所有的数据导出成dump文件，返回导出的kv pair、成员和值的数量
+pub fn dump(store: &impl Storage, writer: impl Write) -> Result<usize, KvError> {
+    write_dump(&all_data(store)?, writer)
+}
+
+/// 把数据写成dump文件，返回写入的kv pair、成员和值的数量
+pub fn write_dump(data: &DumpData, writer: impl Write) -> Result<usize, KvError> {
+    let mut writer = DumpWriter::new(writer)?;
+    for (name, pairs) in &data.tables {
+        writer.write_table(name, pairs)?;
+    }
+    for (key, members) in &data.zsets {
+        writer.write_zset(key, members)?;
+    }
+    for (key, values) in &data.lists {
+        writer.write_list(key, values)?;
+    }
+    writer.finish()?;
+    Ok(data.count())
+}
+
+/// 把dump文件恢复到service中，返回恢复的kv pair、成员和值的数量
+pub fn restore<Store: Storage>(
+    service: &Service<Store>,
+    reader: impl Read,
+) -> Result<usize, KvError> {
+    let mut count = 0;
+    for batch in DumpReader::new(reader)? {
+        let batch = batch?;
+        count += batch.count();
+        match batch {
+            RestoreBatch::Command(cmd) => {
+                execute_ok(service, cmd)?;
+            }
+            RestoreBatch::Blob { table, key, data } => {
+                // 之后的分块都要带上第一个分块返回的upload_id
+                let mut upload_id = 0;
+                for (i, chunk) in data.chunks(DUMP_CHUNK_SIZE).enumerate() {
+                    let offset = (i * DUMP_CHUNK_SIZE) as u64;
+                    let last = offset as usize + chunk.len() == data.len();
+                    let cmd = CommandRequest::new_put_chunk(
+                        table.as_str(),
+                        key.as_str(),
+                        upload_id,
+                        offset,
+                        data.slice_ref(chunk),
+                        last,
+                    );
+                    upload_id = match execute_ok(service, cmd)?.values.get(1) {
+                        Some(Value {
+                            value: Some(value::Value::Integer(id)),
+                        }) => *id as u64,
+                        _ => return Err(KvError::Internal("invalid PutChunk response".into())),
+                    };
+                }
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// 删除存储中所有的table、有序集合和列表
+pub(crate) fn clear_store(store: &impl Storage) -> Result<(), KvError> {
+    for name in store.get_tables()? {
+        for key in store.get_keys(&name)? {
+            store.del(&name, &key)?;
+        }
+    }
+    for key in store.zset_keys()? {
+        let members: Vec<_> = store
+            .zrange(&key, 0, -1)?
+            .into_iter()
+            .map(|(m, _)| m)
+            .collect();
+        store.zrem(&key, &members)?;
+    }
+    for key in store.list_keys()? {
+        while store.rpop(&key)?.is_some() {}
+    }
+    Ok(())
+}
+
+fn execute_ok<Store: Storage>(
+    service: &Service<Store>,
+    cmd: CommandRequest,
+) -> Result<CommandResponse, KvError> {
+    let res = service.execute(cmd);
+    match res.status {
+        200 => Ok(res),
+        _ => Err(KvError::Internal(res.message)),
+    }
+}
+
+/// 把数据导出成方便阅读的JSON，binary类型的值用十六进制表示
+pub fn to_json(data: &DumpData) -> JsonValue {
+    let tables: Map<_, _> = data
+        .tables
+        .iter()
+        .map(|(name, pairs)| {
+            let pairs: Map<_, _> = pairs
+                .iter()
+                .map(|p| (p.key.clone(), value_to_json(p.value.as_ref())))
+                .collect();
+            (name.clone(), JsonValue::Object(pairs))
+        })
+        .collect();
+
+    // 有序集合按排名输出成[member, score]的数组
+    let zsets: Map<_, _> = data
+        .zsets
+        .iter()
+        .map(|(key, members)| {
+            let members = members.iter().map(|m| json!([m.member, m.score])).collect();
+            (key.clone(), JsonValue::Array(members))
+        })
+        .collect();
+    let lists: Map<_, _> = data
+        .lists
+        .iter()
+        .map(|(key, values)| {
+            let values = values.iter().map(|v| value_to_json(Some(v))).collect();
+            (key.clone(), JsonValue::Array(values))
+        })
+        .collect();
+
+    json!({
+        "version": DUMP_VERSION,
+        "tables": tables,
+        "zsets": zsets,
+        "lists": lists,
+    })
+}
+
+fn value_to_json(v: Option<&Value>) -> JsonValue {
+    match typed_json(v) {
+        ("binary", hex) => json!({ "binary": hex }),
+        (_, value) => value,
+    }
+}
+
+/// 值的类型和它的JSON表示，binary用十六进制的字符串表示，web管理界面也使用
+pub(crate) fn typed_json(v: Option<&Value>) -> (&'static str, JsonValue) {
+    match v.and_then(|v| v.value.as_ref()) {
+        Some(value::Value::String(s)) => ("string", json!(s)),
+        Some(value::Value::Binary(b)) => {
+            let hex: String = b.iter().map(|b| format!("{:02x}", b)).collect();
+            ("binary", json!(hex))
+        }
+        Some(value::Value::Integer(i)) => ("integer", json!(i)),
+        Some(value::Value::Float(f)) => ("float", json!(f)),
+        Some(value::Value::Bool(b)) => ("bool", json!(b)),
+        None => ("null", JsonValue::Null),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store_with_data() -> MemTable {
+        let store = MemTable::new();
+        store.set("t1", "k1", "v1").unwrap();
+        store.set("t1", "k2", 2).unwrap();
+        store.set("t2", "k1", true).unwrap();
+        store
+            .set("t2", "k2", Value::from(bytes::Bytes::from_static(b"hi")))
+            .unwrap();
+        // 删光了key的table不会出现在dump中
+        store.set("empty", "k1", 1).unwrap();
+        store.del("empty", "k1").unwrap();
+        store
+            .zadd("z1", vec![("a".into(), 2.0), ("b".into(), 1.0)])
+            .unwrap();
+        store.rpush("l1", vec!["x".into(), 1.into()]).unwrap();
+        store
+    }
+
+    #[test]
+    fn dump_and_restore_should_work() {
+        let store = store_with_data();
+        let mut buf = Vec::new();
+        assert_eq!(dump(&store, &mut buf).unwrap(), 8);
+
+        let service: Service = Service::new(MemTable::new());
+        assert_eq!(restore(&service, &buf[..]).unwrap(), 8);
+        let res = service.execute(CommandRequest::new_zrange("z1", 0, -1));
+        assert_res_ok(
+            res,
+            &[],
+            &[Kvpair::new("a", 2.0.into()), Kvpair::new("b", 1.0.into())],
+        );
+        let res = service.execute(CommandRequest::new_lrange("l1", 0, -1));
+        assert_res_ok(res, &["x".into(), 1.into()], &[]);
+
+        let res = service.execute(CommandRequest::new_hgetall("t2"));
+        assert_res_ok(
+            res,
+            &[],
+            &[
+                Kvpair::new("k1", true.into()),
+                Kvpair::new("k2", bytes::Bytes::from_static(b"hi").into()),
+            ],
+        );
+    }
+
+    #[test]
+    fn restore_should_split_large_table_into_batches() {
+        let store = MemTable::new();
+        for i in 0..RESTORE_BATCH * 2 + 1 {
+            store.set("t1", format!("k{}", i), i as i64).unwrap();
+        }
+        let mut buf = Vec::new();
+        dump(&store, &mut buf).unwrap();
+
+        let batches: Vec<_> = DumpReader::new(&buf[..])
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(batches.len(), 3);
+
+        let service: Service = Service::new(MemTable::new());
+        assert_eq!(restore(&service, &buf[..]).unwrap(), RESTORE_BATCH * 2 + 1);
+    }
+
+    #[test]
+    fn restore_should_limit_batches_by_bytes() {
+        let store = MemTable::new();
+        let value = |i: usize| Value::from(Bytes::from(vec![i as u8; 400 * 1024]));
+        for i in 0..30 {
+            store.set("t1", format!("k{}", i), value(i)).unwrap();
+        }
+        let blob = Bytes::from(vec![7u8; DUMP_CHUNK_SIZE * 5 + 1]);
+        store.set("t1", "blob", blob.clone()).unwrap();
+        let values: Vec<Value> = (0..30)
+            .map(|i| "x".repeat(i * 1000 + 200_000).into())
+            .collect();
+        store.rpush("l1", values.clone()).unwrap();
+        let mut buf = Vec::new();
+        dump(&store, &mut buf).unwrap();
+
+        let mut blobs = 0;
+        for batch in DumpReader::new(&buf[..]).unwrap() {
+            match batch.unwrap() {
+                RestoreBatch::Command(cmd) => assert!(cmd.encoded_len() < MAX_FRAME_LENGTH),
+                RestoreBatch::Blob { key, data, .. } => {
+                    assert_eq!((key.as_str(), &data), ("blob", &blob));
+                    blobs += 1;
+                }
+            }
+        }
+        assert_eq!(blobs, 1);
+
+        // 大的binary值分块上传，不受max_value_size的限制
+        let service: Service = Service::new(MemTable::new());
+        assert_eq!(restore(&service, &buf[..]).unwrap(), 61);
+        let res = service.execute(CommandRequest::new_hget("t1", "blob"));
+        assert_res_ok(res, &[blob.into()], &[]);
+        let res = service.execute(CommandRequest::new_hget("t1", "k29"));
+        assert_res_ok(res, &[value(29)], &[]);
+        let res = service.execute(CommandRequest::new_lrange("l1", 0, -1));
+        assert_res_ok(res, &values, &[]);
+    }
+
+    #[test]
+    fn restore_should_keep_list_order_across_batches() {
+        let store = MemTable::new();
+        let values: Vec<Value> = (0..RESTORE_BATCH as i64 * 2 + 1).map(Value::from).collect();
+        store.rpush("l1", values.clone()).unwrap();
+        let mut buf = Vec::new();
+        dump(&store, &mut buf).unwrap();
+
+        let service: Service = Service::new(MemTable::new());
+        restore(&service, &buf[..]).unwrap();
+        let res = service.execute(CommandRequest::new_lrange("l1", 0, -1));
+        assert_res_ok(res, &values, &[]);
+    }
+
+    #[test]
+    fn version_1_dump_should_be_restored() {
+        let mut buf = MAGIC.to_vec();
+        buf.extend(DumpHeader { version: 1 }.encode_length_delimited_to_vec());
+        let table = DumpTable {
+            name: "t1".into(),
+            count: 1,
+            ..Default::default()
+        };
+        buf.extend(table.encode_length_delimited_to_vec());
+        buf.extend(Kvpair::new("k1", "v1".into()).encode_length_delimited_to_vec());
+
+        let service: Service = Service::new(MemTable::new());
+        assert_eq!(restore(&service, &buf[..]).unwrap(), 1);
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &["v1".into()], &[]);
+    }
+
+    #[test]
+    fn invalid_dump_should_be_rejected() {
+        assert!(DumpReader::new(&b"NOTADUMP"[..]).is_err());
+
+        let mut buf = Vec::new();
+        dump(&store_with_data(), &mut buf).unwrap();
+        buf.truncate(buf.len() - 1);
+        let res: Result<Vec<_>, _> = DumpReader::new(&buf[..]).unwrap().collect();
+        assert!(res.is_err());
+    }
+
+    #[test]
+    fn oversized_message_length_should_be_rejected() {
+        // 长度前缀超过上限时直接失败
+        let mut buf = MAGIC.to_vec();
+        prost::encoding::encode_varint(u64::MAX >> 1, &mut buf);
+        assert!(matches!(
+            DumpReader::new(&buf[..]),
+            Err(KvError::InvalidDump(_))
+        ));
+
+        // 长度在上限之内，但超过了剩下的数据
+        let mut buf = MAGIC.to_vec();
+        prost::encoding::encode_varint(MAX_MESSAGE_LENGTH as u64, &mut buf);
+        buf.extend_from_slice(&[0u8; 16]);
+        assert!(matches!(
+            DumpReader::new(&buf[..]),
+            Err(KvError::InvalidDump(_))
+        ));
+    }
+
+    #[test]
+    fn to_json_should_work() {
+        let data = all_data(&store_with_data()).unwrap();
+        assert_eq!(
+            to_json(&data),
+            json!({
+                "version": 2,
+                "tables": {
+                    "t1": { "k1": "v1", "k2": 2 },
+                    "t2": { "k1": true, "k2": { "binary": "6869" } },
+                },
+                "zsets": { "z1": [["b", 1.0], ["a", 2.0]] },
+                "lists": { "l1": ["x", 1] },
+            })
+        );
+    }
+}
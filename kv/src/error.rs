@@ -9,7 +9,7 @@ pub enum KvError {
     #[error("Cannot parse command:`{0}`")]
     InvalidCommand(String),
 
-    #[error("Cannot convert value {:0} to {1}")]
+    #[error("Cannot convert value {0:?} to {1}")]
     ConvertError(Value, &'static str),
 
     #[error("Cannot process command: {0} with table: {1}, key: {2}, Error: {3}")]
@@ -21,6 +21,37 @@ pub enum KvError {
     #[error("Failed to decode protobuf message")]
     DecodeError(#[from] prost::DecodeError),
 
+    #[error("Too many requests: {0}")]
+    TooManyRequests(String),
+
+    #[error("Invalid dump file: {0}")]
+    InvalidDump(String),
+
+    #[error("I/O error: {0}")]
+    Io(String),
+
+    #[error("Script error: {0}")]
+    ScriptError(String),
+
+    #[error("Not leader, current leader: {0:?}")]
+    NotLeader(Option<u64>),
+
+    #[error("Another membership change is in progress")]
+    ConfChangeInProgress,
+
+    #[error("Quota exceeded: {0}")]
+    QuotaExceeded(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
     #[error("Internal error: {0}")]
     Internal(String),
 }
+
+// std::io::Error没有实现PartialEq，只保留错误信息
+impl From<std::io::Error> for KvError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e.to_string())
+    }
+}
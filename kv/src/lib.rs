@@ -1,9 +1,22 @@
+mod admin;
+mod cluster;
+mod dump;
 mod error;
+mod network;
 mod pb;
+mod proxy;
 mod service;
 mod storage;
+mod telemetry;
 
+pub use admin::*;
+pub use cluster::*;
+pub use dump::*;
 pub use error::KvError;
+pub use network::*;
 pub use pb::abi::*;
+pub use pb::KeyKind;
+pub use proxy::*;
 pub use service::*;
 pub use storage::*;
+pub use telemetry::*;
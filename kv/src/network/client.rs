@@ -0,0 +1,231 @@
+use crate::*;
+use bytes::{Bytes, BytesMut};
+use std::io::Read;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+/// 客户端的连接，写CommandRequest，读CommandResponse
+pub type ClientStream<S> = ProstStream<S, CommandResponse, CommandRequest>;
+
+/// 简单的kv客户端，一次发送一个命令并等待响应
+pub struct KvClient {
+    stream: ClientStream<TcpStream>,
+}
+
+impl KvClient {
+    pub async fn connect(addr: impl ToSocketAddrs) -> Result<Self, KvError> {
+        let stream = TcpStream::connect(addr).await?;
+        Ok(Self {
+            stream: ClientStream::new(stream),
+        })
+    }
+
+    /// 发送命令，返回服务器的响应
+    pub async fn execute(&mut self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        self.stream.send(cmd).await?;
+        self.recv()
+            .await
+            .unwrap_or_else(|| Err(KvError::Io("connection closed".into())))
+    }
+
+    /// 读取下一个响应，用于接收Watch之类的流式命令的推送
+    pub async fn recv(&mut self) -> Option<Result<CommandResponse, KvError>> {
+        self.stream.recv().await
+    }
+
+    /// 把reader中的数据按chunk_size分块上传到table的key，返回上传的字节数
+    /// chunk_size不能是0，也不能超过服务器的max_value_size
+    pub async fn upload(
+        &mut self,
+        table: &str,
+        key: &str,
+        mut reader: impl AsyncRead + Unpin,
+        chunk_size: usize,
+    ) -> Result<u64, KvError> {
+        if chunk_size == 0 {
+            return Err(KvError::InvalidCommand("chunk_size must not be 0".into()));
+        }
+        let (mut offset, mut upload_id) = (0, 0);
+        loop {
+            // 读满一个分块，读不满说明已经到了结尾
+            let mut buf = BytesMut::with_capacity(chunk_size);
+            while buf.len() < chunk_size {
+                if reader.read_buf(&mut buf).await? == 0 {
+                    break;
+                }
+            }
+            let len = buf.len() as u64;
+            let last = buf.len() < chunk_size;
+            let cmd =
+                CommandRequest::new_put_chunk(table, key, upload_id, offset, buf.freeze(), last);
+            let res = self.execute_ok(&cmd).await?;
+            // 之后的分块都要带上第一个分块返回的upload_id
+            upload_id = match res.values.get(1).and_then(|v| v.value.as_ref()) {
+                Some(value::Value::Integer(id)) => *id as u64,
+                _ => return Err(KvError::Internal("invalid PutChunk response".into())),
+            };
+            offset += len;
+            if last {
+                return Ok(offset);
+            }
+        }
+    }
+
+    /// 按chunk_size分块下载table中key的值写入writer，返回下载的字节数
+    pub async fn download(
+        &mut self,
+        table: &str,
+        key: &str,
+        mut writer: impl AsyncWrite + Unpin,
+        chunk_size: usize,
+    ) -> Result<u64, KvError> {
+        let mut offset = 0;
+        loop {
+            let cmd = CommandRequest::new_get_chunk(table, key, offset, chunk_size as _);
+            let res = self.execute_ok(&cmd).await?;
+            let (chunk, total) = match res.values.as_slice() {
+                [Value {
+                    value: Some(value::Value::Binary(chunk)),
+                }, Value {
+                    value: Some(value::Value::Integer(total)),
+                }] => (chunk.clone(), *total),
+                _ => return Err(KvError::Internal("invalid GetChunk response".into())),
+            };
+            writer.write_all(&chunk).await?;
+            offset += chunk.len() as u64;
+            if chunk.is_empty() || offset >= total as u64 {
+                writer.flush().await?;
+                return Ok(offset);
+            }
+        }
+    }
+
+    /// 读出当前数据库中所有的数据，按页读取，每页不超过DUMP_BATCH_BYTES，
+    /// 大的binary值用GetChunk分块读取。读取期间的修改可能只有一部分被导出
+    pub async fn dump(&mut self) -> Result<DumpData, KvError> {
+        let mut data = DumpData::default();
+        for name in self.keys(KeyKind::Hash).await? {
+            let pairs = self.scan_table(&name).await?;
+            if !pairs.is_empty() {
+                data.tables.push((name, pairs));
+            }
+        }
+
+        let max_bytes = DUMP_BATCH_BYTES as u64;
+        for key in self.keys(KeyKind::Zset).await? {
+            let mut members = Vec::new();
+            loop {
+                let cmd = CommandRequest::new_zrange(&key, members.len() as _, -1)
+                    .with_max_bytes(max_bytes);
+                let res = self.execute_ok(&cmd).await?;
+                if res.pairs.is_empty() {
+                    break;
+                }
+                for p in res.pairs {
+                    match p.value.and_then(|v| v.value) {
+                        Some(value::Value::Float(score)) => {
+                            members.push(ScoredMember::new(p.key, score))
+                        }
+                        v => return Err(KvError::Internal(format!("invalid score: {:?}", v))),
+                    }
+                }
+            }
+            if !members.is_empty() {
+                data.zsets.push((key, members));
+            }
+        }
+
+        for key in self.keys(KeyKind::List).await? {
+            let mut values = Vec::new();
+            loop {
+                let cmd = CommandRequest::new_lrange(&key, values.len() as _, -1)
+                    .with_max_bytes(max_bytes);
+                let res = self.execute_ok(&cmd).await?;
+                if res.values.is_empty() {
+                    break;
+                }
+                values.extend(res.values);
+            }
+            if !values.is_empty() {
+                data.lists.push((key, values));
+            }
+        }
+        Ok(data)
+    }
+
+    /// 把dump文件恢复到当前数据库，返回恢复的kv pair、成员和值的数量
+    pub async fn restore(&mut self, reader: impl Read) -> Result<usize, KvError> {
+        let mut count = 0;
+        for batch in DumpReader::new(reader)? {
+            let batch = batch?;
+            count += batch.count();
+            match batch {
+                RestoreBatch::Command(cmd) => {
+                    self.execute_ok(&cmd).await?;
+                }
+                RestoreBatch::Blob { table, key, data } => {
+                    self.upload(&table, &key, &data[..], DUMP_CHUNK_SIZE)
+                        .await?;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// 列出某种类型所有的key，按名字排序
+    async fn keys(&mut self, kind: KeyKind) -> Result<Vec<String>, KvError> {
+        let res = self
+            .execute_ok(&CommandRequest::new_tables_of(kind))
+            .await?;
+        let mut keys = res
+            .values
+            .into_iter()
+            .map(|v| match v.value {
+                Some(value::Value::String(s)) => Ok(s),
+                _ => Err(KvError::ConvertError(v, "String")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// 用Hscan按key的顺序分页读取table，只返回了key的值再用GetChunk读取
+    async fn scan_table(&mut self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let mut pairs = Vec::new();
+        let mut start = String::new();
+        loop {
+            let cmd = CommandRequest::new_hscan(table, start.as_str(), 0)
+                .with_max_bytes(DUMP_BATCH_BYTES as u64);
+            let res = self.execute_ok(&cmd).await?;
+            let last = match res.pairs.last() {
+                Some(p) => p.key.clone(),
+                None => return Ok(pairs),
+            };
+            for mut pair in res.pairs {
+                if pair.value.is_none() {
+                    let mut buf = Vec::new();
+                    self.download(table, &pair.key, &mut buf, DUMP_CHUNK_SIZE)
+                        .await?;
+                    pair.value = Some(Bytes::from(buf).into());
+                }
+                pairs.push(pair);
+            }
+            // 比last大的最小的key
+            start = last + "\0";
+        }
+    }
+
+    // 执行命令，不是200时返回错误
+    async fn execute_ok(&mut self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let res = self.execute(cmd).await?;
+        match res.status {
+            200 => Ok(res),
+            status => Err(KvError::Internal(format!(
+                "server returned {}: {}",
+                status, res.message
+            ))),
+        }
+    }
+}
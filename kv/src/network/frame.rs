@@ -0,0 +1,210 @@
+use crate::KvError;
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{
+    stream::{SplitSink, SplitStream},
+    SinkExt, StreamExt,
+};
+use prost::Message;
+use std::{io, marker::PhantomData};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_util::codec::{Decoder, Encoder, Framed};
+
+/// 默认的最大帧长度，和LengthDelimitedCodec的默认值一样
+pub const MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+/// 4字节长度前缀 + protobuf的帧，和async-prost的格式兼容
+pub struct ProstStream<S, In, Out> {
+    inner: Framed<S, FrameCodec>,
+    _in: PhantomData<In>,
+    _out: PhantomData<Out>,
+}
+
+/// ProstStream拆分出来的读端
+pub struct ProstReader<S, In> {
+    inner: SplitStream<Framed<S, FrameCodec>>,
+    _in: PhantomData<In>,
+}
+
+/// ProstStream拆分出来的写端
+pub struct ProstWriter<S, Out> {
+    inner: SplitSink<Framed<S, FrameCodec>, Bytes>,
+    _out: PhantomData<Out>,
+}
+
+/// 读到的帧，超过最大长度的帧的内容会被跳过，连接还可以继续使用
+#[derive(Debug, PartialEq)]
+enum Frame {
+    Data(BytesMut),
+    TooLarge(usize, usize),
+}
+
+/// 4字节大端长度前缀的编解码，读取时限制帧的长度
+#[derive(Debug)]
+struct FrameCodec {
+    max_length: usize,
+    // 还需要跳过的超长帧的字节数
+    skipping: usize,
+}
+
+impl Decoder for FrameCodec {
+    type Item = Frame;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, io::Error> {
+        if self.skipping > 0 {
+            let n = self.skipping.min(src.len());
+            src.advance(n);
+            self.skipping -= n;
+            if self.skipping > 0 {
+                return Ok(None);
+            }
+        }
+        if src.len() < 4 {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if len > self.max_length {
+            src.advance(4);
+            self.skipping = len;
+            return Ok(Some(Frame::TooLarge(len, self.max_length)));
+        }
+        if src.len() < 4 + len {
+            src.reserve(4 + len - src.len());
+            return Ok(None);
+        }
+        src.advance(4);
+        Ok(Some(Frame::Data(src.split_to(len))))
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, data: Bytes, dst: &mut BytesMut) -> Result<(), io::Error> {
+        let len = u32::try_from(data.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "frame is too large to send")
+        })?;
+        dst.reserve(4 + data.len());
+        dst.put_u32(len);
+        dst.extend_from_slice(&data);
+        Ok(())
+    }
+}
+
+impl<S, In, Out> ProstStream<S, In, Out>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    In: Message + Default,
+    Out: Message,
+{
+    pub fn new(stream: S) -> Self {
+        Self::with_max_frame_length(stream, MAX_FRAME_LENGTH)
+    }
+
+    /// 读取的帧超过max_length时recv返回PayloadTooLarge，之后还可以继续读写
+    pub fn with_max_frame_length(stream: S, max_length: usize) -> Self {
+        let codec = FrameCodec {
+            max_length,
+            skipping: 0,
+        };
+        Self {
+            inner: Framed::new(stream, codec),
+            _in: PhantomData,
+            _out: PhantomData,
+        }
+    }
+
+    /// 读取下一个消息，连接关闭时返回None
+    pub async fn recv(&mut self) -> Option<Result<In, KvError>> {
+        Some(decode(self.inner.next().await?))
+    }
+
+    /// 发送一个消息
+    pub async fn send(&mut self, msg: &Out) -> Result<(), KvError> {
+        self.inner.send(encode(msg)).await?;
+        Ok(())
+    }
+
+    /// 拆分成读端和写端，可以在不同的任务里分别读写
+    pub fn split(self) -> (ProstReader<S, In>, ProstWriter<S, Out>) {
+        let (sink, stream) = self.inner.split();
+        (
+            ProstReader {
+                inner: stream,
+                _in: PhantomData,
+            },
+            ProstWriter {
+                inner: sink,
+                _out: PhantomData,
+            },
+        )
+    }
+}
+
+impl<S, In> ProstReader<S, In>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    In: Message + Default,
+{
+    /// 读取下一个消息，连接关闭时返回None
+    pub async fn recv(&mut self) -> Option<Result<In, KvError>> {
+        Some(decode(self.inner.next().await?))
+    }
+}
+
+impl<S, Out> ProstWriter<S, Out>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+    Out: Message,
+{
+    /// 发送一个消息
+    pub async fn send(&mut self, msg: &Out) -> Result<(), KvError> {
+        self.inner.send(encode(msg)).await?;
+        Ok(())
+    }
+}
+
+fn decode<In: Message + Default>(frame: Result<Frame, io::Error>) -> Result<In, KvError> {
+    match frame? {
+        Frame::Data(data) => Ok(In::decode(data)?),
+        Frame::TooLarge(len, max) => Err(KvError::PayloadTooLarge(format!(
+            "frame size {} exceeds {}",
+            len, max
+        ))),
+    }
+}
+
+fn encode<Out: Message>(msg: &Out) -> Bytes {
+    Bytes::from(msg.encode_to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(data: &[u8]) -> Vec<u8> {
+        let mut buf = (data.len() as u32).to_be_bytes().to_vec();
+        buf.extend_from_slice(data);
+        buf
+    }
+
+    #[test]
+    fn oversized_frame_should_be_skipped() {
+        let mut codec = FrameCodec {
+            max_length: 4,
+            skipping: 0,
+        };
+        let mut src = BytesMut::from(&frame(b"abcdefgh")[..6]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(Frame::TooLarge(8, 4)));
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+
+        // 剩下的内容分几次到达，跳过之后可以读到下一个帧
+        src.extend_from_slice(&frame(b"abcdefgh")[6..]);
+        src.extend_from_slice(&frame(b"abc")[..5]);
+        assert_eq!(codec.decode(&mut src).unwrap(), None);
+        src.extend_from_slice(&frame(b"abc")[5..]);
+        let data = BytesMut::from(&b"abc"[..]);
+        assert_eq!(codec.decode(&mut src).unwrap(), Some(Frame::Data(data)));
+        assert!(src.is_empty());
+    }
+}
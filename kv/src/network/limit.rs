@@ -0,0 +1,84 @@
+use std::{sync::Mutex, time::Instant};
+
+/// 令牌桶限流，每秒补充rate个令牌，最多积攒rate个
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: f64,
+    state: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        let rate = rate as f64;
+        Self {
+            rate,
+            state: Mutex::new(Bucket {
+                tokens: rate,
+                last: Instant::now(),
+            }),
+        }
+    }
+
+    /// 尝试取一个令牌，没有令牌时返回false
+    pub fn try_acquire(&self) -> bool {
+        let mut bucket = self.state.lock().unwrap();
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.last).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 退还一个令牌，取到令牌之后请求又被其他限流拒绝时使用
+    pub fn release(&self) {
+        let mut bucket = self.state.lock().unwrap();
+        bucket.tokens = (bucket.tokens + 1.0).min(self.rate);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn rate_limiter_should_work() {
+        let limiter = RateLimiter::new(10);
+        for _ in 0..10 {
+            assert!(limiter.try_acquire());
+        }
+        assert!(!limiter.try_acquire());
+
+        // 100ms后补充一个令牌
+        thread::sleep(Duration::from_millis(110));
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+    }
+
+    #[test]
+    fn released_token_should_be_reused() {
+        let limiter = RateLimiter::new(1);
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+        limiter.release();
+        assert!(limiter.try_acquire());
+
+        // 令牌不会超过上限
+        limiter.release();
+        limiter.release();
+        assert!(limiter.try_acquire());
+        assert!(!limiter.try_acquire());
+    }
+}
@@ -0,0 +1,317 @@
+mod client;
+mod frame;
+mod limit;
+mod server;
+
+pub use client::{ClientStream, KvClient};
+pub use frame::{ProstReader, ProstStream, ProstWriter, MAX_FRAME_LENGTH};
+pub use limit::RateLimiter;
+pub(crate) use server::shutdown_signal;
+pub use server::{KvServer, ServerConfig, ServerStats, ServerStream};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::*;
+    use bytes::Bytes;
+    use std::time::Duration;
+    use tokio::{net::TcpListener, sync::oneshot, task::JoinHandle};
+
+    async fn start_server(
+        timeout: Duration,
+    ) -> (String, oneshot::Sender<()>, JoinHandle<Result<(), KvError>>) {
+        let config = ServerConfig {
+            shutdown_timeout: timeout,
+            ..Default::default()
+        };
+        start_server_with_config(config).await
+    }
+
+    async fn start_server_with_config(
+        config: ServerConfig,
+    ) -> (String, oneshot::Sender<()>, JoinHandle<Result<(), KvError>>) {
+        start_server_with_service(config, Service::new(MemTable::new())).await
+    }
+
+    async fn start_server_with_service(
+        mut config: ServerConfig,
+        service: Service,
+    ) -> (String, oneshot::Sender<()>, JoinHandle<Result<(), KvError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        config.addr = addr.clone();
+        let server: KvServer = KvServer::new(config, service);
+        let (tx, rx) = oneshot::channel();
+        let handle = tokio::spawn(async move {
+            server
+                .serve(listener, async {
+                    rx.await.ok();
+                })
+                .await
+        });
+        (addr, tx, handle)
+    }
+
+    #[tokio::test]
+    async fn server_should_serve_commands() {
+        let (addr, _tx, _handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[Value::default()], &[]);
+
+        let res = client
+            .execute(&CommandRequest::new_hget("t1", "k1"))
+            .await
+            .unwrap();
+        assert_res_ok(res, &["v1".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn select_should_only_affect_its_connection() {
+        let stores = vec![MemTable::new(), MemTable::new()];
+        let service = Service::with_databases(stores, ServiceConfig::default());
+        let (addr, _tx, _handle) = start_server_with_service(Default::default(), service).await;
+        let mut client1 = KvClient::connect(&addr).await.unwrap();
+        let mut client2 = KvClient::connect(&addr).await.unwrap();
+
+        let res = client1
+            .execute(&CommandRequest::new_select(1))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[], &[]);
+        let res = client1
+            .execute(&CommandRequest::new_select(2))
+            .await
+            .unwrap();
+        assert_res_error(res, 400, "does not exist");
+
+        let cmd = CommandRequest::new_hset("t1", "k1", "v1".into());
+        client1.execute(&cmd).await.unwrap();
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        let res = client1.execute(&cmd).await.unwrap();
+        assert_res_ok(res, &["v1".into()], &[]);
+        let res = client2.execute(&cmd).await.unwrap();
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[tokio::test]
+    async fn client_should_upload_and_download_in_chunks() {
+        let (addr, _tx, _handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let data: Vec<u8> = (0..10000).map(|i| (i % 251) as u8).collect();
+
+        let len = client.upload("files", "f1", &data[..], 4096).await.unwrap();
+        assert_eq!(len, 10000);
+        let mut buf = Vec::new();
+        let len = client
+            .download("files", "f1", &mut buf, 3000)
+            .await
+            .unwrap();
+        assert_eq!(len, 10000);
+        assert_eq!(buf, data);
+
+        // 长度正好是分块大小的整数倍时最后一个分块是空的
+        client
+            .upload("files", "f2", &data[..4096], 4096)
+            .await
+            .unwrap();
+        let mut buf = Vec::new();
+        client
+            .download("files", "f2", &mut buf, 4096)
+            .await
+            .unwrap();
+        assert_eq!(buf, &data[..4096]);
+    }
+
+    #[tokio::test]
+    async fn client_should_dump_and_restore_large_data() {
+        let (addr, _tx, _handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        // 每个table、有序集合和列表都超过了最大帧长度，需要分页读取
+        let value = |i: usize| Value::from(Bytes::from(vec![i as u8; 400 * 1024]));
+        let pairs: Vec<_> = (0..30)
+            .map(|i| Kvpair::new(format!("k{:02}", i), value(i)))
+            .collect();
+        for chunk in pairs.chunks(5) {
+            let cmd = CommandRequest::new_hmset("t1", chunk.to_vec());
+            assert_eq!(client.execute(&cmd).await.unwrap().status, 200);
+        }
+        let blob: Vec<u8> = (0..DUMP_CHUNK_SIZE * 20).map(|i| (i % 251) as u8).collect();
+        client
+            .upload("t1", "blob", &blob[..], 1 << 20)
+            .await
+            .unwrap();
+        let members: Vec<_> = (0..3000)
+            .map(|i| ScoredMember::new(format!("{:04}{}", i, "m".repeat(4000)), i as f64))
+            .collect();
+        for chunk in members.chunks(500) {
+            let cmd = CommandRequest::new_zadd("z1", chunk.to_vec());
+            assert_eq!(client.execute(&cmd).await.unwrap().status, 200);
+        }
+        let values: Vec<_> = (0..30).map(value).collect();
+        for chunk in values.chunks(5) {
+            let cmd = CommandRequest::new_lpush("l1", chunk.to_vec());
+            assert_eq!(client.execute(&cmd).await.unwrap().status, 200);
+        }
+
+        let data = client.dump().await.unwrap();
+        assert_eq!(data.count(), 30 + 1 + 3000 + 30);
+        let mut expected = pairs;
+        expected.insert(0, Kvpair::new("blob", Bytes::from(blob).into()));
+        assert_eq!(data.tables, vec![("t1".to_string(), expected)]);
+        assert_eq!(data.zsets, vec![("z1".to_string(), members)]);
+
+        let mut buf = Vec::new();
+        write_dump(&data, &mut buf).unwrap();
+        let (addr, _tx, _handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        assert_eq!(client.restore(&buf[..]).await.unwrap(), data.count());
+        assert_eq!(client.dump().await.unwrap(), data);
+    }
+
+    #[tokio::test]
+    async fn upload_with_zero_chunk_size_should_fail() {
+        let (addr, _tx, _handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let res = client.upload("files", "f1", &b"abc"[..], 0).await;
+        assert!(matches!(res, Err(KvError::InvalidCommand(_))));
+    }
+
+    #[tokio::test]
+    async fn oversized_frame_should_get_413() {
+        let (addr, _tx, _handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let value: Value = Bytes::from(vec![0u8; MAX_FRAME_LENGTH + 1]).into();
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", value))
+            .await
+            .unwrap();
+        assert_res_error(res, 413, "frame size");
+
+        // 超长的帧被跳过，连接还可以继续使用
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", "v1".into()))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 200);
+    }
+
+    #[tokio::test]
+    async fn max_frame_length_should_follow_max_value_size() {
+        let config = ServiceConfig {
+            max_value_size: MAX_FRAME_LENGTH * 2,
+            ..Default::default()
+        };
+        let service = Service::with_config(MemTable::new(), config);
+        let (addr, _tx, _handle) = start_server_with_service(Default::default(), service).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let value: Value = Bytes::from(vec![0u8; MAX_FRAME_LENGTH + 1]).into();
+        let res = client
+            .execute(&CommandRequest::new_hset("t1", "k1", value))
+            .await
+            .unwrap();
+        assert_eq!(res.status, 200);
+    }
+
+    #[tokio::test]
+    async fn server_should_stop_accepting_after_shutdown() {
+        let (addr, tx, handle) = start_server(Duration::from_secs(1)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        client.execute(&CommandRequest::new_info()).await.unwrap();
+
+        tx.send(()).unwrap();
+        handle.await.unwrap().unwrap();
+
+        // 已有的连接被关闭，新的连接无法建立
+        assert!(client.recv().await.is_none());
+        assert!(KvClient::connect(&addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn shutdown_should_close_watch_streams() {
+        let (addr, tx, handle) = start_server(Duration::from_secs(5)).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let res = client
+            .execute(&CommandRequest::new_watch("t1", ""))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[], &[]);
+
+        tx.send(()).unwrap();
+        // watch流在关闭时直接结束，不需要等到超时
+        tokio::time::timeout(Duration::from_secs(1), handle)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert!(client.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn connection_rate_limit_should_return_429() {
+        let config = ServerConfig {
+            connection_rate_limit: Some(2),
+            ..Default::default()
+        };
+        let (addr, _tx, _handle) = start_server_with_config(config).await;
+        let mut client = KvClient::connect(&addr).await.unwrap();
+
+        let cmd = CommandRequest::new_hget("t1", "k1");
+        for _ in 0..2 {
+            let res = client.execute(&cmd).await.unwrap();
+            assert_res_error(res, 404, "Not found");
+        }
+        let res = client.execute(&cmd).await.unwrap();
+        assert_res_error(res, 429, "connection rate limit");
+
+        // 限流是针对每个连接的，新的连接不受影响
+        let mut client = KvClient::connect(&addr).await.unwrap();
+        let res = client.execute(&cmd).await.unwrap();
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[tokio::test]
+    async fn global_rate_limit_should_be_shared_by_connections() {
+        let config = ServerConfig {
+            global_rate_limit: Some(1),
+            ..Default::default()
+        };
+        let (addr, _tx, _handle) = start_server_with_config(config).await;
+        let cmd = CommandRequest::new_info();
+
+        let mut client1 = KvClient::connect(&addr).await.unwrap();
+        let res = client1.execute(&cmd).await.unwrap();
+        assert_eq!(res.status, 200);
+
+        let mut client2 = KvClient::connect(&addr).await.unwrap();
+        let res = client2.execute(&cmd).await.unwrap();
+        assert_res_error(res, 429, "global rate limit");
+    }
+
+    #[tokio::test]
+    async fn max_connections_should_reject_new_clients() {
+        let config = ServerConfig {
+            max_connections: 1,
+            ..Default::default()
+        };
+        let (addr, _tx, _handle) = start_server_with_config(config).await;
+        let mut client1 = KvClient::connect(&addr).await.unwrap();
+        client1.execute(&CommandRequest::new_info()).await.unwrap();
+
+        let mut client2 = KvClient::connect(&addr).await.unwrap();
+        let res = client2.recv().await.unwrap().unwrap();
+        assert_res_error(res, 429, "too many connections");
+        assert!(client2.recv().await.is_none());
+
+        // 第一个连接断开后可以建立新的连接
+        drop(client1);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut client3 = KvClient::connect(&addr).await.unwrap();
+        let res = client3.execute(&CommandRequest::new_info()).await.unwrap();
+        assert_eq!(res.status, 200);
+    }
+}
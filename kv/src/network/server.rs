@@ -0,0 +1,393 @@
+use crate::{command_request::RequestData, *};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, watch, Semaphore},
+    time,
+};
+use tracing::{info, info_span, warn, Instrument};
+
+/// 服务器端的连接，读CommandRequest，写CommandResponse
+pub type ServerStream<S> = ProstStream<S, CommandRequest, CommandResponse>;
+
+/// 服务器的配置
+#[derive(Clone, Debug)]
+pub struct ServerConfig {
+    /// 监听的地址
+    pub addr: String,
+    /// 关闭时等待连接上正在执行的命令完成的最长时间
+    pub shutdown_timeout: Duration,
+    /// 最大并发连接数，超过之后新的连接会收到429并被关闭
+    pub max_connections: usize,
+    /// 所有连接加起来每秒最多执行的命令数，None表示不限制
+    pub global_rate_limit: Option<u32>,
+    /// 每个连接每秒最多执行的命令数，None表示不限制
+    pub connection_rate_limit: Option<u32>,
+    /// 每个连接最多缓存的未执行的请求数，满了之后暂停读取，让客户端感受到背压
+    pub request_queue_size: usize,
+    /// web管理界面监听的地址，None表示不开启
+    pub admin_addr: Option<String>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9527".into(),
+            shutdown_timeout: Duration::from_secs(30),
+            max_connections: 1024,
+            global_rate_limit: None,
+            connection_rate_limit: None,
+            request_queue_size: 32,
+            admin_addr: None,
+        }
+    }
+}
+
+/// kv服务器，收到ctrl-c或者SIGTERM时会停止接受新连接，等待已有连接处理完再退出
+pub struct KvServer<Store = MemTable> {
+    config: ServerConfig,
+    service: Service<Store>,
+    connections: Arc<Semaphore>,
+    global_limiter: Option<Arc<RateLimiter>>,
+    stats: Arc<ServerStats>,
+    cluster: Option<ClusterHandle>,
+}
+
+/// 服务器的连接统计
+#[derive(Debug, Default)]
+pub struct ServerStats {
+    active: AtomicUsize,
+    accepted: AtomicU64,
+    rejected: AtomicU64,
+}
+
+impl ServerStats {
+    /// 当前的连接数
+    pub fn active_connections(&self) -> usize {
+        self.active.load(Ordering::Relaxed)
+    }
+
+    /// 启动以来接受的连接数
+    pub fn total_connections(&self) -> u64 {
+        self.accepted.load(Ordering::Relaxed)
+    }
+
+    /// 因为连接数超过上限被拒绝的连接数
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+/// 每个连接共享的状态
+struct Connection<Store> {
+    service: Service<Store>,
+    shutdown: watch::Receiver<bool>,
+    global_limiter: Option<Arc<RateLimiter>>,
+    limiter: Option<RateLimiter>,
+    queue_size: usize,
+    cluster: Option<ClusterHandle>,
+}
+
+impl<Store: Storage + Send + Sync + 'static> KvServer<Store> {
+    pub fn new(config: ServerConfig, service: Service<Store>) -> Self {
+        Self {
+            connections: Arc::new(Semaphore::new(config.max_connections)),
+            global_limiter: config
+                .global_rate_limit
+                .map(|rate| Arc::new(RateLimiter::new(rate))),
+            config,
+            service,
+            stats: Arc::default(),
+            cluster: None,
+        }
+    }
+
+    /// 以集群模式运行，命令通过raft在集群中执行，service必须是启动集群节点时使用的service
+    pub fn with_cluster(mut self, cluster: ClusterHandle) -> Self {
+        self.cluster = Some(cluster);
+        self
+    }
+
+    /// 连接统计
+    pub fn stats(&self) -> Arc<ServerStats> {
+        Arc::clone(&self.stats)
+    }
+
+    /// 监听配置的地址，一直运行到收到ctrl-c或者SIGTERM
+    pub async fn run(&self) -> Result<(), KvError> {
+        let listener = TcpListener::bind(&self.config.addr).await?;
+        info!("Start listening on {}", self.config.addr);
+
+        let admin = match &self.config.admin_addr {
+            Some(addr) => {
+                let listener = TcpListener::bind(addr).await?;
+                info!("Admin console listening on http://{}", addr);
+                let router = admin_router(self.service.clone(), self.stats(), self.cluster.clone());
+                Some(tokio::spawn(serve_admin(listener, router)))
+            }
+            None => None,
+        };
+        let res = self.serve(listener, shutdown_signal()).await;
+        if let Some(admin) = admin {
+            admin.abort();
+        }
+        res
+    }
+
+    /// 在listener上处理连接，直到shutdown完成
+    pub async fn serve(
+        &self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), KvError> {
+        // 通知所有连接开始关闭
+        let (notify_tx, notify_rx) = watch::channel(false);
+        // 每个连接持有一个sender，所有sender都drop之后说明连接都处理完了
+        let (done_tx, mut done_rx) = mpsc::channel::<()>(1);
+
+        // 定期清理客户端没有完成的分块上传
+        let service = self.service.clone();
+        let sweeper = tokio::spawn(async move {
+            let mut ticker = time::interval(UPLOAD_SWEEP_INTERVAL);
+            loop {
+                ticker.tick().await;
+                service.sweep_uploads();
+            }
+        });
+
+        tokio::pin!(shutdown);
+        loop {
+            let (stream, addr) = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            let permit = match Arc::clone(&self.connections).try_acquire_owned() {
+                Ok(permit) => permit,
+                Err(_) => {
+                    warn!("Too many connections, reject client: {:?}", addr);
+                    self.stats.rejected.fetch_add(1, Ordering::Relaxed);
+                    tokio::spawn(reject_connection(stream));
+                    continue;
+                }
+            };
+            info!("Client: {:?} connected", addr);
+            self.stats.accepted.fetch_add(1, Ordering::Relaxed);
+            self.stats.active.fetch_add(1, Ordering::Relaxed);
+            let stats = self.stats();
+
+            let conn = Connection {
+                service: self.service.clone(),
+                shutdown: notify_rx.clone(),
+                global_limiter: self.global_limiter.clone(),
+                limiter: self.config.connection_rate_limit.map(RateLimiter::new),
+                queue_size: self.config.request_queue_size,
+                cluster: self.cluster.clone(),
+            };
+            let done = done_tx.clone();
+            // 连接上执行的命令的span都在这个span之下
+            let span = info_span!("connection", peer = %addr);
+            tokio::spawn(
+                async move {
+                    match conn.handle(stream).await {
+                        Ok(()) => info!("Client {:?} disconnected", addr),
+                        Err(e) => warn!("Client {:?} disconnected with error: {}", addr, e),
+                    }
+                    stats.active.fetch_sub(1, Ordering::Relaxed);
+                    drop(permit);
+                    drop(done);
+                }
+                .instrument(span),
+            );
+        }
+
+        info!("Shutting down, stop accepting new connections");
+        drop(listener);
+        sweeper.abort();
+        // 接收端不会在这之前drop，所以不会出错
+        notify_tx.send(true).ok();
+        drop(done_tx);
+
+        if time::timeout(self.config.shutdown_timeout, done_rx.recv())
+            .await
+            .is_err()
+        {
+            warn!(
+                "Connections are not closed in {:?}, force shutdown",
+                self.config.shutdown_timeout
+            );
+        }
+
+        self.service.flush()?;
+        info!("Server stopped");
+        Ok(())
+    }
+}
+
+impl<Store: Storage + Send + Sync + 'static> Connection<Store> {
+    /// 处理一个连接，收到关闭通知后执行完已经读到的命令就返回
+    async fn handle(self, stream: TcpStream) -> Result<(), KvError> {
+        let max_frame_length = self.service.max_frame_length();
+        let stream = ServerStream::with_max_frame_length(stream, max_frame_length);
+        let (mut reader, mut writer) = stream.split();
+        let (tx, mut rx) = mpsc::channel(self.queue_size);
+
+        // 读取请求放入有界队列，队列满时不再读取
+        // 超长的请求已经被跳过，按顺序返回413，连接可以继续使用
+        let mut shutdown = self.shutdown.clone();
+        let read = async move {
+            loop {
+                let cmd = tokio::select! {
+                    cmd = reader.recv() => match cmd {
+                        Some(Err(e @ KvError::PayloadTooLarge(_))) => Err(e),
+                        Some(cmd) => Ok(cmd?),
+                        None => return Ok(()),
+                    },
+                    _ = shutdown.changed() => return Ok(()),
+                };
+                if tx.send(cmd).await.is_err() {
+                    return Ok(());
+                }
+            }
+        };
+
+        // 按顺序执行队列中的请求，读端结束后会把队列中剩下的请求执行完
+        let mut shutdown = self.shutdown.clone();
+        // 连接当前选择的数据库
+        let mut service = self.service.clone();
+        let execute = async {
+            while let Some(cmd) = rx.recv().await {
+                let cmd = match cmd {
+                    Ok(cmd) => cmd,
+                    Err(e) => {
+                        writer.send(&CommandResponse::from(e)).await?;
+                        continue;
+                    }
+                };
+                if let Err(e) = self.check_rate_limit() {
+                    writer.send(&CommandResponse::from(e)).await?;
+                    continue;
+                }
+
+                if let Some(cluster) = &self.cluster {
+                    let res = match &cmd.request_data {
+                        // 集群只复制第0个数据库
+                        Some(RequestData::Select(_)) => KvError::InvalidCommand(
+                            "select is not supported in cluster mode".into(),
+                        )
+                        .into(),
+                        _ => cluster.execute(cmd).await,
+                    };
+                    writer.send(&res).await?;
+                    continue;
+                }
+
+                if let Some(RequestData::Select(param)) = &cmd.request_data {
+                    let res = match service.select(param.db) {
+                        Ok(selected) => {
+                            service = selected;
+                            CommandResponse::ok()
+                        }
+                        Err(e) => e.into(),
+                    };
+                    writer.send(&res).await?;
+                    continue;
+                }
+
+                let mut res = service.execute_streaming(cmd);
+                loop {
+                    // 优先把已经执行完的响应发出去，Watch之类的流式命令在关闭时直接结束
+                    let resp = tokio::select! {
+                        biased;
+                        resp = res.recv() => match resp {
+                            Some(resp) => resp,
+                            None => break,
+                        },
+                        _ = shutdown.changed() => return Ok(()),
+                    };
+                    writer.send(&resp).await?;
+                }
+            }
+            Ok::<_, KvError>(())
+        };
+
+        let (read, execute) = tokio::join!(read, execute);
+        read.and(execute)
+    }
+
+    fn check_rate_limit(&self) -> Result<(), KvError> {
+        if let Some(limiter) = &self.limiter {
+            if !limiter.try_acquire() {
+                return Err(KvError::TooManyRequests(
+                    "connection rate limit exceeded".into(),
+                ));
+            }
+        }
+        if let Some(limiter) = &self.global_limiter {
+            if !limiter.try_acquire() {
+                // 被全局限流拒绝的请求不占用连接的配额
+                if let Some(limiter) = &self.limiter {
+                    limiter.release();
+                }
+                return Err(KvError::TooManyRequests(
+                    "global rate limit exceeded".into(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 连接数超过上限时，告诉客户端原因后关闭连接
+async fn reject_connection(stream: TcpStream) {
+    let mut stream = ServerStream::new(stream);
+    let resp = KvError::TooManyRequests("too many connections".into()).into();
+    if let Err(e) = stream.send(&resp).await {
+        warn!("Failed to reject connection: {}", e);
+    }
+}
+
+/// 等待ctrl-c或者SIGTERM
+pub(crate) async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            warn!("Failed to listen for ctrl-c: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut s) => {
+                s.recv().await;
+            }
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => info!("Received ctrl-c"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
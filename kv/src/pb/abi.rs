@@ -1,163 +1,550 @@
 /// 来自客户端的命令请求
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct CommandRequest {
-    #[prost(oneof="command_request::RequestData", tags="1, 2, 3, 4, 5, 6, 7, 8, 9")]
+    #[prost(
+        oneof = "command_request::RequestData",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26"
+    )]
     pub request_data: ::core::option::Option<command_request::RequestData>,
 }
 /// Nested message and enum types in `CommandRequest`.
 pub mod command_request {
-    #[derive(PartialOrd)]
-    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
     pub enum RequestData {
-        #[prost(message, tag="1")]
+        #[prost(message, tag = "1")]
         Hget(super::Hget),
-        #[prost(message, tag="2")]
+        #[prost(message, tag = "2")]
         Hgetall(super::Hgetall),
-        #[prost(message, tag="3")]
+        #[prost(message, tag = "3")]
         Hmget(super::Hmget),
-        #[prost(message, tag="4")]
+        #[prost(message, tag = "4")]
         Hset(super::Hset),
-        #[prost(message, tag="5")]
+        #[prost(message, tag = "5")]
         Hmset(super::Hmset),
-        #[prost(message, tag="6")]
+        #[prost(message, tag = "6")]
         Hdel(super::Hdel),
-        #[prost(message, tag="7")]
+        #[prost(message, tag = "7")]
         Hmdel(super::Hmdel),
-        #[prost(message, tag="8")]
+        #[prost(message, tag = "8")]
         Hexist(super::Hexist),
-        #[prost(message, tag="9")]
+        #[prost(message, tag = "9")]
         Hmexist(super::Hmexist),
+        #[prost(message, tag = "10")]
+        Watch(super::Watch),
+        #[prost(message, tag = "11")]
+        Info(super::Info),
+        #[prost(message, tag = "12")]
+        Tables(super::Tables),
+        #[prost(message, tag = "13")]
+        Eval(super::Eval),
+        #[prost(message, tag = "14")]
+        Zadd(super::Zadd),
+        #[prost(message, tag = "15")]
+        Zrange(super::Zrange),
+        #[prost(message, tag = "16")]
+        Zrank(super::Zrank),
+        #[prost(message, tag = "17")]
+        Zrem(super::Zrem),
+        #[prost(message, tag = "18")]
+        Lpush(super::Lpush),
+        #[prost(message, tag = "19")]
+        Rpop(super::Rpop),
+        #[prost(message, tag = "20")]
+        Lrange(super::Lrange),
+        #[prost(message, tag = "21")]
+        Brpop(super::Brpop),
+        #[prost(message, tag = "22")]
+        SlowLog(super::SlowLog),
+        #[prost(message, tag = "23")]
+        Select(super::Select),
+        #[prost(message, tag = "24")]
+        PutChunk(super::PutChunk),
+        #[prost(message, tag = "25")]
+        GetChunk(super::GetChunk),
+        #[prost(message, tag = "26")]
+        Hscan(super::Hscan),
     }
 }
 /// 服务器的响应
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct CommandResponse {
     /// 状态码；复用HTTP 2xx/4xx/5xx状态码
-    #[prost(uint32, tag="1")]
+    #[prost(uint32, tag = "1")]
     pub status: u32,
     /// 如果不是2xx，message里包含详细的信息
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub message: ::prost::alloc::string::String,
     /// 成功返回的values
-    #[prost(message, repeated, tag="3")]
+    #[prost(message, repeated, tag = "3")]
     pub values: ::prost::alloc::vec::Vec<Value>,
     /// 成功返回的kv pairs
-    #[prost(message, repeated, tag="4")]
+    #[prost(message, repeated, tag = "4")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
+    /// watch推送的key变化
+    #[prost(message, repeated, tag = "5")]
+    pub events: ::prost::alloc::vec::Vec<WatchEvent>,
+    /// SlowLog返回的慢命令
+    #[prost(message, repeated, tag = "6")]
+    pub slow_logs: ::prost::alloc::vec::Vec<SlowLogEntry>,
 }
 /// 从table中获取一个key，返回value
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hget {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
 /// 从table中获取所有的Kvpair
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hgetall {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
 }
+/// 按key的顺序分页读取table，返回key不小于start的最多count个Kvpair
+/// 总大小不超过max_bytes，至少返回一个；单个binary值超过max_bytes时只返回key，
+/// 值要用GetChunk分块读取。count和max_bytes为0表示不限制，返回空的pairs表示读完了
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Hscan {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub start: ::prost::alloc::string::String,
+    #[prost(uint32, tag = "3")]
+    pub count: u32,
+    #[prost(uint64, tag = "4")]
+    pub max_bytes: u64,
+}
 /// 从table中获取一组key，返回它们的value
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hmget {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 返回的值
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Value {
-    #[prost(oneof="value::Value", tags="1, 2, 3, 4, 5")]
+    #[prost(oneof = "value::Value", tags = "1, 2, 3, 4, 5")]
     pub value: ::core::option::Option<value::Value>,
 }
 /// Nested message and enum types in `Value`.
 pub mod value {
-    #[derive(PartialOrd)]
-    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
     pub enum Value {
-        #[prost(string, tag="1")]
+        #[prost(string, tag = "1")]
         String(::prost::alloc::string::String),
-        #[prost(bytes, tag="2")]
+        #[prost(bytes, tag = "2")]
         Binary(::prost::bytes::Bytes),
-        #[prost(int64, tag="3")]
+        #[prost(int64, tag = "3")]
         Integer(i64),
-        #[prost(double, tag="4")]
+        #[prost(double, tag = "4")]
         Float(f64),
-        #[prost(bool, tag="5")]
+        #[prost(bool, tag = "5")]
         Bool(bool),
     }
 }
 /// 返回的kvpair
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Kvpair {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub key: ::prost::alloc::string::String,
-    #[prost(message, optional, tag="2")]
+    #[prost(message, optional, tag = "2")]
     pub value: ::core::option::Option<Value>,
 }
 /// 往table里存一个kvpair
 /// 如果table不存在就创建这个table
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hset {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(message, optional, tag="2")]
+    #[prost(message, optional, tag = "2")]
     pub pair: ::core::option::Option<Kvpair>,
 }
 /// 往table里存一组kvpair
 /// 如果table不存在就创建这个table
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hmset {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(message, repeated, tag="2")]
+    #[prost(message, repeated, tag = "2")]
     pub pairs: ::prost::alloc::vec::Vec<Kvpair>,
 }
 /// 从table中删除一个key，返回它之前的值
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hdel {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
 /// 从table中删除一组key，返回它们之前的值
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hmdel {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
 }
 /// 查看key是否存在
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hexist {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, tag="2")]
+    #[prost(string, tag = "2")]
     pub key: ::prost::alloc::string::String,
 }
 /// 查看一组key是否存在
-#[derive(PartialOrd)]
-#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
 pub struct Hmexist {
-    #[prost(string, tag="1")]
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 监听table中以key_prefix开头的key的变化
+/// 这是一个流式命令，每次set/del匹配的key都会收到一个响应
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Watch {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key_prefix: ::prost::alloc::string::String,
+}
+/// 一次key的变化
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct WatchEvent {
+    #[prost(string, tag = "1")]
     pub table: ::prost::alloc::string::String,
-    #[prost(string, repeated, tag="2")]
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    /// 变化的类型：set或者del
+    #[prost(string, tag = "3")]
+    pub op: ::prost::alloc::string::String,
+    /// 变化之前的值，之前不存在时为空
+    #[prost(message, optional, tag = "4")]
+    pub old_value: ::core::option::Option<Value>,
+    /// 变化之后的值，删除时为空
+    #[prost(message, optional, tag = "5")]
+    pub new_value: ::core::option::Option<Value>,
+}
+/// 查看存储的状态，比如使用的内存和淘汰的key数量
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Info {}
+/// 列出所有的table，kind为1或者2时列出所有有序集合或者列表的key
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Tables {
+    #[prost(uint32, tag = "1")]
+    pub kind: u32,
+}
+/// 原子地执行一段lua脚本，脚本中可以通过hget/hset/hdel读写存储
+/// keys和args在脚本中分别是KEYS和ARGV
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Eval {
+    #[prost(string, tag = "1")]
+    pub script: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
     pub keys: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+    #[prost(message, repeated, tag = "3")]
+    pub args: ::prost::alloc::vec::Vec<Value>,
+}
+/// 有序集合中的成员
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct ScoredMember {
+    #[prost(string, tag = "1")]
+    pub member: ::prost::alloc::string::String,
+    #[prost(double, tag = "2")]
+    pub score: f64,
+}
+/// 往有序集合里添加成员，返回新增的成员数量
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zadd {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub members: ::prost::alloc::vec::Vec<ScoredMember>,
+}
+/// 按排名返回有序集合中[start, stop]之间的成员，负数表示从后往前数
+/// 返回的pairs中key是成员，value是score
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zrange {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub start: i64,
+    #[prost(int64, tag = "3")]
+    pub stop: i64,
+    /// 返回的成员编码后的总大小上限，超过时截断，至少返回一个；0表示不限制
+    #[prost(uint64, tag = "4")]
+    pub max_bytes: u64,
+}
+/// 返回成员在有序集合中的排名
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zrank {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub member: ::prost::alloc::string::String,
+}
+/// 从有序集合中删除成员，返回删除的数量
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Zrem {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(string, repeated, tag = "2")]
+    pub members: ::prost::alloc::vec::Vec<::prost::alloc::string::String>,
+}
+/// 从列表的左边插入，返回列表的长度
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Lpush {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(message, repeated, tag = "2")]
+    pub values: ::prost::alloc::vec::Vec<Value>,
+}
+/// 从列表的右边取出一个值
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Rpop {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 返回列表中[start, stop]之间的值，负数表示从后往前数
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Lrange {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(int64, tag = "2")]
+    pub start: i64,
+    #[prost(int64, tag = "3")]
+    pub stop: i64,
+    /// 和Zrange一样，返回的值超过max_bytes时截断；0表示不限制
+    #[prost(uint64, tag = "4")]
+    pub max_bytes: u64,
+}
+/// 从列表的右边取出一个值，列表为空时等待，直到有值或者超时
+/// timeout_ms为0表示一直等待
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Brpop {
+    #[prost(string, tag = "1")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub timeout_ms: u64,
+}
+/// 读取慢命令日志，返回最近的count条，最新的在前；count为0时返回全部
+/// reset为true时读取之后清空日志
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct SlowLog {
+    #[prost(uint32, tag = "1")]
+    pub count: u32,
+    #[prost(bool, tag = "2")]
+    pub reset: bool,
+}
+/// 一条慢命令日志
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct SlowLogEntry {
+    /// 递增的编号，清空日志之后也不会重复
+    #[prost(uint64, tag = "1")]
+    pub id: u64,
+    /// 命令开始执行的unix时间，单位毫秒
+    #[prost(uint64, tag = "2")]
+    pub timestamp: u64,
+    /// 命令执行的时间，单位微秒
+    #[prost(uint64, tag = "3")]
+    pub duration_us: u64,
+    /// 命令的类型，比如Hget
+    #[prost(string, tag = "4")]
+    pub command: ::prost::alloc::string::String,
+    #[prost(string, tag = "5")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "6")]
+    pub key: ::prost::alloc::string::String,
+}
+/// 切换当前连接使用的数据库，每个数据库的table互相独立
+/// 新的连接使用第0个数据库
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct Select {
+    #[prost(uint32, tag = "1")]
+    pub db: u32,
+}
+/// 分块上传一个binary值，offset必须等于已经上传的长度，offset为0时开始新的上传
+/// 之后的分块要带上第一个分块返回的upload_id，同一个key可以同时有多个上传，互不影响
+/// last为true时把完整的值写入存储，上传完成之前读不到这个值
+/// 返回的values中第一个是已经上传的字节数，第二个是upload_id
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct PutChunk {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub offset: u64,
+    #[prost(bytes = "bytes", tag = "4")]
+    pub data: ::prost::bytes::Bytes,
+    #[prost(bool, tag = "5")]
+    pub last: bool,
+    #[prost(uint64, tag = "6")]
+    pub upload_id: u64,
+}
+/// 分块读取一个binary值中[offset, offset + length)之间的数据
+/// 返回的values中第一个是读到的数据，第二个是值的总长度
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct GetChunk {
+    #[prost(string, tag = "1")]
+    pub table: ::prost::alloc::string::String,
+    #[prost(string, tag = "2")]
+    pub key: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "3")]
+    pub offset: u64,
+    #[prost(uint64, tag = "4")]
+    pub length: u64,
+}
+/// dump文件的头
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct DumpHeader {
+    #[prost(uint32, tag = "1")]
+    pub version: u32,
+}
+/// dump文件中一个table的头，后面跟着count个Kvpair
+/// kind为1时name是有序集合的key，后面跟着count个ScoredMember
+/// kind为2时name是列表的key，后面跟着count个Value，从列表的尾部到头部排列
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct DumpTable {
+    #[prost(string, tag = "1")]
+    pub name: ::prost::alloc::string::String,
+    #[prost(uint64, tag = "2")]
+    pub count: u64,
+    #[prost(uint32, tag = "3")]
+    pub kind: u32,
+}
+/// 集群中节点之间的raft消息
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftEnvelope {
+    #[prost(uint64, tag = "1")]
+    pub from: u64,
+    #[prost(uint64, tag = "2")]
+    pub to: u64,
+    #[prost(oneof = "raft_envelope::Msg", tags = "3, 4, 5, 6, 7, 8")]
+    pub msg: ::core::option::Option<raft_envelope::Msg>,
+}
+/// Nested message and enum types in `RaftEnvelope`.
+pub mod raft_envelope {
+    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
+    pub enum Msg {
+        #[prost(message, tag = "3")]
+        RequestVote(super::RaftRequestVote),
+        #[prost(message, tag = "4")]
+        Vote(super::RaftVote),
+        #[prost(message, tag = "5")]
+        AppendEntries(super::RaftAppendEntries),
+        #[prost(message, tag = "6")]
+        AppendResponse(super::RaftAppendResponse),
+        #[prost(message, tag = "7")]
+        InstallSnapshot(super::RaftInstallSnapshot),
+        #[prost(message, tag = "8")]
+        SnapshotResponse(super::RaftSnapshotResponse),
+    }
+}
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftRequestVote {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(uint64, tag = "2")]
+    pub last_log_index: u64,
+    #[prost(uint64, tag = "3")]
+    pub last_log_term: u64,
+}
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftVote {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(bool, tag = "2")]
+    pub granted: bool,
+}
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftAppendEntries {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(uint64, tag = "2")]
+    pub prev_log_index: u64,
+    #[prost(uint64, tag = "3")]
+    pub prev_log_term: u64,
+    #[prost(message, repeated, tag = "4")]
+    pub entries: ::prost::alloc::vec::Vec<RaftLogEntry>,
+    #[prost(uint64, tag = "5")]
+    pub leader_commit: u64,
+    #[prost(uint64, tag = "6")]
+    pub read_seq: u64,
+}
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftAppendResponse {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(bool, tag = "2")]
+    pub success: bool,
+    #[prost(uint64, tag = "3")]
+    pub match_index: u64,
+    #[prost(uint64, tag = "4")]
+    pub read_seq: u64,
+}
+/// 快照按offset分块发送，done表示最后一块
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftInstallSnapshot {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(uint64, tag = "2")]
+    pub last_index: u64,
+    #[prost(uint64, tag = "3")]
+    pub last_term: u64,
+    #[prost(message, optional, tag = "4")]
+    pub members: ::core::option::Option<RaftMembers>,
+    #[prost(uint64, tag = "5")]
+    pub offset: u64,
+    #[prost(bytes = "bytes", tag = "6")]
+    pub data: ::prost::bytes::Bytes,
+    #[prost(bool, tag = "7")]
+    pub done: bool,
+}
+/// received是已经收到的快照数据的字节数
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftSnapshotResponse {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(uint64, tag = "2")]
+    pub last_index: u64,
+    #[prost(uint64, tag = "3")]
+    pub received: u64,
+}
+/// raft日志
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftLogEntry {
+    #[prost(uint64, tag = "1")]
+    pub term: u64,
+    #[prost(uint64, tag = "2")]
+    pub index: u64,
+    #[prost(oneof = "raft_log_entry::Data", tags = "3, 4, 5, 6, 7")]
+    pub data: ::core::option::Option<raft_log_entry::Data>,
+}
+/// Nested message and enum types in `RaftLogEntry`.
+pub mod raft_log_entry {
+    #[derive(PartialOrd, Clone, PartialEq, ::prost::Oneof)]
+    pub enum Data {
+        #[prost(message, tag = "3")]
+        Members(super::RaftMembers),
+        #[prost(message, tag = "4")]
+        Noop(super::RaftNoop),
+        #[prost(message, tag = "5")]
+        Command(super::CommandRequest),
+        #[prost(uint64, tag = "6")]
+        AddNode(u64),
+        #[prost(uint64, tag = "7")]
+        RemoveNode(u64),
+    }
+}
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftMembers {
+    #[prost(uint64, repeated, tag = "1")]
+    pub ids: ::prost::alloc::vec::Vec<u64>,
 }
+#[derive(PartialOrd, Clone, PartialEq, ::prost::Message)]
+pub struct RaftNoop {}
@@ -1,4 +1,6 @@
+use bytes::Bytes;
 use http::status::StatusCode;
+use std::time::Duration;
 
 pub mod abi;
 
@@ -17,6 +19,16 @@ impl CommandRequest {
         }
     }
 
+    /// 创建HMSET命令
+    pub fn new_hmset(table: impl Into<String>, pairs: Vec<Kvpair>) -> Self {
+        Self {
+            request_data: Some(RequestData::Hmset(Hmset {
+                table: table.into(),
+                pairs,
+            })),
+        }
+    }
+
     /// 创建HGET命令
     pub fn new_hget(table: impl Into<String>, key: impl Into<String>) -> Self {
         Self {
@@ -36,6 +48,29 @@ impl CommandRequest {
         }
     }
 
+    /// 创建HSCAN命令，从start开始按key的顺序读取最多count个kv pair
+    pub fn new_hscan(table: impl Into<String>, start: impl Into<String>, count: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Hscan(Hscan {
+                table: table.into(),
+                start: start.into(),
+                count,
+                max_bytes: 0,
+            })),
+        }
+    }
+
+    /// 限制Hscan、Zrange和Lrange返回的数据大小，用来分页读取，其它命令不受影响
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        match &mut self.request_data {
+            Some(RequestData::Hscan(p)) => p.max_bytes = max_bytes,
+            Some(RequestData::Zrange(p)) => p.max_bytes = max_bytes,
+            Some(RequestData::Lrange(p)) => p.max_bytes = max_bytes,
+            _ => {}
+        }
+        self
+    }
+
     /// 创建HDEL命令
     pub fn new_hdel(table: impl Into<String>, key: impl Into<String>) -> Self {
         Self {
@@ -55,6 +90,261 @@ impl CommandRequest {
             })),
         }
     }
+
+    /// 创建WATCH命令
+    pub fn new_watch(table: impl Into<String>, key_prefix: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Watch(Watch {
+                table: table.into(),
+                key_prefix: key_prefix.into(),
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    /// 创建INFO命令
+    pub fn new_info() -> Self {
+        Self {
+            request_data: Some(RequestData::Info(Info {})),
+        }
+    }
+
+    /// 创建TABLES命令
+    pub fn new_tables() -> Self {
+        Self::new_tables_of(KeyKind::Hash)
+    }
+
+    /// 创建列出有序集合或者列表的key的TABLES命令
+    pub fn new_tables_of(kind: KeyKind) -> Self {
+        Self {
+            request_data: Some(RequestData::Tables(Tables { kind: kind as _ })),
+        }
+    }
+
+    /// 创建EVAL命令
+    pub fn new_eval(script: impl Into<String>, keys: Vec<String>, args: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Eval(Eval {
+                script: script.into(),
+                keys,
+                args,
+            })),
+        }
+    }
+
+    /// 创建ZADD命令
+    pub fn new_zadd(key: impl Into<String>, members: Vec<ScoredMember>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zadd(Zadd {
+                key: key.into(),
+                members,
+            })),
+        }
+    }
+
+    /// 创建ZRANGE命令
+    pub fn new_zrange(key: impl Into<String>, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrange(Zrange {
+                key: key.into(),
+                start,
+                stop,
+                max_bytes: 0,
+            })),
+        }
+    }
+
+    /// 创建ZRANK命令
+    pub fn new_zrank(key: impl Into<String>, member: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrank(Zrank {
+                key: key.into(),
+                member: member.into(),
+            })),
+        }
+    }
+
+    /// 创建ZREM命令
+    pub fn new_zrem(key: impl Into<String>, members: Vec<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Zrem(Zrem {
+                key: key.into(),
+                members,
+            })),
+        }
+    }
+
+    /// 创建LPUSH命令
+    pub fn new_lpush(key: impl Into<String>, values: Vec<Value>) -> Self {
+        Self {
+            request_data: Some(RequestData::Lpush(Lpush {
+                key: key.into(),
+                values,
+            })),
+        }
+    }
+
+    /// 创建RPOP命令
+    pub fn new_rpop(key: impl Into<String>) -> Self {
+        Self {
+            request_data: Some(RequestData::Rpop(Rpop { key: key.into() })),
+        }
+    }
+
+    /// 创建LRANGE命令
+    pub fn new_lrange(key: impl Into<String>, start: i64, stop: i64) -> Self {
+        Self {
+            request_data: Some(RequestData::Lrange(Lrange {
+                key: key.into(),
+                start,
+                stop,
+                max_bytes: 0,
+            })),
+        }
+    }
+
+    /// 创建BRPOP命令，timeout为0表示一直等待
+    pub fn new_brpop(key: impl Into<String>, timeout: Duration) -> Self {
+        Self {
+            request_data: Some(RequestData::Brpop(Brpop {
+                key: key.into(),
+                timeout_ms: timeout.as_millis() as _,
+            })),
+        }
+    }
+
+    /// 创建SLOWLOG命令，count为0时返回全部，reset为true时读取之后清空
+    pub fn new_slow_log(count: u32, reset: bool) -> Self {
+        Self {
+            request_data: Some(RequestData::SlowLog(SlowLog { count, reset })),
+        }
+    }
+
+    /// 创建SELECT命令
+    pub fn new_select(db: u32) -> Self {
+        Self {
+            request_data: Some(RequestData::Select(Select { db })),
+        }
+    }
+
+    /// 创建PUTCHUNK命令，last为true表示这是最后一个分块
+    /// offset为0时开始新的上传，upload_id会被忽略
+    pub fn new_put_chunk(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        upload_id: u64,
+        offset: u64,
+        data: impl Into<Bytes>,
+        last: bool,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::PutChunk(PutChunk {
+                table: table.into(),
+                key: key.into(),
+                offset,
+                data: data.into(),
+                last,
+                upload_id,
+            })),
+        }
+    }
+
+    /// 创建GETCHUNK命令
+    pub fn new_get_chunk(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        offset: u64,
+        length: u64,
+    ) -> Self {
+        Self {
+            request_data: Some(RequestData::GetChunk(GetChunk {
+                table: table.into(),
+                key: key.into(),
+                offset,
+                length,
+            })),
+        }
+    }
+}
+
+impl CommandRequest {
+    /// 命令的类型，用于日志和tracing
+    pub fn name(&self) -> &'static str {
+        match &self.request_data {
+            Some(RequestData::Hget(_)) => "Hget",
+            Some(RequestData::Hgetall(_)) => "Hgetall",
+            Some(RequestData::Hmget(_)) => "Hmget",
+            Some(RequestData::Hset(_)) => "Hset",
+            Some(RequestData::Hmset(_)) => "Hmset",
+            Some(RequestData::Hdel(_)) => "Hdel",
+            Some(RequestData::Hmdel(_)) => "Hmdel",
+            Some(RequestData::Hexist(_)) => "Hexist",
+            Some(RequestData::Hmexist(_)) => "Hmexist",
+            Some(RequestData::Watch(_)) => "Watch",
+            Some(RequestData::Info(_)) => "Info",
+            Some(RequestData::Tables(_)) => "Tables",
+            Some(RequestData::Eval(_)) => "Eval",
+            Some(RequestData::Zadd(_)) => "Zadd",
+            Some(RequestData::Zrange(_)) => "Zrange",
+            Some(RequestData::Zrank(_)) => "Zrank",
+            Some(RequestData::Zrem(_)) => "Zrem",
+            Some(RequestData::Lpush(_)) => "Lpush",
+            Some(RequestData::Rpop(_)) => "Rpop",
+            Some(RequestData::Lrange(_)) => "Lrange",
+            Some(RequestData::Brpop(_)) => "Brpop",
+            Some(RequestData::SlowLog(_)) => "SlowLog",
+            Some(RequestData::Select(_)) => "Select",
+            Some(RequestData::PutChunk(_)) => "PutChunk",
+            Some(RequestData::GetChunk(_)) => "GetChunk",
+            Some(RequestData::Hscan(_)) => "Hscan",
+            None => "None",
+        }
+    }
+
+    /// 命令操作的table和key，没有table或者操作多个key时对应的部分为空
+    /// 有序集合和列表没有table，只有key
+    pub fn target(&self) -> (&str, &str) {
+        match &self.request_data {
+            Some(RequestData::Hget(p)) => (&p.table, &p.key),
+            Some(RequestData::Hgetall(p)) => (&p.table, ""),
+            Some(RequestData::Hmget(p)) => (&p.table, ""),
+            Some(RequestData::Hset(p)) => (&p.table, p.pair.as_ref().map_or("", |p| &p.key)),
+            Some(RequestData::Hmset(p)) => (&p.table, ""),
+            Some(RequestData::Hdel(p)) => (&p.table, &p.key),
+            Some(RequestData::Hmdel(p)) => (&p.table, ""),
+            Some(RequestData::Hexist(p)) => (&p.table, &p.key),
+            Some(RequestData::Hmexist(p)) => (&p.table, ""),
+            Some(RequestData::Watch(p)) => (&p.table, &p.key_prefix),
+            Some(RequestData::Eval(p)) => ("", p.keys.first().map_or("", |k| k.as_str())),
+            Some(RequestData::Zadd(p)) => ("", &p.key),
+            Some(RequestData::Zrange(p)) => ("", &p.key),
+            Some(RequestData::Zrank(p)) => ("", &p.key),
+            Some(RequestData::Zrem(p)) => ("", &p.key),
+            Some(RequestData::Lpush(p)) => ("", &p.key),
+            Some(RequestData::Rpop(p)) => ("", &p.key),
+            Some(RequestData::Lrange(p)) => ("", &p.key),
+            Some(RequestData::Brpop(p)) => ("", &p.key),
+            Some(RequestData::PutChunk(p)) => (&p.table, &p.key),
+            Some(RequestData::GetChunk(p)) => (&p.table, &p.key),
+            Some(RequestData::Hscan(p)) => (&p.table, ""),
+            Some(RequestData::Info(_))
+            | Some(RequestData::Tables(_))
+            | Some(RequestData::SlowLog(_))
+            | Some(RequestData::Select(_))
+            | None => ("", ""),
+        }
+    }
+}
+
+impl CommandResponse {
+    /// 创建一个不带数据的成功响应
+    pub fn ok() -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            ..Default::default()
+        }
+    }
 }
 
 /// 从Value转成CommandResponse
@@ -68,6 +358,17 @@ impl From<Value> for CommandResponse {
     }
 }
 
+/// 从Vec<Value>转成CommandResponse
+impl From<Vec<Value>> for CommandResponse {
+    fn from(v: Vec<Value>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            values: v,
+            ..Default::default()
+        }
+    }
+}
+
 /// 从Vec<Kvpair>转成CommandResponse
 impl From<Vec<Kvpair>> for CommandResponse {
     fn from(v: Vec<Kvpair>) -> Self {
@@ -79,19 +380,52 @@ impl From<Vec<Kvpair>> for CommandResponse {
     }
 }
 
+/// 从WatchEvent转成CommandResponse
+impl From<WatchEvent> for CommandResponse {
+    fn from(e: WatchEvent) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            events: vec![e],
+            ..Default::default()
+        }
+    }
+}
+
+/// 从Vec<SlowLogEntry>转成CommandResponse
+impl From<Vec<SlowLogEntry>> for CommandResponse {
+    fn from(v: Vec<SlowLogEntry>) -> Self {
+        Self {
+            status: StatusCode::OK.as_u16() as _,
+            slow_logs: v,
+            ..Default::default()
+        }
+    }
+}
+
 /// 从KvError转成CommandResponse
 impl From<KvError> for CommandResponse {
     fn from(e: KvError) -> Self {
         let mut result = Self {
             status: StatusCode::INTERNAL_SERVER_ERROR.as_u16() as _,
             message: e.to_string(),
-            values: vec![],
-            pairs: vec![],
+            ..Default::default()
         };
 
         match e {
             KvError::NotFound(_, _) => result.status = StatusCode::NOT_FOUND.as_u16() as _,
             KvError::InvalidCommand(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::TooManyRequests(_) => {
+                result.status = StatusCode::TOO_MANY_REQUESTS.as_u16() as _
+            }
+            KvError::ScriptError(_) => result.status = StatusCode::BAD_REQUEST.as_u16() as _,
+            KvError::NotLeader(_) => result.status = StatusCode::MISDIRECTED_REQUEST.as_u16() as _,
+            KvError::ConfChangeInProgress => result.status = StatusCode::CONFLICT.as_u16() as _,
+            KvError::QuotaExceeded(_) => {
+                result.status = StatusCode::INSUFFICIENT_STORAGE.as_u16() as _
+            }
+            KvError::PayloadTooLarge(_) => {
+                result.status = StatusCode::PAYLOAD_TOO_LARGE.as_u16() as _
+            }
             _ => {}
         }
 
@@ -99,6 +433,36 @@ impl From<KvError> for CommandResponse {
     }
 }
 
+/// Tables命令和dump文件中key的类型，在protobuf中是uint32
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeyKind {
+    Hash = 0,
+    Zset = 1,
+    List = 2,
+}
+
+impl TryFrom<u32> for KeyKind {
+    type Error = KvError;
+
+    fn try_from(v: u32) -> Result<Self, Self::Error> {
+        match v {
+            0 => Ok(Self::Hash),
+            1 => Ok(Self::Zset),
+            2 => Ok(Self::List),
+            v => Err(KvError::InvalidCommand(format!("unknown key kind {}", v))),
+        }
+    }
+}
+
+impl ScoredMember {
+    pub fn new(member: impl Into<String>, score: f64) -> Self {
+        Self {
+            member: member.into(),
+            score,
+        }
+    }
+}
+
 impl Kvpair {
     /// 创建一个kv pair
     pub fn new(key: impl Into<String>, value: Value) -> Self {
@@ -109,6 +473,25 @@ impl Kvpair {
     }
 }
 
+impl WatchEvent {
+    /// 创建一个key变化的事件
+    pub fn new(
+        table: impl Into<String>,
+        key: impl Into<String>,
+        op: impl Into<String>,
+        old_value: Option<Value>,
+        new_value: Option<Value>,
+    ) -> Self {
+        Self {
+            table: table.into(),
+            key: key.into(),
+            op: op.into(),
+            old_value,
+            new_value,
+        }
+    }
+}
+
 /// 从String转成Value
 impl From<String> for Value {
     fn from(s: String) -> Self {
@@ -136,6 +519,15 @@ impl From<i64> for Value {
     }
 }
 
+/// 从f64转成Value
+impl From<f64> for Value {
+    fn from(f: f64) -> Self {
+        Self {
+            value: Some(value::Value::Float(f)),
+        }
+    }
+}
+
 /// 从bool转成Value
 impl From<bool> for Value {
     fn from(b: bool) -> Self {
@@ -144,3 +536,28 @@ impl From<bool> for Value {
         }
     }
 }
+
+/// 从Bytes转成Value
+impl From<Bytes> for Value {
+    fn from(b: Bytes) -> Self {
+        Self {
+            value: Some(value::Value::Binary(b)),
+        }
+    }
+}
+
+impl Value {
+    /// Value占用的内存大小，包括String和Binary在堆上的数据
+    pub fn size(&self) -> usize {
+        std::mem::size_of::<Self>() + self.data_len()
+    }
+
+    /// String和Binary的长度，其它类型为0
+    pub fn data_len(&self) -> usize {
+        match &self.value {
+            Some(value::Value::String(s)) => s.len(),
+            Some(value::Value::Binary(b)) => b.len(),
+            _ => 0,
+        }
+    }
+}
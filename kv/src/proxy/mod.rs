@@ -0,0 +1,639 @@
+use crate::command_request::RequestData;
+use crate::*;
+use futures::future::join_all;
+use std::{collections::HashMap, future::Future, sync::Arc};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::{watch, Mutex, RwLock},
+};
+use tracing::{info, warn};
+
+mod ring;
+
+pub use ring::HashRing;
+
+/// 数据按什么分片
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sharding {
+    /// 同一个table的数据在同一个节点上，Hgetall不需要访问所有节点
+    #[default]
+    Table,
+    /// 按table和key分片，大的table也能分散到多个节点上
+    TableKey,
+}
+
+/// 代理的配置
+#[derive(Clone, Debug)]
+pub struct ProxyConfig {
+    /// 监听的地址
+    pub addr: String,
+    /// 后端kv节点的地址
+    pub nodes: Vec<String>,
+    pub sharding: Sharding,
+    /// 每个节点在哈希环上的虚拟节点数
+    pub virtual_nodes: usize,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        Self {
+            addr: "127.0.0.1:9526".into(),
+            nodes: Vec::new(),
+            sharding: Sharding::default(),
+            virtual_nodes: 160,
+        }
+    }
+}
+
+/// 分片代理，使用和kv服务器相同的协议，根据一致性哈希把命令转发给后端的节点
+#[derive(Clone)]
+pub struct KvProxy {
+    inner: Arc<ProxyInner>,
+}
+
+struct ProxyInner {
+    addr: String,
+    sharding: Sharding,
+    // 增加节点迁移数据时持有写锁，迁移完成之前的请求都会等待
+    nodes: RwLock<Nodes>,
+}
+
+struct Nodes {
+    ring: HashRing,
+    backends: HashMap<String, Arc<Backend>>,
+}
+
+/// 到后端节点的连接，出错之后下一次请求时重新连接
+struct Backend {
+    addr: String,
+    client: Mutex<Option<KvClient>>,
+}
+
+impl Backend {
+    fn new(addr: String) -> Self {
+        Self {
+            addr,
+            client: Mutex::new(None),
+        }
+    }
+
+    async fn execute(&self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let mut client = self.client.lock().await;
+        if client.is_none() {
+            *client = Some(KvClient::connect(&self.addr).await?);
+        }
+        let res = client.as_mut().unwrap().execute(cmd).await;
+        if res.is_err() {
+            *client = None;
+        }
+        res
+    }
+
+    /// 执行命令，非200的响应当作错误
+    async fn execute_ok(&self, cmd: &CommandRequest) -> Result<CommandResponse, KvError> {
+        let res = self.execute(cmd).await?;
+        match res.status {
+            200 => Ok(res),
+            status => Err(KvError::Internal(format!(
+                "node {} returned {}: {}",
+                self.addr, status, res.message
+            ))),
+        }
+    }
+}
+
+impl Nodes {
+    fn owner(&self, sharding: Sharding, table: &str, key: &str) -> Result<&Arc<Backend>, KvError> {
+        self.ring
+            .get(&shard_key(sharding, table, key))
+            .and_then(|addr| self.backends.get(addr))
+            .ok_or_else(|| KvError::Internal("no backend nodes".into()))
+    }
+}
+
+/// 列出节点上某种类型所有的key
+async fn keys_of(backend: &Backend, kind: KeyKind) -> Result<Vec<String>, KvError> {
+    let res = backend
+        .execute_ok(&CommandRequest::new_tables_of(kind))
+        .await?;
+    res.values
+        .into_iter()
+        .map(|v| match v.value {
+            Some(value::Value::String(s)) => Ok(s),
+            _ => Err(KvError::ConvertError(v, "String")),
+        })
+        .collect()
+}
+
+fn shard_key(sharding: Sharding, table: &str, key: &str) -> Vec<u8> {
+    let mut buf = table.as_bytes().to_vec();
+    if sharding == Sharding::TableKey {
+        buf.push(0);
+        buf.extend_from_slice(key.as_bytes());
+    }
+    buf
+}
+
+/// 把命令发给所有节点，按照backends的顺序返回响应，任何一个节点出错都返回错误
+async fn fan_out(nodes: &Nodes, cmd: &CommandRequest) -> Result<Vec<CommandResponse>, KvError> {
+    join_all(nodes.backends.values().map(|b| b.execute_ok(cmd)))
+        .await
+        .into_iter()
+        .collect()
+}
+
+/// 合并所有节点的Hscan结果。每个节点返回的都可能被截断，
+/// 只保留不超过各节点最后一个key的部分，剩下的留给下一页
+fn merge_hscan(pages: Vec<CommandResponse>, param: &Hscan) -> CommandResponse {
+    let end = pages
+        .iter()
+        .filter_map(|res| res.pairs.last())
+        .map(|p| p.key.clone())
+        .min();
+    let mut pairs: Vec<_> = pages
+        .into_iter()
+        .flat_map(|res| res.pairs)
+        .filter(|p| end.as_ref().is_some_and(|end| p.key <= *end))
+        .collect();
+    pairs.sort_by(|a, b| a.key.cmp(&b.key));
+    if param.count > 0 {
+        pairs.truncate(param.count as usize);
+    }
+    truncate_by_bytes(&mut pairs, param.max_bytes);
+    pairs.into()
+}
+
+impl KvProxy {
+    pub fn new(config: ProxyConfig) -> Self {
+        let mut ring = HashRing::new(config.virtual_nodes);
+        let mut backends = HashMap::new();
+        for addr in config.nodes {
+            ring.add(&addr);
+            backends.insert(addr.clone(), Arc::new(Backend::new(addr)));
+        }
+        Self {
+            inner: Arc::new(ProxyInner {
+                addr: config.addr,
+                sharding: config.sharding,
+                nodes: RwLock::new(Nodes { ring, backends }),
+            }),
+        }
+    }
+
+    /// 执行命令，后端出错时返回错误的响应
+    pub async fn execute(&self, cmd: CommandRequest) -> CommandResponse {
+        let nodes = self.inner.nodes.read().await;
+        self.route(&nodes, cmd).await.unwrap_or_else(|e| e.into())
+    }
+
+    async fn route(&self, nodes: &Nodes, cmd: CommandRequest) -> Result<CommandResponse, KvError> {
+        let sharding = self.inner.sharding;
+        let owner = |table: &str, key: &str| nodes.owner(sharding, table, key);
+        match &cmd.request_data {
+            Some(RequestData::Hget(p)) => owner(&p.table, &p.key)?.execute(&cmd).await,
+            Some(RequestData::Hdel(p)) => owner(&p.table, &p.key)?.execute(&cmd).await,
+            Some(RequestData::Hexist(p)) => owner(&p.table, &p.key)?.execute(&cmd).await,
+            Some(RequestData::PutChunk(p)) => owner(&p.table, &p.key)?.execute(&cmd).await,
+            Some(RequestData::GetChunk(p)) => owner(&p.table, &p.key)?.execute(&cmd).await,
+            Some(RequestData::Hset(p)) => {
+                let key = p.pair.as_ref().map(|p| p.key.as_str()).unwrap_or_default();
+                owner(&p.table, key)?.execute(&cmd).await
+            }
+            Some(RequestData::Hgetall(p)) if sharding == Sharding::Table => {
+                owner(&p.table, "")?.execute(&cmd).await
+            }
+            Some(RequestData::Hscan(p)) if sharding == Sharding::Table => {
+                owner(&p.table, "")?.execute(&cmd).await
+            }
+            Some(RequestData::Hscan(p)) => Ok(merge_hscan(fan_out(nodes, &cmd).await?, p)),
+            Some(RequestData::Hmset(p)) if sharding == Sharding::Table => {
+                owner(&p.table, "")?.execute(&cmd).await
+            }
+            Some(RequestData::Hgetall(_)) => {
+                let mut merged = CommandResponse::ok();
+                for res in fan_out(nodes, &cmd).await? {
+                    merged.pairs.extend(res.pairs);
+                }
+                Ok(merged)
+            }
+            Some(RequestData::Hmset(p)) => self.split_hmset(nodes, p).await,
+            Some(RequestData::Tables(_)) => {
+                let mut tables: Vec<_> = fan_out(nodes, &cmd)
+                    .await?
+                    .into_iter()
+                    .flat_map(|res| res.values)
+                    .collect();
+                tables.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                tables.dedup();
+                Ok(tables.into())
+            }
+            // 每个节点的信息加上节点地址作为前缀
+            Some(RequestData::Info(_)) => {
+                let mut pairs = Vec::new();
+                for (addr, res) in nodes.backends.keys().zip(fan_out(nodes, &cmd).await?) {
+                    pairs.extend(res.pairs.into_iter().map(|p| {
+                        Kvpair::new(format!("{}/{}", addr, p.key), p.value.unwrap_or_default())
+                    }));
+                }
+                pairs.sort_by(|a, b| a.key.cmp(&b.key));
+                Ok(pairs.into())
+            }
+            // 有序集合和列表按key分片
+            Some(RequestData::Zadd(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Zrange(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Zrank(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Zrem(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Lpush(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Rpop(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Lrange(p)) => owner(&p.key, "")?.execute(&cmd).await,
+            Some(RequestData::Watch(_)) | Some(RequestData::Brpop(_)) => Err(
+                KvError::InvalidCommand("streaming commands are not supported by proxy".into()),
+            ),
+            // 后端的连接是所有客户端共享的，不能切换数据库
+            Some(RequestData::Select(_)) => Err(KvError::InvalidCommand(
+                "Select is not supported by proxy".into(),
+            )),
+            None => Err(KvError::InvalidCommand("Request has no data".into())),
+            _ => Err(KvError::Internal("Not implemented".into())),
+        }
+    }
+
+    /// 按key把Hmset拆分到不同的节点，返回的旧值保持原来的顺序
+    async fn split_hmset(&self, nodes: &Nodes, param: &Hmset) -> Result<CommandResponse, KvError> {
+        let mut groups: HashMap<String, (Arc<Backend>, Vec<usize>)> = HashMap::new();
+        for (i, pair) in param.pairs.iter().enumerate() {
+            let backend = nodes.owner(self.inner.sharding, &param.table, &pair.key)?;
+            groups
+                .entry(backend.addr.clone())
+                .or_insert_with(|| (Arc::clone(backend), Vec::new()))
+                .1
+                .push(i);
+        }
+
+        let requests = groups.into_values().map(|(backend, indexes)| async move {
+            let pairs = indexes.iter().map(|i| param.pairs[*i].clone()).collect();
+            let cmd = CommandRequest::new_hmset(param.table.as_str(), pairs);
+            backend.execute_ok(&cmd).await.map(|res| (indexes, res))
+        });
+        let mut values = vec![Value::default(); param.pairs.len()];
+        for res in join_all(requests).await {
+            let (indexes, res) = res?;
+            for (i, v) in indexes.into_iter().zip(res.values) {
+                values[i] = v;
+            }
+        }
+        Ok(values.into())
+    }
+
+    /// 增加一个节点，把哈希环上属于它的数据从其它节点迁移过来，
+    /// 返回迁移的kv pair、有序集合成员和列表中值的数量
+    pub async fn add_node(&self, addr: impl Into<String>) -> Result<usize, KvError> {
+        let addr = addr.into();
+        let mut nodes = self.inner.nodes.write().await;
+        if nodes.backends.contains_key(&addr) {
+            return Err(KvError::InvalidCommand(format!(
+                "node {} already exists",
+                addr
+            )));
+        }
+
+        let backend = Arc::new(Backend::new(addr.clone()));
+        let mut ring = nodes.ring.clone();
+        ring.add(&addr);
+
+        let moves = |table: &str, key: &str| {
+            ring.get(&shard_key(self.inner.sharding, table, key)) == Some(addr.as_str())
+        };
+        // 先写入新节点再从旧节点删除，中途失败不会丢数据
+        let mut moved = 0;
+        for old in nodes.backends.values() {
+            for table in keys_of(old, KeyKind::Hash).await? {
+                let res = old.execute_ok(&CommandRequest::new_hgetall(&table)).await?;
+                let pairs: Vec<_> = res
+                    .pairs
+                    .into_iter()
+                    .filter(|p| moves(&table, &p.key))
+                    .collect();
+                if pairs.is_empty() {
+                    continue;
+                }
+
+                let keys: Vec<_> = pairs.iter().map(|p| p.key.clone()).collect();
+                backend
+                    .execute_ok(&CommandRequest::new_hmset(table.as_str(), pairs))
+                    .await?;
+                for key in keys {
+                    old.execute_ok(&CommandRequest::new_hdel(&table, key))
+                        .await?;
+                    moved += 1;
+                }
+            }
+
+            // 有序集合和列表按key分片，整个迁移
+            for key in keys_of(old, KeyKind::Zset).await? {
+                if !moves(&key, "") {
+                    continue;
+                }
+                let res = old
+                    .execute_ok(&CommandRequest::new_zrange(&key, 0, -1))
+                    .await?;
+                let members = res
+                    .pairs
+                    .into_iter()
+                    .map(|p| match p.value.and_then(|v| v.value) {
+                        Some(value::Value::Float(score)) => Ok(ScoredMember::new(p.key, score)),
+                        v => Err(KvError::ConvertError(Value { value: v }, "Float")),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let names: Vec<_> = members.iter().map(|m| m.member.clone()).collect();
+                moved += names.len();
+                backend
+                    .execute_ok(&CommandRequest::new_zadd(&key, members))
+                    .await?;
+                old.execute_ok(&CommandRequest::new_zrem(&key, names))
+                    .await?;
+            }
+            for key in keys_of(old, KeyKind::List).await? {
+                if !moves(&key, "") {
+                    continue;
+                }
+                let res = old
+                    .execute_ok(&CommandRequest::new_lrange(&key, 0, -1))
+                    .await?;
+                let len = res.values.len();
+                moved += len;
+                // Lpush依次插入到头部，倒过来插入才能保持原来的顺序
+                let values = res.values.into_iter().rev().collect();
+                backend
+                    .execute_ok(&CommandRequest::new_lpush(&key, values))
+                    .await?;
+                for _ in 0..len {
+                    old.execute_ok(&CommandRequest::new_rpop(&key)).await?;
+                }
+            }
+        }
+
+        info!("Node {} added, {} items migrated", addr, moved);
+        nodes.ring = ring;
+        nodes.backends.insert(addr, backend);
+        Ok(moved)
+    }
+
+    /// 监听配置的地址，一直运行到收到ctrl-c或者SIGTERM
+    pub async fn run(&self) -> Result<(), KvError> {
+        let listener = TcpListener::bind(&self.inner.addr).await?;
+        info!("Proxy listening on {}", self.inner.addr);
+        self.serve(listener, shutdown_signal()).await
+    }
+
+    /// 在listener上处理连接，shutdown完成后关闭所有连接
+    pub async fn serve(
+        &self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), KvError> {
+        let (notify_tx, notify_rx) = watch::channel(false);
+        tokio::pin!(shutdown);
+        loop {
+            let (stream, addr) = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok(v) => v,
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+            info!("Client: {:?} connected", addr);
+
+            let proxy = self.clone();
+            let shutdown = notify_rx.clone();
+            tokio::spawn(async move {
+                if let Err(e) = proxy.handle(stream, shutdown).await {
+                    warn!("Client {:?} disconnected with error: {}", addr, e);
+                }
+            });
+        }
+
+        notify_tx.send(true).ok();
+        info!("Proxy stopped");
+        Ok(())
+    }
+
+    async fn handle(
+        &self,
+        stream: TcpStream,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<(), KvError> {
+        let mut stream = ServerStream::new(stream);
+        loop {
+            let cmd = tokio::select! {
+                cmd = stream.recv() => match cmd {
+                    Some(cmd) => cmd?,
+                    None => return Ok(()),
+                },
+                _ = shutdown.changed() => return Ok(()),
+            };
+            let res = self.execute(cmd).await;
+            stream.send(&res).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn start_node() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server: KvServer =
+            KvServer::new(ServerConfig::default(), Service::new(MemTable::new()));
+        tokio::spawn(async move { server.serve(listener, std::future::pending::<()>()).await });
+        addr
+    }
+
+    async fn start_proxy(n: usize, sharding: Sharding) -> (KvProxy, Vec<String>) {
+        let mut nodes = Vec::new();
+        for _ in 0..n {
+            nodes.push(start_node().await);
+        }
+        let config = ProxyConfig {
+            nodes: nodes.clone(),
+            sharding,
+            ..Default::default()
+        };
+        (KvProxy::new(config), nodes)
+    }
+
+    // 直接从节点上读取table的所有数据
+    async fn node_pairs(addr: &str, table: &str) -> Vec<Kvpair> {
+        let mut client = KvClient::connect(addr).await.unwrap();
+        let res = client
+            .execute(&CommandRequest::new_hgetall(table))
+            .await
+            .unwrap();
+        res.pairs
+    }
+
+    #[tokio::test]
+    async fn proxy_should_route_by_table() {
+        let (proxy, nodes) = start_proxy(3, Sharding::Table).await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let p = proxy.clone();
+        tokio::spawn(async move { p.serve(listener, std::future::pending()).await });
+
+        // 客户端通过代理读写，和直接访问kv服务器一样
+        let mut client = KvClient::connect(addr).await.unwrap();
+        for i in 0..10i64 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), i.into());
+            let res = client.execute(&cmd).await.unwrap();
+            assert_res_ok(res, &[Value::default()], &[]);
+        }
+        let res = client
+            .execute(&CommandRequest::new_hget("t1", "k3"))
+            .await
+            .unwrap();
+        assert_res_ok(res, &[3.into()], &[]);
+
+        // 同一个table的数据都在一个节点上
+        let mut counts = Vec::new();
+        for node in &nodes {
+            counts.push(node_pairs(node, "t1").await.len());
+        }
+        counts.sort_unstable();
+        assert_eq!(counts, [0, 0, 10]);
+    }
+
+    #[tokio::test]
+    async fn proxy_should_fan_out_and_merge_by_key() {
+        let (proxy, nodes) = start_proxy(3, Sharding::TableKey).await;
+        let pairs: Vec<_> = (0..30i64)
+            .map(|i| Kvpair::new(format!("k{}", i), i.into()))
+            .collect();
+        let res = proxy
+            .execute(CommandRequest::new_hmset("t1", pairs.clone()))
+            .await;
+        assert_res_ok(res, &vec![Value::default(); 30], &[]);
+        proxy
+            .execute(CommandRequest::new_hset("t2", "k1", "v1".into()))
+            .await;
+
+        // 旧值按照请求中的顺序返回
+        let mut update = pairs[..3].to_vec();
+        update.reverse();
+        let res = proxy.execute(CommandRequest::new_hmset("t1", update)).await;
+        assert_res_ok(res, &[2.into(), 1.into(), 0.into()], &[]);
+
+        let mut expected = pairs.clone();
+        expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let res = proxy.execute(CommandRequest::new_hgetall("t1")).await;
+        assert_res_ok(res, &[], &expected);
+
+        // 分页读取时每页都按key排序，合起来正好是所有的kv pair
+        let mut scanned = Vec::new();
+        loop {
+            let start = scanned
+                .last()
+                .map_or(String::new(), |p: &Kvpair| p.key.clone() + "\0");
+            let res = proxy
+                .execute(CommandRequest::new_hscan("t1", start, 7))
+                .await;
+            assert!(res.pairs.len() <= 7);
+            if res.pairs.is_empty() {
+                break;
+            }
+            scanned.extend(res.pairs);
+        }
+        let mut sorted = pairs.clone();
+        sorted.sort_by(|a, b| a.key.cmp(&b.key));
+        assert_eq!(scanned, sorted);
+
+        let res = proxy.execute(CommandRequest::new_tables()).await;
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+
+        // 数据分散在所有的节点上
+        for node in &nodes {
+            assert!(!node_pairs(node, "t1").await.is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn add_node_should_migrate_keys() {
+        let (proxy, nodes) = start_proxy(2, Sharding::TableKey).await;
+        for i in 0..100i64 {
+            let cmd = CommandRequest::new_hset("t1", format!("k{}", i), i.into());
+            proxy.execute(cmd).await;
+        }
+        for i in 0..20i64 {
+            let members = vec![ScoredMember::new("m1", 1.0), ScoredMember::new("m2", 2.0)];
+            proxy
+                .execute(CommandRequest::new_zadd(format!("z{}", i), members))
+                .await;
+            let values = vec!["c".into(), "b".into(), "a".into()];
+            proxy
+                .execute(CommandRequest::new_lpush(format!("l{}", i), values))
+                .await;
+        }
+
+        let new = start_node().await;
+        let moved = proxy.add_node(&new).await.unwrap();
+        let pairs = node_pairs(&new, "t1").await.len();
+        assert!(pairs > 0);
+        let mut remaining = 0;
+        for node in &nodes {
+            remaining += node_pairs(node, "t1").await.len();
+        }
+        assert_eq!(remaining + pairs, 100);
+
+        // 有序集合和列表整个迁移，每个key只在一个节点上
+        let mut all = nodes.clone();
+        all.push(new.clone());
+        for kind in [KeyKind::Zset, KeyKind::List] {
+            let mut keys = Vec::new();
+            for node in &all {
+                let mut client = KvClient::connect(node).await.unwrap();
+                let res = client
+                    .execute(&CommandRequest::new_tables_of(kind))
+                    .await
+                    .unwrap();
+                keys.extend(res.values);
+            }
+            assert_eq!(keys.len(), 20);
+        }
+        let mut client = KvClient::connect(&new).await.unwrap();
+        let res = client
+            .execute(&CommandRequest::new_tables_of(KeyKind::Zset))
+            .await
+            .unwrap();
+        let zsets = res.values.len();
+        let res = client
+            .execute(&CommandRequest::new_tables_of(KeyKind::List))
+            .await
+            .unwrap();
+        let lists = res.values.len();
+        assert!(zsets > 0 && lists > 0);
+        assert_eq!(moved, pairs + zsets * 2 + lists * 3);
+        for i in 0..20i64 {
+            let res = proxy
+                .execute(CommandRequest::new_zrange(format!("z{}", i), 0, -1))
+                .await;
+            let pairs = vec![Kvpair::new("m1", 1.0.into()), Kvpair::new("m2", 2.0.into())];
+            assert_res_ok(res, &[], &pairs);
+            let res = proxy
+                .execute(CommandRequest::new_lrange(format!("l{}", i), 0, -1))
+                .await;
+            assert_res_ok(res, &["a".into(), "b".into(), "c".into()], &[]);
+        }
+
+        for i in 0..100i64 {
+            let res = proxy
+                .execute(CommandRequest::new_hget("t1", format!("k{}", i)))
+                .await;
+            assert_res_ok(res, &[i.into()], &[]);
+        }
+        assert!(proxy.add_node(&new).await.is_err());
+    }
+}
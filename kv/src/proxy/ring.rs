@@ -0,0 +1,103 @@
+use std::collections::BTreeMap;
+
+/// 一致性哈希环，每个节点在环上有多个虚拟节点，让数据分布得更均匀
+#[derive(Clone, Debug, Default)]
+pub struct HashRing {
+    virtual_nodes: usize,
+    ring: BTreeMap<u64, String>,
+}
+
+impl HashRing {
+    pub fn new(virtual_nodes: usize) -> Self {
+        Self {
+            virtual_nodes: virtual_nodes.max(1),
+            ring: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, node: &str) {
+        for i in 0..self.virtual_nodes {
+            self.ring
+                .insert(hash(format!("{}#{}", node, i).as_bytes()), node.into());
+        }
+    }
+
+    pub fn remove(&mut self, node: &str) {
+        self.ring.retain(|_, n| n != node);
+    }
+
+    /// 找到key所在的节点：环上顺时针方向的第一个虚拟节点
+    pub fn get(&self, key: &[u8]) -> Option<&str> {
+        let h = hash(key);
+        self.ring
+            .range(h..)
+            .next()
+            .or_else(|| self.ring.iter().next())
+            .map(|(_, node)| node.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring.is_empty()
+    }
+}
+
+// FNV-1a，不依赖标准库哈希算法的实现，重启之后key仍然落在同一个节点上
+fn hash(data: &[u8]) -> u64 {
+    const OFFSET: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+    let h = data
+        .iter()
+        .fold(OFFSET, |h, b| (h ^ *b as u64).wrapping_mul(PRIME));
+    // FNV的低位分布不好，再做一次混合
+    let h = (h ^ (h >> 33)).wrapping_mul(0xff51afd7ed558ccd);
+    h ^ (h >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn ring_should_distribute_keys() {
+        let mut ring = HashRing::new(160);
+        assert_eq!(ring.get(b"k1"), None);
+        for node in ["n1", "n2", "n3"] {
+            ring.add(node);
+        }
+
+        let mut counts = HashMap::new();
+        for i in 0..3000 {
+            let node = ring.get(format!("k{}", i).as_bytes()).unwrap();
+            *counts.entry(node.to_string()).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 3);
+        assert!(counts.values().all(|c| *c > 600), "{:?}", counts);
+    }
+
+    #[test]
+    fn adding_node_should_only_move_keys_to_it() {
+        let mut ring = HashRing::new(160);
+        ring.add("n1");
+        ring.add("n2");
+        let before: Vec<_> = (0..1000)
+            .map(|i| ring.get(format!("k{}", i).as_bytes()).unwrap().to_string())
+            .collect();
+
+        ring.add("n3");
+        let mut moved = 0;
+        for (i, old) in before.iter().enumerate() {
+            let new = ring.get(format!("k{}", i).as_bytes()).unwrap();
+            if new != old {
+                assert_eq!(new, "n3");
+                moved += 1;
+            }
+        }
+        assert!(moved > 0 && moved < 600, "moved {}", moved);
+
+        ring.remove("n3");
+        for (i, old) in before.iter().enumerate() {
+            assert_eq!(ring.get(format!("k{}", i).as_bytes()).unwrap(), old);
+        }
+    }
+}
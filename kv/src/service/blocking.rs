@@ -0,0 +1,162 @@
+use crate::*;
+use dashmap::DashMap;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, Notify},
+    time::{self, Instant},
+};
+
+/// 在列表上等待的Brpop，Lpush之后唤醒它们
+#[derive(Debug, Default)]
+pub struct ListWaiters {
+    notifies: DashMap<String, Arc<Notify>>,
+}
+
+impl ListWaiters {
+    /// 唤醒在key上等待的Brpop
+    pub(crate) fn notify(&self, key: &str) {
+        if let Some(notify) = self.notifies.get(key) {
+            notify.notify_waiters();
+        }
+    }
+
+    fn register(&self, key: &str) -> Arc<Notify> {
+        Arc::clone(&self.notifies.entry(key.into()).or_default())
+    }
+
+    // 没有其它等待者时删除key对应的Notify
+    fn release(&self, key: &str, notify: Arc<Notify>) {
+        drop(notify);
+        self.notifies
+            .remove_if(key, |_, n| Arc::strong_count(n) == 1);
+    }
+}
+
+impl<Store: Storage + Send + Sync + 'static> Service<Store> {
+    /// 执行Brpop，列表为空时在后台等待，取到值、超时或者客户端断开时结束
+    pub(crate) fn blocking_pop(&self, param: Brpop) -> StreamingResponse {
+        let (tx, rx) = mpsc::channel(1);
+        let service = self.clone();
+        tokio::spawn(async move {
+            if let Some(res) = service.pop_or_wait(&param, &tx).await {
+                // 取出值之后客户端断开了，把值放回列表的右边
+                if let Err(mpsc::error::SendError(res)) = tx.send(res).await {
+                    if res.status == 200 {
+                        service.db().store.rpush(&param.key, res.values).ok();
+                        service.db().lists.notify(&param.key);
+                    }
+                }
+            }
+        });
+        rx
+    }
+
+    async fn pop_or_wait(
+        &self,
+        param: &Brpop,
+        tx: &mpsc::Sender<CommandResponse>,
+    ) -> Option<CommandResponse> {
+        let notify = self.db().lists.register(&param.key);
+        let deadline = match param.timeout_ms {
+            0 => None,
+            ms => Some(Instant::now() + Duration::from_millis(ms)),
+        };
+
+        let res = loop {
+            // 先注册再检查列表，不会错过检查之后的Lpush
+            let notified = notify.notified();
+            tokio::pin!(notified);
+            notified.as_mut().enable();
+
+            let popped = {
+                let _guard = self.inner.lock.read().unwrap();
+                self.db().store.rpop(&param.key)
+            };
+            match popped {
+                Ok(Some(v)) => break Some(v.into()),
+                Ok(None) => {}
+                Err(e) => break Some(e.into()),
+            }
+
+            let timeout = async {
+                match deadline {
+                    Some(deadline) => time::sleep_until(deadline).await,
+                    None => std::future::pending().await,
+                }
+            };
+            tokio::select! {
+                _ = notified => {}
+                _ = timeout => break Some(KvError::NotFound(param.key.clone(), "".into()).into()),
+                _ = tx.closed() => break None,
+            }
+        };
+
+        self.db().lists.release(&param.key, notify);
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn brpop_should_wait_for_lpush() {
+        let service: Service = Service::new(MemTable::new());
+        let mut rx = service.execute_streaming(CommandRequest::new_brpop("q", Duration::ZERO));
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+
+        service.execute(CommandRequest::new_lpush("q", vec!["job1".into()]));
+        let res = time::timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_res_ok(res, &["job1".into()], &[]);
+        assert!(rx.recv().await.is_none());
+        assert!(service.db().lists.notifies.is_empty());
+    }
+
+    #[tokio::test]
+    async fn brpop_should_return_immediately_if_list_not_empty() {
+        let service: Service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_lpush("q", vec!["a".into(), "b".into()]));
+        let mut rx = service.execute_streaming(CommandRequest::new_brpop("q", Duration::ZERO));
+        assert_res_ok(rx.recv().await.unwrap(), &["a".into()], &[]);
+    }
+
+    #[tokio::test]
+    async fn brpop_should_timeout() {
+        let service: Service = Service::new(MemTable::new());
+        let cmd = CommandRequest::new_brpop("q", Duration::from_millis(20));
+        let mut rx = service.execute_streaming(cmd);
+        assert_res_error(rx.recv().await.unwrap(), 404, "Not found");
+    }
+
+    #[tokio::test]
+    async fn each_value_should_be_popped_once() {
+        let service: Service = Service::new(MemTable::new());
+        let mut receivers: Vec<_> = (0..3)
+            .map(|_| service.execute_streaming(CommandRequest::new_brpop("q", Duration::ZERO)))
+            .collect();
+        tokio::task::yield_now().await;
+
+        // 一次lpush两个值，只有两个等待者能取到
+        service.execute(CommandRequest::new_lpush("q", vec!["a".into(), "b".into()]));
+        let mut values = Vec::new();
+        for rx in receivers.iter_mut() {
+            if let Ok(res) = time::timeout(Duration::from_millis(50), rx.recv()).await {
+                values.push(res.unwrap().values.remove(0));
+            }
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(values, ["a".into(), "b".into()]);
+
+        // 断开的等待者不会取走值
+        drop(receivers);
+        tokio::task::yield_now().await;
+        service.execute(CommandRequest::new_lpush("q", vec!["c".into()]));
+        let res = service.execute(CommandRequest::new_lrange("q", 0, -1));
+        assert_res_ok(res, &["c".into()], &[]);
+    }
+}
@@ -0,0 +1,350 @@
+use crate::*;
+use bytes::{Bytes, BytesMut};
+use dashmap::DashMap;
+use std::{
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+    time::{Duration, Instant},
+};
+
+/// 超过这个时间没有收到新分块的上传会被丢弃
+const UPLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+/// 服务器定期清理超时上传的间隔
+pub(crate) const UPLOAD_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// 正在进行的分块上传，上传完成之前数据不会写入存储
+/// 所有数据库的上传放在一起，用第一个分块返回的upload_id区分
+#[derive(Debug, Default)]
+pub struct Uploads {
+    uploads: DashMap<u64, Upload>,
+    next_id: AtomicU64,
+    // 所有上传缓存的字节数
+    buffered: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct Upload {
+    db: usize,
+    table: String,
+    key: String,
+    data: BytesMut,
+    updated: Instant,
+}
+
+impl Uploads {
+    /// 丢弃超时的上传
+    pub fn sweep(&self) {
+        let expired: Vec<_> = self
+            .uploads
+            .iter()
+            .filter(|u| u.updated.elapsed() >= UPLOAD_TIMEOUT)
+            .map(|u| *u.key())
+            .collect();
+        for id in expired {
+            // 收集之后可能又收到了新的分块，删除时再检查一次
+            if let Some((_, upload)) = self
+                .uploads
+                .remove_if(&id, |_, u| u.updated.elapsed() >= UPLOAD_TIMEOUT)
+            {
+                self.release(upload.data.len());
+            }
+        }
+    }
+
+    /// 所有上传缓存的字节数
+    pub fn buffered(&self) -> usize {
+        self.buffered.load(Ordering::Relaxed)
+    }
+
+    fn remove(&self, id: u64) -> Option<Upload> {
+        let (_, upload) = self.uploads.remove(&id)?;
+        self.release(upload.data.len());
+        Some(upload)
+    }
+
+    // 增加缓存的字节数，超过max时失败
+    fn reserve(&self, size: usize, max: usize) -> Result<(), KvError> {
+        self.buffered
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                (used + size <= max).then_some(used + size)
+            })
+            .map(|_| ())
+            .map_err(|used| {
+                KvError::QuotaExceeded(format!(
+                    "upload buffer {} would exceed {}",
+                    used + size,
+                    max
+                ))
+            })
+    }
+
+    fn release(&self, size: usize) {
+        self.buffered.fetch_sub(size, Ordering::Relaxed);
+    }
+}
+
+impl<Store: Storage> Service<Store> {
+    /// 执行PutChunk，最后一个分块到达时把完整的值写入存储
+    pub(crate) fn put_chunk(&self, param: PutChunk) -> (CommandResponse, Vec<WatchEvent>) {
+        let uploads = &self.inner.uploads;
+        let limits = &self.inner.limits;
+        let id = match param.offset {
+            0 => {
+                // 新的上传开始时顺便清理掉超时的上传
+                uploads.sweep();
+                let id = uploads.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+                let upload = Upload {
+                    db: self.db,
+                    table: param.table.clone(),
+                    key: param.key.clone(),
+                    data: BytesMut::new(),
+                    updated: Instant::now(),
+                };
+                uploads.uploads.insert(id, upload);
+                id
+            }
+            _ => param.upload_id,
+        };
+
+        let len = {
+            // upload_id必须是同一个数据库中同一个key的上传
+            let mut upload = match uploads.uploads.get_mut(&id) {
+                Some(u) if u.db == self.db && u.table == param.table && u.key == param.key => u,
+                _ => {
+                    let msg = format!(
+                        "no upload {} in progress for {}/{}",
+                        id, param.table, param.key
+                    );
+                    return (KvError::InvalidCommand(msg).into(), vec![]);
+                }
+            };
+            if upload.data.len() as u64 != param.offset {
+                let msg = format!(
+                    "chunk offset {} does not match uploaded length {}",
+                    param.offset,
+                    upload.data.len()
+                );
+                return (KvError::InvalidCommand(msg).into(), vec![]);
+            }
+            let len = upload.data.len() + param.data.len();
+            let res = match len > limits.max_blob_size {
+                true => Err(KvError::PayloadTooLarge(format!(
+                    "blob size {} exceeds {}",
+                    len, limits.max_blob_size
+                ))),
+                false => uploads.reserve(param.data.len(), limits.max_upload_buffer),
+            };
+            if let Err(e) = res {
+                drop(upload);
+                uploads.remove(id);
+                return (e.into(), vec![]);
+            }
+            upload.data.extend_from_slice(&param.data);
+            upload.updated = Instant::now();
+            len
+        };
+        let progress = || vec![Value::from(len as i64), Value::from(id as i64)].into();
+        if !param.last {
+            return (progress(), vec![]);
+        }
+
+        let upload = match uploads.remove(id) {
+            Some(upload) => upload,
+            None => {
+                let msg = format!("upload {} was aborted", id);
+                return (KvError::InvalidCommand(msg).into(), vec![]);
+            }
+        };
+        let value = Value::from(upload.data.freeze());
+        let res = {
+            let _guard = self.inner.lock.read().unwrap();
+            self.db()
+                .store
+                .set(&upload.table, upload.key.as_str(), value.clone())
+        };
+        match res {
+            Ok(old) => {
+                let event = WatchEvent::new(upload.table, upload.key, "set", old, Some(value));
+                (progress(), vec![event])
+            }
+            Err(e) => (e.into(), vec![]),
+        }
+    }
+
+    /// 丢弃超时的分块上传，服务器会定期调用
+    pub fn sweep_uploads(&self) {
+        self.inner.uploads.sweep();
+    }
+}
+
+impl CommandService for GetChunk {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let data = match store.get(&self.table, &self.key) {
+            Ok(Some(v)) => match v.value {
+                Some(value::Value::Binary(b)) => b,
+                Some(value::Value::String(s)) => Bytes::from(s),
+                _ => {
+                    let msg = format!("{}/{} is not a binary value", self.table, self.key);
+                    return KvError::InvalidCommand(msg).into();
+                }
+            },
+            Ok(None) => return KvError::NotFound(self.table, self.key).into(),
+            Err(e) => return e.into(),
+        };
+
+        let total = data.len();
+        let start = (self.offset as usize).min(total);
+        let end = start.saturating_add(self.length as usize).min(total);
+        // Bytes::slice不会复制数据
+        vec![
+            Value::from(data.slice(start..end)),
+            Value::from(total as i64),
+        ]
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(max_blob_size: usize) -> Service {
+        let config = ServiceConfig {
+            max_value_size: 1024,
+            max_blob_size,
+            ..Default::default()
+        };
+        Service::with_config(MemTable::new(), config)
+    }
+
+    // 返回已经上传的字节数和upload_id
+    fn put(service: &Service, id: u64, offset: u64, data: &[u8], last: bool) -> (i64, u64) {
+        let cmd = CommandRequest::new_put_chunk("t", "k", id, offset, data.to_vec(), last);
+        let res = service.execute(cmd);
+        assert_eq!(res.status, 200, "{}", res.message);
+        match res.values.as_slice() {
+            [Value {
+                value: Some(value::Value::Integer(len)),
+            }, Value {
+                value: Some(value::Value::Integer(id)),
+            }] => (*len, *id as u64),
+            _ => panic!("invalid PutChunk response: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn chunked_upload_and_download_should_work() {
+        let service = service(1 << 20);
+        let data: Vec<u8> = (0..3000).map(|i| i as u8).collect();
+        let mut rx = service.execute_streaming(CommandRequest::new_watch("files", ""));
+        rx.try_recv().unwrap();
+
+        let mut id = 0;
+        for (i, chunk) in data.chunks(1000).enumerate() {
+            let last = i == 2;
+            let offset = i as u64 * 1000;
+            let cmd =
+                CommandRequest::new_put_chunk("files", "f1", id, offset, chunk.to_vec(), last);
+            let res = service.execute(cmd);
+            assert_eq!(res.values[0], ((i + 1) as i64 * 1000).into());
+            id = match res.values[1].value {
+                Some(value::Value::Integer(id)) => id as u64,
+                _ => panic!("no upload id"),
+            };
+            // 上传完成之前读不到
+            if !last {
+                let res = service.execute(CommandRequest::new_hexist("files", "f1"));
+                assert_res_ok(res, &[false.into()], &[]);
+            }
+        }
+        assert_eq!(rx.try_recv().unwrap().events[0].op, "set");
+        assert_eq!(service.inner.uploads.buffered(), 0);
+
+        let res = service.execute(CommandRequest::new_get_chunk("files", "f1", 2500, 1000));
+        let chunk = Bytes::copy_from_slice(&data[2500..]);
+        assert_res_ok(res, &[chunk.into(), 3000.into()], &[]);
+
+        // 读取时不检查大小限制
+        let res = service.execute(CommandRequest::new_hget("files", "f1"));
+        assert_eq!(res.status, 200);
+    }
+
+    #[test]
+    fn uploads_to_same_key_should_not_interfere() {
+        let service = service(1 << 20);
+        let (_, a) = put(&service, 0, 0, b"aaa", false);
+        let (_, b) = put(&service, 0, 0, b"bb", false);
+        assert_ne!(a, b);
+        put(&service, a, 3, b"a", true);
+        let res = service.execute(CommandRequest::new_hget("t", "k"));
+        assert_res_ok(res, &[Bytes::from("aaaa").into()], &[]);
+
+        put(&service, b, 2, b"b", true);
+        let res = service.execute(CommandRequest::new_hget("t", "k"));
+        assert_res_ok(res, &[Bytes::from("bbb").into()], &[]);
+    }
+
+    #[test]
+    fn chunk_with_wrong_offset_should_be_rejected() {
+        let service = service(1 << 20);
+        let res = service.execute(CommandRequest::new_put_chunk("t", "k", 1, 10, "abc", false));
+        assert_res_error(res, 400, "no upload 1 in progress");
+
+        let (_, id) = put(&service, 0, 0, b"abc", false);
+        let res = service.execute(CommandRequest::new_put_chunk("t", "k", id, 2, "abc", true));
+        assert_res_error(res, 400, "does not match");
+
+        // upload_id只能用在开始上传的数据库和key上
+        let res = service.execute(CommandRequest::new_put_chunk("t", "k2", id, 3, "abc", true));
+        assert_res_error(res, 400, "no upload");
+    }
+
+    #[test]
+    fn blob_larger_than_limit_should_be_rejected() {
+        let service = service(1500);
+        let chunk = vec![0u8; 1000];
+        let (_, id) = put(&service, 0, 0, &chunk, false);
+        let cmd = CommandRequest::new_put_chunk("t", "k", id, 1000, chunk, true);
+        assert_res_error(service.execute(cmd), 413, "exceeds");
+        assert_eq!(service.inner.uploads.buffered(), 0);
+
+        // 单个分块也不能超过value的大小限制
+        let chunk = vec![0u8; 2000];
+        let res = service.execute(CommandRequest::new_put_chunk("t", "k", 0, 0, chunk, true));
+        assert_res_error(res, 413, "exceeds");
+    }
+
+    #[test]
+    fn upload_buffer_should_be_limited() {
+        let config = ServiceConfig {
+            max_value_size: 1024,
+            max_upload_buffer: 1500,
+            ..Default::default()
+        };
+        let service = Service::with_config(MemTable::new(), config);
+        let chunk = vec![0u8; 1000];
+        let (_, a) = put(&service, 0, 0, &chunk, false);
+        let cmd = CommandRequest::new_put_chunk("t", "k", 0, 0, chunk.clone(), false);
+        assert_res_error(service.execute(cmd), 507, "upload buffer");
+        assert_eq!(service.inner.uploads.buffered(), 1000);
+
+        // 上传完成之后释放缓存
+        put(&service, a, 1000, b"", true);
+        assert_eq!(service.inner.uploads.buffered(), 0);
+        put(&service, 0, 0, &chunk, false);
+    }
+
+    #[test]
+    fn expired_uploads_should_be_swept() {
+        let service = service(1 << 20);
+        let (_, id) = put(&service, 0, 0, b"abc", false);
+        let (_, fresh) = put(&service, 0, 0, b"de", false);
+        let past = Instant::now().checked_sub(UPLOAD_TIMEOUT * 2).unwrap();
+        service.inner.uploads.uploads.get_mut(&id).unwrap().updated = past;
+
+        service.sweep_uploads();
+        assert_eq!(service.inner.uploads.buffered(), 2);
+        let res = service.execute(CommandRequest::new_put_chunk("t", "k", id, 3, "d", true));
+        assert_res_error(res, 400, "no upload");
+        put(&service, fresh, 2, b"f", true);
+    }
+}
@@ -1,4 +1,5 @@
 use crate::*;
+use prost::Message;
 
 impl CommandService for Hset {
     fn execute(self, store: &impl Storage) -> CommandResponse {
@@ -13,6 +14,19 @@ impl CommandService for Hset {
     }
 }
 
+impl CommandService for Hmset {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut values = Vec::with_capacity(self.pairs.len());
+        for pair in self.pairs {
+            match store.set(&self.table, pair.key, pair.value.unwrap_or_default()) {
+                Ok(v) => values.push(v.unwrap_or_default()),
+                Err(e) => return e.into(),
+            }
+        }
+        values.into()
+    }
+}
+
 impl CommandService for Hget {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match store.get(&self.table, &self.key) {
@@ -32,6 +46,66 @@ impl CommandService for Hgetall {
     }
 }
 
+impl CommandService for Hscan {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let mut keys = match store.get_keys(&self.table) {
+            Ok(keys) => keys,
+            Err(e) => return e.into(),
+        };
+        // 每次都要排序，table很大时分页读取的开销是O(n log n)乘以页数
+        keys.retain(|k| *k >= self.start);
+        keys.sort_unstable();
+        if self.count > 0 {
+            keys.truncate(self.count as usize);
+        }
+
+        let mut pairs = Vec::new();
+        let mut total = 0;
+        for key in keys {
+            // 读取期间被删除的key直接跳过
+            let mut pair = match store.get(&self.table, &key) {
+                Ok(Some(v)) => Kvpair::new(key, v),
+                Ok(None) => continue,
+                Err(e) => return e.into(),
+            };
+            let size = pair.encoded_len() as u64;
+            if self.max_bytes > 0 && size > self.max_bytes && is_binary(&pair) {
+                pair.value = None;
+            } else if self.max_bytes > 0 && !pairs.is_empty() && total + size > self.max_bytes {
+                break;
+            }
+            total += pair.encoded_len() as u64;
+            pairs.push(pair);
+        }
+        pairs.into()
+    }
+}
+
+fn is_binary(pair: &Kvpair) -> bool {
+    matches!(
+        pair.value,
+        Some(Value {
+            value: Some(value::Value::Binary(_))
+        })
+    )
+}
+
+/// 按编码后的总大小截断，至少保留一个，max_bytes为0时不截断
+pub(crate) fn truncate_by_bytes<M: Message>(items: &mut Vec<M>, max_bytes: u64) {
+    if max_bytes == 0 {
+        return;
+    }
+    let mut total = 0;
+    let len = items
+        .iter()
+        .position(|item| {
+            total += item.encoded_len() as u64;
+            total > max_bytes
+        })
+        .unwrap_or(items.len());
+    items.truncate(len.max(1));
+}
+
 impl CommandService for Hdel {
     fn execute(self, store: &impl Storage) -> CommandResponse {
         match store.del(&self.table, &self.key) {
@@ -54,9 +128,160 @@ impl CommandService for Hexist {
     }
 }
 
+impl CommandService for Info {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.info() {
+            Ok(v) => v.into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Tables {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let keys = KeyKind::try_from(self.kind).and_then(|kind| match kind {
+            KeyKind::Hash => store.get_tables(),
+            KeyKind::Zset => store.zset_keys(),
+            KeyKind::List => store.list_keys(),
+        });
+        match keys {
+            Ok(v) => v.into_iter().map(Value::from).collect::<Vec<_>>().into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zadd {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        let members = self
+            .members
+            .into_iter()
+            .map(|m| (m.member, m.score))
+            .collect();
+        match store.zadd(&self.key, members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrange(&self.key, self.start, self.stop) {
+            Ok(v) => {
+                let mut pairs: Vec<_> = v
+                    .into_iter()
+                    .map(|(member, score)| Kvpair::new(member, score.into()))
+                    .collect();
+                truncate_by_bytes(&mut pairs, self.max_bytes);
+                pairs.into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrank {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrank(&self.key, &self.member) {
+            Ok(Some(rank)) => Value::from(rank as i64).into(),
+            Ok(None) => KvError::NotFound(self.key, self.member).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Zrem {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.zrem(&self.key, &self.members) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lpush {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.lpush(&self.key, self.values) {
+            Ok(n) => Value::from(n as i64).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Rpop {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.rpop(&self.key) {
+            Ok(Some(v)) => v.into(),
+            Ok(None) => KvError::NotFound(self.key, "".into()).into(),
+            Err(e) => e.into(),
+        }
+    }
+}
+
+impl CommandService for Lrange {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        match store.lrange(&self.key, self.start, self.stop) {
+            Ok(mut v) => {
+                truncate_by_bytes(&mut v, self.max_bytes);
+                v.into()
+            }
+            Err(e) => e.into(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn hscan_should_page_by_count_and_bytes() {
+        let store = MemTable::new();
+        for key in ["c", "a", "", "b"] {
+            store.set("t1", key, format!("v{}", key)).unwrap();
+        }
+        store
+            .set("t1", "blob", Bytes::from(vec![0u8; 100]))
+            .unwrap();
+
+        let res = dispatch(CommandRequest::new_hscan("t1", "", 2), &store);
+        let keys: Vec<_> = res.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["", "a"]);
+        let res = dispatch(CommandRequest::new_hscan("t1", "a\0", 0), &store);
+        let keys: Vec<_> = res.pairs.iter().map(|p| p.key.as_str()).collect();
+        assert_eq!(keys, ["b", "blob", "c"]);
+        let res = dispatch(CommandRequest::new_hscan("t1", "d", 0), &store);
+        assert_res_ok(res, &[], &[]);
+
+        // 超过max_bytes时截断，太大的binary值只返回key
+        let cmd = CommandRequest::new_hscan("t1", "b", 0).with_max_bytes(20);
+        let res = dispatch(cmd, &store);
+        assert_eq!(res.pairs[0], Kvpair::new("b", "vb".into()));
+        assert_eq!(res.pairs[1].key, "blob");
+        assert_eq!(res.pairs[1].value, None);
+        assert_eq!(res.pairs.len(), 2);
+        let cmd = CommandRequest::new_hscan("t1", "", 0).with_max_bytes(1);
+        assert_eq!(dispatch(cmd, &store).pairs.len(), 1);
+    }
+
+    #[test]
+    fn range_should_be_limited_by_bytes() {
+        let store = MemTable::new();
+        let values: Vec<Value> = (0..10).map(|i| "x".repeat(i * 10).into()).collect();
+        store.rpush("l1", values.clone()).unwrap();
+        let cmd = CommandRequest::new_lrange("l1", 0, -1).with_max_bytes(50);
+        assert_res_ok(dispatch(cmd, &store), &values[..3], &[]);
+        let cmd = CommandRequest::new_lrange("l1", 9, -1).with_max_bytes(1);
+        assert_res_ok(dispatch(cmd, &store), &values[9..], &[]);
+
+        store
+            .zadd("z1", vec![("a".into(), 1.0), ("b".into(), 2.0)])
+            .unwrap();
+        let cmd = CommandRequest::new_zrange("z1", 0, -1).with_max_bytes(1);
+        assert_res_ok(dispatch(cmd, &store), &[], &[Kvpair::new("a", 1.0.into())]);
+    }
 
     #[test]
     fn hset_should_work() {
@@ -143,4 +368,96 @@ mod tests {
         let res = dispatch(cmd, &store);
         assert_res_ok(res, &[false.into()], &[]);
     }
+
+    #[test]
+    fn info_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("score", "u1", 10.into()), &store);
+        let res = dispatch(CommandRequest::new_info(), &store);
+        assert_eq!(res.status, 200);
+        let keys = res.pairs.iter().find(|p| p.key == "keys").unwrap();
+        assert_eq!(keys.value, Some(1.into()));
+        assert!(res.pairs.iter().any(|p| p.key == "used_memory"));
+        assert!(res.pairs.iter().any(|p| p.key == "evicted_keys"));
+    }
+
+    #[test]
+    fn hmset_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("score", "u1", 10.into()), &store);
+        let pairs = vec![Kvpair::new("u1", 6.into()), Kvpair::new("u2", 8.into())];
+        let res = dispatch(CommandRequest::new_hmset("score", pairs), &store);
+        assert_res_ok(res, &[10.into(), Value::default()], &[]);
+
+        let res = dispatch(CommandRequest::new_hget("score", "u2"), &store);
+        assert_res_ok(res, &[8.into()], &[]);
+    }
+
+    #[test]
+    fn tables_should_work() {
+        let store = MemTable::new();
+        dispatch(CommandRequest::new_hset("t1", "k1", 1.into()), &store);
+        dispatch(CommandRequest::new_hset("t2", "k1", 1.into()), &store);
+        let mut res = dispatch(CommandRequest::new_tables(), &store);
+        res.values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_res_ok(res, &["t1".into(), "t2".into()], &[]);
+    }
+
+    #[test]
+    fn zset_commands_should_work() {
+        let store = MemTable::new();
+        let members = vec![
+            ScoredMember::new("alice", 30.0),
+            ScoredMember::new("bob", 10.0),
+            ScoredMember::new("carol", 20.0),
+        ];
+        let res = dispatch(CommandRequest::new_zadd("board", members), &store);
+        assert_res_ok(res, &[3.into()], &[]);
+        let members = vec![ScoredMember::new("bob", 40.0)];
+        let res = dispatch(CommandRequest::new_zadd("board", members), &store);
+        assert_res_ok(res, &[0.into()], &[]);
+
+        // 按score从小到大返回
+        let res = dispatch(CommandRequest::new_zrange("board", 0, -1), &store);
+        assert_eq!(
+            res.pairs,
+            [
+                Kvpair::new("carol", 20.0.into()),
+                Kvpair::new("alice", 30.0.into()),
+                Kvpair::new("bob", 40.0.into()),
+            ]
+        );
+
+        let res = dispatch(CommandRequest::new_zrank("board", "bob"), &store);
+        assert_res_ok(res, &[2.into()], &[]);
+        let res = dispatch(CommandRequest::new_zrank("board", "dave"), &store);
+        assert_res_error(res, 404, "Not found");
+
+        let cmd = CommandRequest::new_zrem("board", vec!["alice".into(), "dave".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[1.into()], &[]);
+        let res = dispatch(CommandRequest::new_zrank("board", "bob"), &store);
+        assert_res_ok(res, &[1.into()], &[]);
+    }
+
+    #[test]
+    fn list_commands_should_work() {
+        let store = MemTable::new();
+        let cmd = CommandRequest::new_lpush("queue", vec!["a".into(), "b".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[2.into()], &[]);
+        let cmd = CommandRequest::new_lpush("queue", vec!["c".into()]);
+        assert_res_ok(dispatch(cmd, &store), &[3.into()], &[]);
+
+        let res = dispatch(CommandRequest::new_lrange("queue", 0, -1), &store);
+        assert_res_ok(res, &["c".into(), "b".into(), "a".into()], &[]);
+        let res = dispatch(CommandRequest::new_lrange("queue", -2, 10), &store);
+        assert_res_ok(res, &["b".into(), "a".into()], &[]);
+
+        // lpush和rpop组成先进先出的队列
+        for v in ["a", "b", "c"] {
+            let res = dispatch(CommandRequest::new_rpop("queue"), &store);
+            assert_res_ok(res, &[v.into()], &[]);
+        }
+        let res = dispatch(CommandRequest::new_rpop("queue"), &store);
+        assert_res_error(res, 404, "Not found");
+    }
 }
@@ -0,0 +1,111 @@
+use crate::{command_request::RequestData, *};
+
+/// 请求中除了value以外的部分，比如table名字和protobuf的字段头，预留的空间
+const FRAME_OVERHEAD: usize = 64 * 1024;
+
+/// 写入命令中key和value的大小限制，超过时返回413
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Limits {
+    pub max_key_size: usize,
+    pub max_value_size: usize,
+    pub max_blob_size: usize,
+    pub max_upload_buffer: usize,
+}
+
+impl Limits {
+    pub fn new(config: &ServiceConfig) -> Self {
+        Self {
+            max_key_size: config.max_key_size,
+            max_value_size: config.max_value_size,
+            max_blob_size: config.max_blob_size,
+            max_upload_buffer: config.max_upload_buffer,
+        }
+    }
+
+    /// 检查写入命令中的key和value，读取命令不检查
+    pub fn check(&self, cmd: &CommandRequest) -> Result<(), KvError> {
+        match &cmd.request_data {
+            Some(RequestData::Hset(p)) => p.pair.iter().try_for_each(|p| self.check_pair(p)),
+            Some(RequestData::Hmset(p)) => p.pairs.iter().try_for_each(|p| self.check_pair(p)),
+            Some(RequestData::Zadd(p)) => {
+                self.check_key(&p.key)?;
+                p.members.iter().try_for_each(|m| self.check_key(&m.member))
+            }
+            Some(RequestData::Lpush(p)) => {
+                self.check_key(&p.key)?;
+                p.values.iter().try_for_each(|v| self.check_value(v))
+            }
+            Some(RequestData::Eval(p)) => {
+                p.keys.iter().try_for_each(|k| self.check_key(k))?;
+                p.args.iter().try_for_each(|v| self.check_value(v))
+            }
+            // 分块上传的总大小由max_blob_size限制，每个分块不能超过max_value_size
+            Some(RequestData::PutChunk(p)) => {
+                self.check_key(&p.key)?;
+                self.check_size("chunk", p.data.len(), self.max_value_size)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// 请求帧的最大长度，要能放下最大的key和value
+    pub fn max_frame_length(&self) -> usize {
+        (self.max_key_size + self.max_value_size + FRAME_OVERHEAD).max(MAX_FRAME_LENGTH)
+    }
+
+    fn check_pair(&self, pair: &Kvpair) -> Result<(), KvError> {
+        self.check_key(&pair.key)?;
+        pair.value.iter().try_for_each(|v| self.check_value(v))
+    }
+
+    pub fn check_key(&self, key: &str) -> Result<(), KvError> {
+        self.check_size("key", key.len(), self.max_key_size)
+    }
+
+    pub fn check_value(&self, value: &Value) -> Result<(), KvError> {
+        self.check_size("value", value.data_len(), self.max_value_size)
+    }
+
+    fn check_size(&self, name: &str, size: usize, max: usize) -> Result<(), KvError> {
+        match size > max {
+            true => Err(KvError::PayloadTooLarge(format!(
+                "{} size {} exceeds {}",
+                name, size, max
+            ))),
+            false => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn oversized_key_and_value_should_be_rejected() {
+        let config = ServiceConfig {
+            max_key_size: 8,
+            max_value_size: 16,
+            ..Default::default()
+        };
+        let service = Service::with_config(MemTable::new(), config);
+
+        let res = service.execute(CommandRequest::new_hset("t1", "k".repeat(9), 1.into()));
+        assert_res_error(res, 413, "key size 9 exceeds 8");
+        let pairs = vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v".repeat(17).into()),
+        ];
+        let res = service.execute(CommandRequest::new_hmset("t1", pairs));
+        assert_res_error(res, 413, "value size 17 exceeds 16");
+        let res = service.execute(CommandRequest::new_lpush("l1", vec!["v".repeat(17).into()]));
+        assert_res_error(res, 413, "value size");
+
+        // 被拒绝的命令不会写入任何数据
+        let res = service.execute(CommandRequest::new_hexist("t1", "k1"));
+        assert_res_ok(res, &[false.into()], &[]);
+
+        let res = service.execute(CommandRequest::new_hset("t1", "k1", "v".repeat(16).into()));
+        assert_eq!(res.status, 200);
+    }
+}
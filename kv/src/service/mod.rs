@@ -1,9 +1,29 @@
 use crate::command_request::RequestData;
 use crate::*;
-use std::sync::Arc;
-use tracing::debug;
+use limits::Limits;
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{debug, field, info_span};
 
+mod blocking;
+mod chunked;
 mod command_service;
+mod limits;
+mod script;
+mod slowlog;
+mod stats;
+mod watch;
+
+pub use blocking::ListWaiters;
+pub use chunked::Uploads;
+pub(crate) use chunked::UPLOAD_SWEEP_INTERVAL;
+pub(crate) use command_service::truncate_by_bytes;
+pub use script::SCRIPT_TIMEOUT;
+pub use slowlog::SlowLogBuffer;
+pub use stats::{CommandStat, CommandStats};
+pub use watch::{StreamingResponse, Watchers};
 
 /// 对Command的处理的抽象
 pub trait CommandService {
@@ -12,52 +32,324 @@ pub trait CommandService {
 }
 
 /// Service
+/// 每个Service选择了一个数据库，clone之后可以用select切换到其它数据库
 pub struct Service<Store = MemTable> {
     inner: Arc<ServiceInner<Store>>,
+    db: usize,
 }
 
 impl<Store> Clone for Service<Store> {
     fn clone(&self) -> Self {
         Self {
             inner: Arc::clone(&self.inner),
+            db: self.db,
+        }
+    }
+}
+
+/// Service的配置
+#[derive(Clone, Debug)]
+pub struct ServiceConfig {
+    /// 执行时间超过这个值的命令会记入慢命令日志，None表示不记录
+    pub slow_log_threshold: Option<Duration>,
+    /// 慢命令日志最多保留的条数
+    pub slow_log_capacity: usize,
+    /// key的最大长度，写入命令超过时返回413
+    pub max_key_size: usize,
+    /// String和Binary值的最大长度，分块上传时也是每个分块的最大长度
+    pub max_value_size: usize,
+    /// 分块上传的值的最大长度
+    pub max_blob_size: usize,
+    /// 所有未完成的分块上传最多缓存的字节数，超过时返回507
+    pub max_upload_buffer: usize,
+}
+
+impl Default for ServiceConfig {
+    fn default() -> Self {
+        Self {
+            slow_log_threshold: Some(Duration::from_millis(10)),
+            slow_log_capacity: 128,
+            max_key_size: 4 * 1024,
+            max_value_size: 1024 * 1024,
+            max_blob_size: 512 * 1024 * 1024,
+            max_upload_buffer: 1024 * 1024 * 1024,
         }
     }
 }
 
 pub struct ServiceInner<Store> {
+    dbs: Vec<Database<Store>>,
+    slow_log: SlowLogBuffer,
+    stats: CommandStats,
+    limits: Limits,
+    uploads: Uploads,
+    // 普通命令持有读锁，Eval持有写锁，保证脚本执行期间数据不会被其它命令修改
+    lock: RwLock<()>,
+}
+
+/// 一个数据库有自己的存储，table互相独立，watch和brpop也只能看到本数据库的变化
+struct Database<Store> {
     store: Store,
+    watchers: Watchers,
+    lists: ListWaiters,
+}
+
+impl Service<MemTable> {
+    /// 按每个数据库的存储配置创建Service，configs的长度就是数据库的个数
+    pub fn with_storage_configs(configs: &[StorageConfig], config: ServiceConfig) -> Self {
+        let stores = configs.iter().map(|c| MemTable::with_config(*c)).collect();
+        Self::with_databases(stores, config)
+    }
 }
 
 impl<Store: Storage> Service<Store> {
     pub fn new(store: Store) -> Self {
+        Self::with_config(store, ServiceConfig::default())
+    }
+
+    pub fn with_config(store: Store, config: ServiceConfig) -> Self {
+        Self::with_databases(vec![store], config)
+    }
+
+    /// 创建有多个数据库的Service，每个store是一个数据库，默认选择第0个
+    /// 每个数据库可以使用不同配置的存储，比如用NoEviction的MemTable限制数据库的内存
+    pub fn with_databases(stores: Vec<Store>, config: ServiceConfig) -> Self {
+        assert!(!stores.is_empty(), "service needs at least one database");
+        let dbs = stores
+            .into_iter()
+            .map(|store| Database {
+                store,
+                watchers: Watchers::default(),
+                lists: ListWaiters::default(),
+            })
+            .collect();
         Self {
-            inner: Arc::new(ServiceInner { store }),
+            db: 0,
+            inner: Arc::new(ServiceInner {
+                dbs,
+                slow_log: SlowLogBuffer::new(config.slow_log_threshold, config.slow_log_capacity),
+                stats: CommandStats::default(),
+                limits: Limits::new(&config),
+                uploads: Uploads::default(),
+                lock: RwLock::new(()),
+            }),
         }
     }
 
+    /// 返回选择了第db个数据库的Service
+    pub fn select(&self, db: u32) -> Result<Self, KvError> {
+        let db = db as usize;
+        if db >= self.inner.dbs.len() {
+            return Err(KvError::InvalidCommand(format!(
+                "database {} does not exist, there are {} databases",
+                db,
+                self.inner.dbs.len()
+            )));
+        }
+        Ok(Self {
+            inner: Arc::clone(&self.inner),
+            db,
+        })
+    }
+
+    /// 当前选择的数据库
+    pub fn current_db(&self) -> usize {
+        self.db
+    }
+
+    /// 数据库的个数
+    pub fn databases(&self) -> usize {
+        self.inner.dbs.len()
+    }
+
+    /// 连接上请求帧的最大长度，根据key和value的大小限制计算，超过时返回413
+    pub fn max_frame_length(&self) -> usize {
+        self.inner.limits.max_frame_length()
+    }
+
+    fn db(&self) -> &Database<Store> {
+        &self.inner.dbs[self.db]
+    }
+
     pub fn execute(&self, cmd: CommandRequest) -> CommandResponse {
         debug!("Got request: {:?}", cmd);
+        let name = cmd.name();
+        let (table, key) = cmd.target();
+        let span = info_span!(
+            "command",
+            db = self.db,
+            command = name,
+            table,
+            key,
+            status = field::Empty
+        );
+        let _enter = span.enter();
+        // 命令执行之后就拿不到table和key了，慢命令日志需要提前保存
+        let target = (table.to_owned(), key.to_owned());
+        let (start, timer) = (SystemTime::now(), Instant::now());
+
+        // 读取慢命令日志不需要访问存储，也不记入慢命令日志
+        if let Some(RequestData::SlowLog(param)) = &cmd.request_data {
+            let res = self.inner.slow_log.execute(param.clone());
+            span.record("status", res.status);
+            return res;
+        }
+
         // TODO: 发送on_received事件
-        let res = dispatch(cmd, &self.inner.store);
+        let (res, events) = match self.inner.limits.check(&cmd) {
+            Ok(()) => self.run(cmd),
+            Err(e) => (e.into(), vec![]),
+        };
+        let elapsed = timer.elapsed();
+        span.record("status", res.status);
+        self.inner.stats.record(name, res.status, elapsed);
+        let (table, key) = (target.0.as_str(), target.1.as_str());
+        self.inner
+            .slow_log
+            .record(name, (table, key), start, elapsed);
         debug!("Executed response: {:?}", res);
         // TODO: 发送on_executed事件
+        for event in events {
+            self.db().watchers.publish(event);
+        }
 
         res
     }
+
+    // 执行命令，返回响应和需要通知watcher的事件
+    fn run(&self, cmd: CommandRequest) -> (CommandResponse, Vec<WatchEvent>) {
+        match cmd.request_data {
+            Some(RequestData::PutChunk(param)) => self.put_chunk(param),
+            Some(RequestData::Eval(param)) => {
+                let _guard = self.inner.lock.write().unwrap();
+                param.run(&self.db().store, Some(&self.inner.limits))
+            }
+            _ => {
+                // 没有watcher时不需要提取事件
+                let events = match self.db().watchers.is_empty() {
+                    true => vec![],
+                    false => WatchEvent::from_request(&cmd),
+                };
+                // Lpush之后需要唤醒等待的Brpop
+                let pushed = match &cmd.request_data {
+                    Some(RequestData::Lpush(param)) => Some(param.key.clone()),
+                    _ => None,
+                };
+                let res = {
+                    let _guard = self.inner.lock.read().unwrap();
+                    dispatch(cmd, &self.db().store)
+                };
+                if let Some(key) = pushed.filter(|_| res.status == 200) {
+                    self.db().lists.notify(&key);
+                }
+                let events = WatchEvent::complete(events, &res);
+                (res, events)
+            }
+        }
+    }
+
+    /// 每种命令的执行统计
+    pub fn command_stats(&self) -> Vec<(&'static str, CommandStat)> {
+        self.inner.stats.snapshot()
+    }
+
+    /// 把当前数据库的所有数据导出成dump文件，集群模式用来生成raft快照
+    pub(crate) fn snapshot(&self) -> Result<Vec<u8>, KvError> {
+        let _guard = self.inner.lock.read().unwrap();
+        let mut buf = Vec::new();
+        crate::dump(&self.db().store, &mut buf)?;
+        Ok(buf)
+    }
+
+    /// 删除当前数据库的所有数据，集群模式安装raft快照之前使用
+    pub(crate) fn clear(&self) -> Result<(), KvError> {
+        let _guard = self.inner.lock.write().unwrap();
+        clear_store(&self.db().store)
+    }
+
+    /// 把所有数据库中还没有持久化的数据写入
+    pub fn flush(&self) -> Result<(), KvError> {
+        self.inner.dbs.iter().try_for_each(|db| db.store.flush())
+    }
+}
+
+impl<Store: Storage + Send + Sync + 'static> Service<Store> {
+    /// 执行流式命令，Watch会持续返回key的变化，Brpop会等待列表中有值，其它命令只返回一个响应
+    pub fn execute_streaming(&self, cmd: CommandRequest) -> StreamingResponse {
+        match cmd.request_data {
+            Some(RequestData::Watch(param)) => self.db().watchers.subscribe(param),
+            Some(RequestData::Brpop(param)) => self.blocking_pop(param),
+            _ => {
+                let (tx, rx) = tokio::sync::mpsc::channel(1);
+                tx.try_send(self.execute(cmd)).unwrap();
+                rx
+            }
+        }
+    }
 }
 
 pub fn dispatch(cmd: CommandRequest, store: &impl Storage) -> CommandResponse {
     match cmd.request_data {
         Some(RequestData::Hget(param)) => param.execute(store),
         Some(RequestData::Hgetall(param)) => param.execute(store),
+        Some(RequestData::Hscan(param)) => param.execute(store),
         Some(RequestData::Hset(param)) => param.execute(store),
+        Some(RequestData::Hmset(param)) => param.execute(store),
         Some(RequestData::Hdel(param)) => param.execute(store),
         Some(RequestData::Hexist(param)) => param.execute(store),
+        Some(RequestData::Info(param)) => param.execute(store),
+        Some(RequestData::Tables(param)) => param.execute(store),
+        Some(RequestData::Eval(param)) => param.execute(store),
+        Some(RequestData::Zadd(param)) => param.execute(store),
+        Some(RequestData::Zrange(param)) => param.execute(store),
+        Some(RequestData::Zrank(param)) => param.execute(store),
+        Some(RequestData::Zrem(param)) => param.execute(store),
+        Some(RequestData::Lpush(param)) => param.execute(store),
+        Some(RequestData::Rpop(param)) => param.execute(store),
+        Some(RequestData::Lrange(param)) => param.execute(store),
+        Some(RequestData::GetChunk(param)) => param.execute(store),
+        Some(RequestData::Watch(_)) => {
+            KvError::InvalidCommand("Watch is a streaming command".into()).into()
+        }
+        Some(RequestData::Brpop(_)) => {
+            KvError::InvalidCommand("Brpop is a streaming command".into()).into()
+        }
+        Some(RequestData::SlowLog(_)) => {
+            KvError::InvalidCommand("SlowLog is handled by service".into()).into()
+        }
+        Some(RequestData::Select(_)) => {
+            KvError::InvalidCommand("Select can only be used on a connection".into()).into()
+        }
+        Some(RequestData::PutChunk(_)) => {
+            KvError::InvalidCommand("PutChunk is handled by service".into()).into()
+        }
         None => KvError::InvalidCommand("Request has no data".into()).into(),
         _ => KvError::Internal("Not implemented".into()).into(),
     }
 }
 
+#[cfg(test)]
+use crate::{Kvpair, Value};
+
+// 测试成功返回的结果
+#[cfg(test)]
+pub fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
+    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    assert_eq!(res.status, 200);
+    assert_eq!(res.message, "");
+    assert_eq!(res.values, values);
+    assert_eq!(res.pairs, pairs);
+}
+
+// 测试失败返回的结果
+#[cfg(test)]
+pub fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
+    assert_eq!(res.status, code);
+    assert!(res.message.contains(msg));
+    assert_eq!(res.values, &[]);
+    assert_eq!(res.pairs, &[]);
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -85,26 +377,71 @@ mod tests {
         let res = service.execute(CommandRequest::new_hget("t1", "k1"));
         assert_res_ok(res, &["v1".into()], &[]);
     }
-}
 
-#[cfg(test)]
-use crate::{Kvpair, Value};
+    #[test]
+    fn databases_should_be_isolated() {
+        let stores = vec![MemTable::new(), MemTable::new()];
+        let service = Service::with_databases(stores, ServiceConfig::default());
+        let db1 = service.select(1).unwrap();
+        assert_eq!(db1.current_db(), 1);
+        assert!(service.select(2).is_err());
 
-// 测试成功返回的结果
-#[cfg(test)]
-pub fn assert_res_ok(mut res: CommandResponse, values: &[Value], pairs: &[Kvpair]) {
-    res.pairs.sort_by(|a, b| a.partial_cmp(b).unwrap());
-    assert_eq!(res.status, 200);
-    assert_eq!(res.message, "");
-    assert_eq!(res.values, values);
-    assert_eq!(res.pairs, pairs);
-}
+        service.execute(CommandRequest::new_hset("t1", "k1", "v0".into()));
+        db1.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        db1.execute(CommandRequest::new_hset("t2", "k1", "v1".into()));
 
-// 测试失败返回的结果
-#[cfg(test)]
-pub fn assert_res_error(res: CommandResponse, code: u32, msg: &str) {
-    assert_eq!(res.status, code);
-    assert!(res.message.contains(msg));
-    assert_eq!(res.values, &[]);
-    assert_eq!(res.pairs, &[]);
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &["v0".into()], &[]);
+        let res = db1.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_ok(res, &["v1".into()], &[]);
+        let res = service.execute(CommandRequest::new_tables());
+        assert_res_ok(res, &["t1".into()], &[]);
+
+        // 只能在连接上切换数据库
+        let res = service.execute(CommandRequest::new_select(1));
+        assert_res_error(res, 400, "connection");
+    }
+
+    #[test]
+    fn database_quota_should_reject_writes() {
+        let quota = MemTable::with_memory_limit(128, EvictionPolicy::NoEviction);
+        let service = Service::with_databases(vec![MemTable::new(), quota], Default::default());
+        let db1 = service.select(1).unwrap();
+        let value: Value = "x".repeat(32).into();
+
+        let res = db1.execute(CommandRequest::new_hset("t1", "k1", value.clone()));
+        assert_eq!(res.status, 200);
+        let res = db1.execute(CommandRequest::new_hset("t1", "k2", value.clone()));
+        assert_res_error(res, 507, "Quota exceeded");
+
+        // 其它数据库不受影响
+        let res = service.execute(CommandRequest::new_hset("t1", "k2", value));
+        assert_eq!(res.status, 200);
+    }
+
+    #[test]
+    fn service_should_be_created_from_storage_configs() {
+        let configs: Vec<StorageConfig> = ["unlimited", "128:noeviction", "1024"]
+            .iter()
+            .map(|c| c.parse().unwrap())
+            .collect();
+        let service = Service::with_storage_configs(&configs, Default::default());
+        assert_eq!(service.databases(), 3);
+
+        let info = service
+            .select(1)
+            .unwrap()
+            .execute(CommandRequest::new_info());
+        let get = |name: &str| info.pairs.iter().find(|p| p.key == name).cloned();
+        assert_eq!(
+            get("max_memory"),
+            Some(Kvpair::new("max_memory", 128.into()))
+        );
+        assert_eq!(
+            get("eviction_policy"),
+            Some(Kvpair::new("eviction_policy", "noeviction".into()))
+        );
+        assert!("abc:lru".parse::<StorageConfig>().is_err());
+        assert!("128:fifo".parse::<StorageConfig>().is_err());
+    }
 }
@@ -0,0 +1,331 @@
+use super::limits::Limits;
+use crate::*;
+use mlua::{HookTriggers, Lua, LuaOptions, StdLib};
+use std::{
+    cell::RefCell,
+    time::{Duration, Instant},
+};
+
+/// 脚本最长的执行时间，超时之后脚本被中止，已经做的修改会被回滚
+pub const SCRIPT_TIMEOUT: Duration = Duration::from_millis(500);
+/// 脚本最多能使用的内存
+const SCRIPT_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+/// 每执行多少条指令检查一次是否超时
+const HOOK_INSTRUCTIONS: u32 = 1000;
+
+// 脚本执行过程中的修改，用来在出错时回滚以及通知watcher
+#[derive(Default)]
+struct Changes {
+    // (table, key, 修改之前的值)
+    undo: Vec<(String, String, Option<Value>)>,
+    events: Vec<WatchEvent>,
+}
+
+impl CommandService for Eval {
+    fn execute(self, store: &impl Storage) -> CommandResponse {
+        self.run(store, None).0
+    }
+}
+
+impl Eval {
+    /// 执行脚本，返回响应以及脚本对数据做的修改；脚本出错时所有的修改都会被回滚
+    /// 有limits时，脚本写入的key和value和普通命令一样检查大小，超过时脚本出错
+    pub(crate) fn run(
+        self,
+        store: &impl Storage,
+        limits: Option<&Limits>,
+    ) -> (CommandResponse, Vec<WatchEvent>) {
+        let changes = RefCell::new(Changes::default());
+        match self.eval(store, limits, &changes) {
+            Ok(values) => (values.into(), changes.into_inner().events),
+            Err(e) => {
+                for (table, key, old) in changes.into_inner().undo.into_iter().rev() {
+                    let res = match old {
+                        Some(v) => store.set(&table, key, v),
+                        None => store.del(&table, &key),
+                    };
+                    if let Err(e) = res {
+                        return (e.into(), vec![]);
+                    }
+                }
+                (e.into(), vec![])
+            }
+        }
+    }
+
+    fn eval(
+        self,
+        store: &impl Storage,
+        limits: Option<&Limits>,
+        changes: &RefCell<Changes>,
+    ) -> Result<Vec<Value>, KvError> {
+        let lua = sandbox()?;
+        let result = lua.scope(|scope| {
+            let globals = lua.globals();
+            globals.set("KEYS", self.keys)?;
+            let args = self
+                .args
+                .into_iter()
+                .map(|v| to_lua(&lua, v))
+                .collect::<mlua::Result<Vec<_>>>()?;
+            globals.set("ARGV", args)?;
+
+            let hget = scope.create_function(|lua, (table, key): (String, String)| {
+                let value = store.get(&table, &key).map_err(mlua::Error::external)?;
+                to_lua(lua, value.unwrap_or_default())
+            })?;
+            let hset = scope.create_function(
+                |lua, (table, key, value): (String, String, mlua::Value)| {
+                    let value = from_lua(value)?;
+                    if value.value.is_none() {
+                        return Err(mlua::Error::RuntimeError("hset value is nil".into()));
+                    }
+                    if let Some(limits) = limits {
+                        limits
+                            .check_key(&key)
+                            .and_then(|_| limits.check_value(&value))
+                            .map_err(mlua::Error::external)?;
+                    }
+                    let old = store
+                        .set(&table, key.as_str(), value.clone())
+                        .map_err(mlua::Error::external)?;
+                    let mut changes = changes.borrow_mut();
+                    let event = WatchEvent::new(&table, &key, "set", old.clone(), Some(value));
+                    changes.events.push(event);
+                    changes.undo.push((table, key, old.clone()));
+                    to_lua(lua, old.unwrap_or_default())
+                },
+            )?;
+            let hdel = scope.create_function(|lua, (table, key): (String, String)| {
+                let old = store.del(&table, &key).map_err(mlua::Error::external)?;
+                if old.is_some() {
+                    let mut changes = changes.borrow_mut();
+                    let event = WatchEvent::new(&table, &key, "del", old.clone(), None);
+                    changes.events.push(event);
+                    changes.undo.push((table, key, old.clone()));
+                }
+                to_lua(lua, old.unwrap_or_default())
+            })?;
+            globals.set("hget", hget)?;
+            globals.set("hset", hset)?;
+            globals.set("hdel", hdel)?;
+
+            match lua.load(&self.script).set_name("eval").eval()? {
+                mlua::Value::Nil => Ok(vec![]),
+                // 数组的每个元素是一个返回值
+                mlua::Value::Table(t) => t.sequence_values().map(|v| from_lua(v?)).collect(),
+                v => Ok(vec![from_lua(v)?]),
+            }
+        });
+        result.map_err(|e| KvError::ScriptError(e.to_string()))
+    }
+}
+
+/// 创建一个只能做计算的lua环境：没有io/os/package/debug库，限制内存和执行时间
+fn sandbox() -> Result<Lua, KvError> {
+    let libs = StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8;
+    let lua =
+        Lua::new_with(libs, LuaOptions::default()).map_err(|e| KvError::Internal(e.to_string()))?;
+    let setup = || -> mlua::Result<()> {
+        // 基础库中可以读文件、加载代码和输出的函数也要去掉，
+        // pcall/xpcall能捕获超时和内存不足的错误，让脚本一直占着锁，也要去掉
+        let globals = lua.globals();
+        for name in [
+            "dofile",
+            "loadfile",
+            "load",
+            "print",
+            "collectgarbage",
+            "pcall",
+            "xpcall",
+        ] {
+            globals.set(name, mlua::Value::Nil)?;
+        }
+        lua.set_memory_limit(SCRIPT_MEMORY_LIMIT)?;
+
+        let start = Instant::now();
+        let triggers = HookTriggers::new().every_nth_instruction(HOOK_INSTRUCTIONS);
+        lua.set_hook(triggers, move |_, _| {
+            match start.elapsed() > SCRIPT_TIMEOUT {
+                true => Err(mlua::Error::RuntimeError(format!(
+                    "script exceeded time budget of {:?}",
+                    SCRIPT_TIMEOUT
+                ))),
+                false => Ok(()),
+            }
+        });
+        Ok(())
+    };
+    setup().map_err(|e| KvError::Internal(e.to_string()))?;
+    Ok(lua)
+}
+
+fn to_lua(lua: &Lua, v: Value) -> mlua::Result<mlua::Value<'_>> {
+    Ok(match v.value {
+        Some(value::Value::String(s)) => mlua::Value::String(lua.create_string(s)?),
+        Some(value::Value::Binary(b)) => mlua::Value::String(lua.create_string(b)?),
+        Some(value::Value::Integer(i)) => mlua::Value::Integer(i),
+        Some(value::Value::Float(f)) => mlua::Value::Number(f),
+        Some(value::Value::Bool(b)) => mlua::Value::Boolean(b),
+        None => mlua::Value::Nil,
+    })
+}
+
+// lua的字符串不区分文本和二进制，不是合法UTF-8的字符串转换成Binary
+fn from_lua(v: mlua::Value) -> mlua::Result<Value> {
+    Ok(match v {
+        mlua::Value::Nil => Value::default(),
+        mlua::Value::Boolean(b) => b.into(),
+        mlua::Value::Integer(i) => i.into(),
+        mlua::Value::Number(f) => f.into(),
+        mlua::Value::String(s) => match s.to_str() {
+            Ok(s) => s.into(),
+            Err(_) => bytes::Bytes::copy_from_slice(s.as_bytes()).into(),
+        },
+        v => {
+            return Err(mlua::Error::RuntimeError(format!(
+                "cannot convert {} to value",
+                v.type_name()
+            )))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eval_should_read_and_write_store() {
+        let service: Service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "balance", 10.into()));
+
+        // 读取、检查、写入在一个命令中完成
+        let script = r#"
+            local balance = hget("t1", KEYS[1])
+            if balance < ARGV[1] then
+                return "insufficient"
+            end
+            hset("t1", KEYS[1], balance - ARGV[1])
+            return {balance - ARGV[1], "ok"}
+        "#;
+        let cmd = CommandRequest::new_eval(script, vec!["balance".into()], vec![3.into()]);
+        let res = service.execute(cmd.clone());
+        assert_res_ok(res, &[7.into(), "ok".into()], &[]);
+
+        let cmd = CommandRequest::new_eval(script, vec!["balance".into()], vec![30.into()]);
+        let res = service.execute(cmd);
+        assert_res_ok(res, &["insufficient".into()], &[]);
+
+        let res = service.execute(CommandRequest::new_hget("t1", "balance"));
+        assert_res_ok(res, &[7.into()], &[]);
+    }
+
+    #[test]
+    fn eval_error_should_rollback_changes() {
+        let service: Service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+
+        let script = r#"
+            hset("t1", "k1", "changed")
+            hset("t1", "k2", "new")
+            hdel("t1", "k1")
+            error("boom")
+        "#;
+        let res = service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+        assert_res_error(res, 400, "boom");
+
+        let res = service.execute(CommandRequest::new_hgetall("t1"));
+        assert_res_ok(res, &[], &[Kvpair::new("k1", "v1".into())]);
+    }
+
+    #[test]
+    fn eval_writes_should_respect_size_limits() {
+        let config = ServiceConfig {
+            max_key_size: 8,
+            max_value_size: 16,
+            ..Default::default()
+        };
+        let service = Service::with_config(MemTable::new(), config);
+
+        // 前面的写入也会被回滚
+        let script = r#"
+            hset("t1", "k1", "v1")
+            hset("t1", "k2", string.rep("v", 17))
+        "#;
+        let res = service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+        assert_res_error(res, 400, "value size 17 exceeds 16");
+        let script = r#"hset("t1", string.rep("k", 9), 1)"#;
+        let res = service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+        assert_res_error(res, 400, "key size 9 exceeds 8");
+
+        let res = service.execute(CommandRequest::new_hgetall("t1"));
+        assert_res_ok(res, &[], &[]);
+    }
+
+    #[test]
+    fn eval_changes_should_notify_watchers() {
+        let service: Service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        let mut rx = service.execute_streaming(CommandRequest::new_watch("t1", ""));
+        assert_res_ok(rx.try_recv().unwrap(), &[], &[]);
+
+        let script = r#"hset("t1", "k2", "v2") hdel("t1", "k1")"#;
+        service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+        let events: Vec<_> = (0..2)
+            .map(|_| rx.try_recv().unwrap().events.remove(0))
+            .collect();
+        assert_eq!(
+            events,
+            [
+                WatchEvent::new("t1", "k2", "set", None, Some("v2".into())),
+                WatchEvent::new("t1", "k1", "del", Some("v1".into()), None),
+            ]
+        );
+
+        // 出错回滚的脚本不产生事件
+        let script = r#"hset("t1", "k3", "v3") error("boom")"#;
+        service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn eval_should_be_sandboxed() {
+        let service: Service = Service::new(MemTable::new());
+        for script in [
+            "return io.open('/etc/passwd')",
+            "os.exit(1)",
+            "return load('return 1')()",
+        ] {
+            let res = service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+            assert_res_error(res, 400, "Script error");
+        }
+    }
+
+    #[test]
+    fn eval_should_stop_after_time_budget() {
+        let service: Service = Service::new(MemTable::new());
+        let script = r#"hset("t1", "k1", "v1") while true do end"#;
+        let start = Instant::now();
+        let res = service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+        assert_res_error(res, 400, "time budget");
+        assert!(start.elapsed() < SCRIPT_TIMEOUT * 4);
+
+        let res = service.execute(CommandRequest::new_hget("t1", "k1"));
+        assert_res_error(res, 404, "Not found");
+    }
+
+    #[test]
+    fn eval_should_not_catch_time_budget_error() {
+        let service: Service = Service::new(MemTable::new());
+        for script in [
+            "while true do pcall(function() while true do end end) end",
+            "while true do xpcall(function() while true do end end, function() end) end",
+        ] {
+            let start = Instant::now();
+            let res = service.execute(CommandRequest::new_eval(script, vec![], vec![]));
+            assert_res_error(res, 400, "Script error");
+            assert!(start.elapsed() < SCRIPT_TIMEOUT * 4);
+        }
+    }
+}
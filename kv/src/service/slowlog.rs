@@ -0,0 +1,165 @@
+use crate::*;
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// 慢命令日志：执行时间超过阈值的命令放入固定大小的环形缓冲区，满了之后丢弃最旧的
+#[derive(Debug)]
+pub struct SlowLogBuffer {
+    // None表示不记录
+    threshold: Option<Duration>,
+    capacity: usize,
+    inner: Mutex<SlowLogInner>,
+}
+
+#[derive(Debug, Default)]
+struct SlowLogInner {
+    next_id: u64,
+    entries: VecDeque<SlowLogEntry>,
+}
+
+impl SlowLogBuffer {
+    pub fn new(threshold: Option<Duration>, capacity: usize) -> Self {
+        Self {
+            threshold,
+            capacity,
+            inner: Mutex::new(SlowLogInner::default()),
+        }
+    }
+
+    /// 命令是否需要记录
+    pub fn is_slow(&self, elapsed: Duration) -> bool {
+        self.capacity > 0 && self.threshold.is_some_and(|t| elapsed >= t)
+    }
+
+    /// 记录一个执行完的命令，执行时间没有超过阈值时忽略
+    pub(crate) fn record(
+        &self,
+        command: &str,
+        (table, key): (&str, &str),
+        start: SystemTime,
+        elapsed: Duration,
+    ) {
+        if !self.is_slow(elapsed) {
+            return;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        let entry = SlowLogEntry {
+            id: inner.next_id,
+            timestamp: start
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as _,
+            duration_us: elapsed.as_micros() as _,
+            command: command.into(),
+            table: table.into(),
+            key: key.into(),
+        };
+        inner.next_id += 1;
+        if inner.entries.len() == self.capacity {
+            inner.entries.pop_back();
+        }
+        inner.entries.push_front(entry);
+    }
+
+    /// 返回最近的count条日志，最新的在前，count为0时返回全部
+    pub fn get(&self, count: usize) -> Vec<SlowLogEntry> {
+        let inner = self.inner.lock().unwrap();
+        let count = match count {
+            0 => inner.entries.len(),
+            n => n,
+        };
+        inner.entries.iter().take(count).cloned().collect()
+    }
+
+    /// 清空日志，编号继续递增
+    pub fn reset(&self) {
+        self.inner.lock().unwrap().entries.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// 执行SlowLog命令
+    pub(crate) fn execute(&self, param: SlowLog) -> CommandResponse {
+        let entries = self.get(param.count as _);
+        if param.reset {
+            self.reset();
+        }
+        entries.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(log: &SlowLogBuffer, key: &str, elapsed: Duration) {
+        log.record("Hget", ("t1", key), SystemTime::now(), elapsed);
+    }
+
+    #[test]
+    fn slow_log_should_only_record_slow_commands() {
+        let log = SlowLogBuffer::new(Some(Duration::from_millis(10)), 8);
+        record(&log, "fast", Duration::from_millis(1));
+        record(&log, "slow", Duration::from_millis(15));
+        let entries = log.get(0);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].command, "Hget");
+        assert_eq!(entries[0].table, "t1");
+        assert_eq!(entries[0].key, "slow");
+        assert_eq!(entries[0].duration_us, 15000);
+
+        // 没有阈值时不记录
+        let log = SlowLogBuffer::new(None, 8);
+        record(&log, "slow", Duration::from_secs(1));
+        assert!(log.is_empty());
+    }
+
+    #[test]
+    fn slow_log_should_drop_oldest_entries() {
+        let log = SlowLogBuffer::new(Some(Duration::ZERO), 3);
+        for key in ["k1", "k2", "k3", "k4"] {
+            record(&log, key, Duration::from_millis(1));
+        }
+        let keys: Vec<_> = log.get(0).into_iter().map(|e| (e.id, e.key)).collect();
+        assert_eq!(keys, [(3, "k4".into()), (2, "k3".into()), (1, "k2".into())]);
+        assert_eq!(log.get(1)[0].key, "k4");
+
+        // 清空之后编号不会重复
+        log.reset();
+        record(&log, "k5", Duration::from_millis(1));
+        assert_eq!(log.get(0)[0].id, 4);
+    }
+
+    #[test]
+    fn slow_log_command_should_work() {
+        let config = ServiceConfig {
+            slow_log_threshold: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        let service: Service = Service::with_config(MemTable::new(), config);
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        service.execute(CommandRequest::new_zadd("z", vec![]));
+
+        let res = service.execute(CommandRequest::new_slow_log(0, true));
+        assert_eq!(res.status, 200);
+        let commands: Vec<_> = res
+            .slow_logs
+            .iter()
+            .map(|e| (e.command.as_str(), e.table.as_str(), e.key.as_str()))
+            .collect();
+        assert_eq!(commands, [("Zadd", "", "z"), ("Hset", "t1", "k1")]);
+
+        // SlowLog本身不会被记录
+        let res = service.execute(CommandRequest::new_slow_log(0, false));
+        assert!(res.slow_logs.is_empty());
+    }
+}
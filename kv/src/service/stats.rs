@@ -0,0 +1,56 @@
+use dashmap::DashMap;
+use std::time::Duration;
+
+/// 每种命令的执行统计
+#[derive(Debug, Default)]
+pub struct CommandStats {
+    stats: DashMap<&'static str, CommandStat>,
+}
+
+/// 一种命令的执行次数、失败次数和总耗时
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CommandStat {
+    pub calls: u64,
+    /// 返回的状态码不是2xx的次数
+    pub errors: u64,
+    pub total_us: u64,
+}
+
+impl CommandStats {
+    /// 记录一次命令的执行
+    pub(crate) fn record(&self, command: &'static str, status: u32, elapsed: Duration) {
+        let mut stat = self.stats.entry(command).or_default();
+        stat.calls += 1;
+        if !(200..300).contains(&status) {
+            stat.errors += 1;
+        }
+        stat.total_us += elapsed.as_micros() as u64;
+    }
+
+    /// 按命令的名字排序返回所有的统计
+    pub fn snapshot(&self) -> Vec<(&'static str, CommandStat)> {
+        let mut stats: Vec<_> = self.stats.iter().map(|s| (*s.key(), *s.value())).collect();
+        stats.sort_by_key(|(name, _)| *name);
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[test]
+    fn command_stats_should_count_calls_and_errors() {
+        let service: Service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        service.execute(CommandRequest::new_hget("t1", "k1"));
+        service.execute(CommandRequest::new_hget("t1", "k2"));
+
+        let stats: Vec<_> = service
+            .command_stats()
+            .into_iter()
+            .map(|(name, s)| (name, s.calls, s.errors))
+            .collect();
+        assert_eq!(stats, [("Hget", 2, 1), ("Hset", 1, 0)]);
+    }
+}
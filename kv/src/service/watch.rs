@@ -0,0 +1,212 @@
+use crate::command_request::RequestData;
+use crate::*;
+use dashmap::DashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{debug, warn};
+
+/// 每个watcher最多缓存的事件数，客户端消费太慢时新的事件会被丢弃
+const WATCH_CAPACITY: usize = 128;
+
+/// 流式命令的响应
+pub type StreamingResponse = mpsc::Receiver<CommandResponse>;
+
+/// 下一个watcher的id
+static NEXT_ID: AtomicU32 = AtomicU32::new(1);
+
+struct Watcher {
+    table: String,
+    key_prefix: String,
+    tx: mpsc::Sender<CommandResponse>,
+}
+
+impl Watcher {
+    fn matches(&self, event: &WatchEvent) -> bool {
+        self.table == event.table && event.key.starts_with(&self.key_prefix)
+    }
+}
+
+/// 管理所有的watcher，key发生变化时推送给匹配的watcher
+#[derive(Default)]
+pub struct Watchers {
+    watchers: DashMap<u32, Watcher>,
+}
+
+impl Watchers {
+    /// 注册一个watcher，返回的stream第一个响应表示watch已经建立
+    pub fn subscribe(&self, watch: Watch) -> StreamingResponse {
+        let (tx, rx) = mpsc::channel(WATCH_CAPACITY);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        debug!("Watcher {} subscribed: {:?}", id, watch);
+
+        // channel是新建的，一定能放下第一个响应
+        tx.try_send(CommandResponse::ok()).unwrap();
+        self.watchers.insert(
+            id,
+            Watcher {
+                table: watch.table,
+                key_prefix: watch.key_prefix,
+                tx,
+            },
+        );
+        rx
+    }
+
+    /// 当前是否没有watcher
+    pub fn is_empty(&self) -> bool {
+        self.watchers.is_empty()
+    }
+
+    /// 把一次key的变化推送给所有匹配的watcher，清理掉已经断开的watcher
+    pub fn publish(&self, event: WatchEvent) {
+        let mut closed = Vec::new();
+        for watcher in self.watchers.iter() {
+            if !watcher.matches(&event) {
+                continue;
+            }
+
+            match watcher.tx.try_send(event.clone().into()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    warn!("Watcher {} is lagging, event dropped", watcher.key())
+                }
+                Err(TrySendError::Closed(_)) => closed.push(*watcher.key()),
+            }
+        }
+
+        // 不能在遍历时删除，否则DashMap会死锁
+        for id in closed {
+            debug!("Watcher {} unsubscribed", id);
+            self.watchers.remove(&id);
+        }
+    }
+}
+
+impl WatchEvent {
+    /// 从会修改数据的命令中提取出事件，此时还不知道旧的值
+    pub(crate) fn from_request(cmd: &CommandRequest) -> Vec<Self> {
+        match &cmd.request_data {
+            Some(RequestData::Hset(Hset {
+                table,
+                pair: Some(pair),
+            })) => vec![Self::set(table, pair)],
+            Some(RequestData::Hmset(Hmset { table, pairs })) => {
+                pairs.iter().map(|pair| Self::set(table, pair)).collect()
+            }
+            Some(RequestData::Hdel(Hdel { table, key })) => {
+                vec![Self::new(table.as_str(), key.as_str(), "del", None, None)]
+            }
+            _ => vec![],
+        }
+    }
+
+    /// 根据命令的执行结果按顺序补全旧的值，命令失败时不产生事件
+    pub(crate) fn complete(events: Vec<Self>, res: &CommandResponse) -> Vec<Self> {
+        if res.status != 200 {
+            return vec![];
+        }
+
+        events
+            .into_iter()
+            .zip(res.values.iter())
+            .map(|(mut event, old)| {
+                event.old_value = Some(old.clone()).filter(|v| v.value.is_some());
+                event
+            })
+            .collect()
+    }
+
+    fn set(table: &str, pair: &Kvpair) -> Self {
+        let value = pair.value.clone().unwrap_or_default();
+        Self::new(table, pair.key.as_str(), "set", None, Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watch_should_receive_matching_changes() {
+        let service: Service = Service::new(MemTable::new());
+        let mut rx = service.execute_streaming(CommandRequest::new_watch("t1", "user:"));
+        assert_res_ok(rx.try_recv().unwrap(), &[], &[]);
+
+        service.execute(CommandRequest::new_hset("t1", "user:1", "v1".into()));
+        service.execute(CommandRequest::new_hset("t1", "user:1", "v2".into()));
+        service.execute(CommandRequest::new_hdel("t1", "user:1"));
+
+        let events: Vec<_> = (0..3)
+            .map(|_| rx.try_recv().unwrap().events.remove(0))
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                WatchEvent::new("t1", "user:1", "set", None, Some("v1".into())),
+                WatchEvent::new("t1", "user:1", "set", Some("v1".into()), Some("v2".into())),
+                WatchEvent::new("t1", "user:1", "del", Some("v2".into()), None),
+            ]
+        );
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn watch_should_ignore_unmatched_changes() {
+        let service: Service = Service::new(MemTable::new());
+        let mut rx = service.execute_streaming(CommandRequest::new_watch("t1", "user:"));
+        rx.try_recv().unwrap();
+
+        service.execute(CommandRequest::new_hset("t1", "order:1", "v1".into()));
+        service.execute(CommandRequest::new_hset("t2", "user:1", "v1".into()));
+        service.execute(CommandRequest::new_hmset(
+            "t1",
+            vec![Kvpair::new("order:2", "v2".into())],
+        ));
+        // 删除不存在的key不会产生事件
+        service.execute(CommandRequest::new_hdel("t1", "user:2"));
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn dropped_watcher_should_be_removed() {
+        let service: Service = Service::new(MemTable::new());
+        let rx = service.execute_streaming(CommandRequest::new_watch("t1", ""));
+        drop(rx);
+
+        service.execute(CommandRequest::new_hset("t1", "k1", "v1".into()));
+        assert!(service.db().watchers.is_empty());
+    }
+
+    #[test]
+    fn watch_should_not_be_dispatched_directly() {
+        let store = MemTable::new();
+        let res = dispatch(CommandRequest::new_watch("t1", ""), &store);
+        assert_res_error(res, 400, "streaming");
+    }
+
+    #[test]
+    fn hmset_should_publish_event_for_each_pair() {
+        let service: Service = Service::new(MemTable::new());
+        service.execute(CommandRequest::new_hset("t1", "k1", "v0".into()));
+        let mut rx = service.execute_streaming(CommandRequest::new_watch("t1", "k"));
+        rx.try_recv().unwrap();
+
+        let pairs = vec![
+            Kvpair::new("k1", "v1".into()),
+            Kvpair::new("k2", "v2".into()),
+        ];
+        service.execute(CommandRequest::new_hmset("t1", pairs));
+
+        let event = rx.try_recv().unwrap().events.remove(0);
+        assert_eq!(
+            event,
+            WatchEvent::new("t1", "k1", "set", Some("v0".into()), Some("v1".into()))
+        );
+        let event = rx.try_recv().unwrap().events.remove(0);
+        assert_eq!(
+            event,
+            WatchEvent::new("t1", "k2", "set", None, Some("v2".into()))
+        );
+    }
+}
@@ -1,9 +1,247 @@
-use crate::{KvError, Kvpair, Storage, Value};
-use dashmap::{mapref::one::Ref, DashMap};
+use crate::{normalize_range, KvError, Kvpair, SortedSet, Storage, Value};
+use dashmap::{mapref::entry::Entry as MapEntry, DashMap};
+use indexmap::IndexSet;
+use rand::Rng;
+use std::{
+    borrow::Borrow,
+    collections::{HashSet, VecDeque},
+    hash::{Hash, Hasher},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tracing::debug;
 
-#[derive(Clone, Debug, Default)]
+/// 淘汰时每次采样的key数量，和redis一样用采样来近似LRU/LFU
+const EVICTION_SAMPLES: usize = 5;
+
+/// 内存超出限制时淘汰key的策略
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EvictionPolicy {
+    /// 淘汰最久没有被访问的key
+    #[default]
+    Lru,
+    /// 淘汰访问次数最少的key
+    Lfu,
+    /// 随机淘汰
+    Random,
+    /// 不淘汰，超出限制的写入直接失败，用作配额
+    NoEviction,
+}
+
+impl EvictionPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lru => "lru",
+            Self::Lfu => "lfu",
+            Self::Random => "random",
+            Self::NoEviction => "noeviction",
+        }
+    }
+}
+
+impl FromStr for EvictionPolicy {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "lru" => Ok(Self::Lru),
+            "lfu" => Ok(Self::Lfu),
+            "random" => Ok(Self::Random),
+            "noeviction" => Ok(Self::NoEviction),
+            v => Err(KvError::InvalidCommand(format!(
+                "unknown eviction policy: {}",
+                v
+            ))),
+        }
+    }
+}
+
+/// 一个数据库的存储配置
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct StorageConfig {
+    /// 内存上限，None表示不限制
+    pub max_memory: Option<usize>,
+    /// 超出上限时的处理方式
+    pub policy: EvictionPolicy,
+}
+
+/// 格式是`内存上限[:策略]`，比如`67108864:lru`，`unlimited`表示不限制内存
+impl FromStr for StorageConfig {
+    type Err = KvError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (max_memory, policy) = s.split_once(':').unwrap_or((s, "lru"));
+        let max_memory = match max_memory.trim() {
+            "unlimited" => None,
+            v => Some(
+                v.parse()
+                    .map_err(|_| KvError::InvalidCommand(format!("invalid max memory: {}", v)))?,
+            ),
+        };
+        Ok(Self {
+            max_memory,
+            policy: policy.trim().parse()?,
+        })
+    }
+}
+
+/// 所有table的key放在同一个DashMap中，table名字是共享的Arc<str>
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct EntryKey {
+    table: Arc<str>,
+    key: String,
+}
+
+/// 让(&str, &str)可以直接查询EntryKey，避免每次查询都分配内存
+trait AsKey {
+    fn table(&self) -> &str;
+    fn key(&self) -> &str;
+}
+
+impl AsKey for EntryKey {
+    fn table(&self) -> &str {
+        &self.table
+    }
+
+    fn key(&self) -> &str {
+        &self.key
+    }
+}
+
+impl AsKey for (&str, &str) {
+    fn table(&self) -> &str {
+        self.0
+    }
+
+    fn key(&self) -> &str {
+        self.1
+    }
+}
+
+// 必须和EntryKey derive出来的Hash一致
+impl Hash for dyn AsKey + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.table().hash(state);
+        self.key().hash(state);
+    }
+}
+
+impl PartialEq for dyn AsKey + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.table() == other.table() && self.key() == other.key()
+    }
+}
+
+impl Eq for dyn AsKey + '_ {}
+
+impl<'a> Borrow<dyn AsKey + 'a> for EntryKey {
+    fn borrow(&self) -> &(dyn AsKey + 'a) {
+        self
+    }
+}
+
+/// table中存储的值，以及用于淘汰的访问信息
+#[derive(Debug)]
+struct Entry {
+    value: Value,
+    last_access: AtomicU64,
+    hits: AtomicU64,
+}
+
+impl Entry {
+    fn new(value: Value, now: u64) -> Self {
+        Self {
+            value,
+            last_access: AtomicU64::new(now),
+            hits: AtomicU64::new(1),
+        }
+    }
+
+    fn touch(&self, now: u64) {
+        self.last_access.store(now, Ordering::Relaxed);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// 一个key占用的内存，只计算key和value，不计算DashMap本身的开销
+    fn size(key: &str, value: &Value) -> usize {
+        key.len() + value.size()
+    }
+}
+
+/// 有序集合中一个成员占用的内存
+fn member_size(member: &str) -> usize {
+    member.len() + std::mem::size_of::<f64>()
+}
+
+impl Clone for Entry {
+    fn clone(&self) -> Self {
+        Self {
+            value: self.value.clone(),
+            last_access: AtomicU64::new(self.last_access.load(Ordering::Relaxed)),
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// 淘汰时采样到的候选key
+struct Candidate {
+    key: EntryKey,
+    last_access: u64,
+    hits: u64,
+}
+
+impl Candidate {
+    fn new(key: &EntryKey, entry: &Entry) -> Self {
+        Self {
+            key: key.clone(),
+            last_access: entry.last_access.load(Ordering::Relaxed),
+            hits: entry.hits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 内存存储
+/// 所有table的数据放在同一个按(table, key)分片的DashMap中，get/set只需要锁一个分片，
+/// 大量客户端同时访问少数几个热点table时不会都竞争同一把锁。
+/// 每个table另外保存一份key的索引，get_all和淘汰时的采样不需要遍历所有的key。
+/// 有序集合和列表各自用一个DashMap保存，它们计入内存用量，但不会被淘汰，
+/// 所以它们自己占用的内存不能超过上限，超过时写入失败。
+#[derive(Debug, Default)]
 pub struct MemTable {
-    tables: DashMap<String, DashMap<String, Value>>,
+    entries: DashMap<EntryKey, Entry>,
+    // table名字到它的key，只在新增和删除key的时候修改
+    // 加锁顺序总是先entries再tables，读tables时不能同时持有entries的锁
+    tables: DashMap<Arc<str>, IndexSet<String>>,
+    max_memory: Option<usize>,
+    policy: EvictionPolicy,
+    // 逻辑时钟，每次访问加一，用于LRU，没有内存限制时不使用
+    clock: AtomicU64,
+    used_memory: AtomicUsize,
+    // 有序集合和列表占用的内存，包含在used_memory中
+    fixed_memory: AtomicUsize,
+    evicted_keys: AtomicU64,
+    zsets: DashMap<String, SortedSet>,
+    lists: DashMap<String, VecDeque<Value>>,
+}
+
+impl Clone for MemTable {
+    fn clone(&self) -> Self {
+        Self {
+            entries: self.entries.clone(),
+            tables: self.tables.clone(),
+            max_memory: self.max_memory,
+            policy: self.policy,
+            clock: AtomicU64::new(self.clock.load(Ordering::Relaxed)),
+            used_memory: AtomicUsize::new(self.used_memory()),
+            fixed_memory: AtomicUsize::new(self.fixed_memory.load(Ordering::Relaxed)),
+            evicted_keys: AtomicU64::new(self.evicted_keys()),
+            zsets: self.zsets.clone(),
+            lists: self.lists.clone(),
+        }
+    }
 }
 
 impl MemTable {
@@ -11,21 +249,245 @@ impl MemTable {
         Self::default()
     }
 
-    fn get_or_create_table(&self, name: &str) -> Ref<String, DashMap<String, Value>> {
-        match self.tables.get(name) {
-            Some(table) => table,
+    /// 创建一个有内存上限的MemTable，超出上限时按policy淘汰key
+    pub fn with_memory_limit(max_memory: usize, policy: EvictionPolicy) -> Self {
+        Self {
+            max_memory: Some(max_memory),
+            policy,
+            ..Default::default()
+        }
+    }
+
+    /// 按配置创建MemTable
+    pub fn with_config(config: StorageConfig) -> Self {
+        Self {
+            max_memory: config.max_memory,
+            policy: config.policy,
+            ..Default::default()
+        }
+    }
+
+    /// 当前key和value占用的内存
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.load(Ordering::Relaxed)
+    }
+
+    /// 被淘汰的key的数量
+    pub fn evicted_keys(&self) -> u64 {
+        self.evicted_keys.load(Ordering::Relaxed)
+    }
+
+    /// 从table的索引中删除key，没有key的table会被移除
+    fn unindex(&self, key: &EntryKey) {
+        if let Some(mut keys) = self.tables.get_mut(&*key.table) {
+            keys.swap_remove(&key.key);
+        }
+        self.tables
+            .remove_if(&*key.table, |_, keys| keys.is_empty());
+    }
+
+    /// 取出table中所有的key，返回之前释放tables的锁
+    fn table_keys(&self, table: &str) -> Vec<EntryKey> {
+        self.tables.get(table).map_or_else(Vec::new, |keys| {
+            keys.iter()
+                .map(|key| EntryKey {
+                    table: keys.key().clone(),
+                    key: key.clone(),
+                })
+                .collect()
+        })
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn remove(&self, table: &str, key: &str) -> Option<(EntryKey, Entry)> {
+        // 持有entries分片的锁时更新索引，和set的加锁顺序一致
+        let (k, e) = self
+            .entries
+            .remove_if(&(table, key) as &dyn AsKey, |k, _| {
+                self.unindex(k);
+                true
+            })?;
+        self.used_memory
+            .fetch_sub(Entry::size(&k.key, &e.value), Ordering::Relaxed);
+        Some((k, e))
+    }
+
+    fn evict_if_needed(&self) {
+        let max_memory = match self.max_memory {
+            Some(v) => v,
+            None => return,
+        };
+
+        while self.used_memory() > max_memory {
+            let victim = match self.pick_victim() {
+                Some(v) => v,
+                None => break,
+            };
+
+            if let Some((key, _)) = self.remove(&victim.key.table, &victim.key.key) {
+                debug!("Evicted key: {} from table: {}", key.key, key.table);
+                self.evicted_keys.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// 增加内存用量，不淘汰key时超出限制直接失败，用量检查和增加是原子的
+    fn reserve(&self, size: usize, what: impl FnOnce() -> String) -> Result<(), KvError> {
+        match (self.max_memory, self.policy) {
+            (Some(max_memory), EvictionPolicy::NoEviction) => self
+                .used_memory
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                    (used + size <= max_memory).then_some(used + size)
+                })
+                .map(|_| ())
+                .map_err(|used| {
+                    KvError::QuotaExceeded(format!(
+                        "{}, used memory {} would exceed max memory {}",
+                        what(),
+                        used + size,
+                        max_memory
+                    ))
+                }),
+            _ => {
+                self.used_memory.fetch_add(size, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+
+    /// 增加有序集合和列表的内存用量，它们不会被淘汰，超出上限时即使可以淘汰key也直接失败
+    fn reserve_fixed(&self, size: usize, what: impl Fn() -> String) -> Result<(), KvError> {
+        match self.max_memory {
+            Some(max_memory) => self
+                .fixed_memory
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |fixed| {
+                    (fixed + size <= max_memory).then_some(fixed + size)
+                })
+                .map(|_| ())
+                .map_err(|fixed| {
+                    KvError::QuotaExceeded(format!(
+                        "{}, memory of zsets and lists {} would exceed max memory {}",
+                        what(),
+                        fixed + size,
+                        max_memory
+                    ))
+                })?,
             None => {
-                let entry = self.tables.entry(name.into()).or_default();
-                entry.downgrade()
+                self.fixed_memory.fetch_add(size, Ordering::Relaxed);
             }
         }
+        self.reserve(size, &what).inspect_err(|_| {
+            self.fixed_memory.fetch_sub(size, Ordering::Relaxed);
+        })
+    }
+
+    /// 删除有序集合的成员或者列表中的值之后减少内存用量
+    fn release_fixed(&self, size: usize) {
+        self.fixed_memory.fetch_sub(size, Ordering::Relaxed);
+        self.used_memory.fetch_sub(size, Ordering::Relaxed);
+    }
+
+    // 往列表中插入值，先按值的大小增加内存用量
+    fn push(
+        &self,
+        key: &str,
+        values: Vec<Value>,
+        f: impl FnOnce(&mut VecDeque<Value>, Vec<Value>),
+    ) -> Result<usize, KvError> {
+        // 不创建空的列表
+        if values.is_empty() {
+            return Ok(self.lists.get(key).map_or(0, |list| list.len()));
+        }
+        let mut list = self.lists.entry(key.into()).or_default();
+        // 空的列表会被删除，所以空列表一定是刚创建的
+        let created = list.is_empty();
+        let size =
+            values.iter().map(|v| v.size()).sum::<usize>() + if created { key.len() } else { 0 };
+        if let Err(e) = self.reserve_fixed(size, || format!("list: {}", key)) {
+            drop(list);
+            self.lists.remove_if(key, |_, list| list.is_empty());
+            return Err(e);
+        }
+
+        f(&mut list, values);
+        let len = list.len();
+        drop(list);
+        self.evict_if_needed();
+        Ok(len)
+    }
+
+    /// 按照淘汰策略选出一个要淘汰的key
+    fn pick_victim(&self) -> Option<Candidate> {
+        if self.policy == EvictionPolicy::NoEviction {
+            return None;
+        }
+        let candidates = self.sample();
+        match self.policy {
+            EvictionPolicy::Lru => candidates.into_iter().min_by_key(|c| c.last_access),
+            EvictionPolicy::Lfu => candidates
+                .into_iter()
+                .min_by_key(|c| (c.hits, c.last_access)),
+            EvictionPolicy::Random => candidates.into_iter().next(),
+            EvictionPolicy::NoEviction => None,
+        }
+    }
+
+    /// 随机采样EVICTION_SAMPLES个key，key不多时直接返回全部
+    /// 先按key的数量加权选出table，再从table的索引中按下标取key，不需要遍历entries
+    fn sample(&self) -> Vec<Candidate> {
+        let sizes: Vec<_> = self
+            .tables
+            .iter()
+            .map(|t| (t.key().clone(), t.len()))
+            .collect();
+        let total: usize = sizes.iter().map(|(_, len)| len).sum();
+
+        let keys = if total <= EVICTION_SAMPLES {
+            sizes
+                .iter()
+                .flat_map(|(table, _)| self.table_keys(table))
+                .collect()
+        } else {
+            let mut rng = rand::thread_rng();
+            (0..EVICTION_SAMPLES)
+                .filter_map(|_| {
+                    let mut n = rng.gen_range(0..total);
+                    let (table, _) = sizes.iter().find(|(_, len)| {
+                        if n < *len {
+                            return true;
+                        }
+                        n -= len;
+                        false
+                    })?;
+                    let keys = self.tables.get(table)?;
+                    // 采样期间可能有key被删除，取不到就跳过
+                    let key = keys.get_index(n)?.clone();
+                    Some(EntryKey {
+                        table: table.clone(),
+                        key,
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+
+        keys.iter()
+            .filter_map(|key| self.entries.get(key))
+            .map(|item| Candidate::new(item.key(), item.value()))
+            .collect()
     }
 }
 
 impl Storage for MemTable {
     fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.get(key).map(|v| v.value().clone()))
+        Ok(self.entries.get(&(table, key) as &dyn AsKey).map(|v| {
+            if self.max_memory.is_some() {
+                v.touch(self.tick());
+            }
+            v.value.clone()
+        }))
     }
 
     fn set(
@@ -34,29 +496,472 @@ impl Storage for MemTable {
         key: impl Into<String>,
         value: impl Into<Value>,
     ) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.insert(key.into(), value.into()))
+        let key = key.into();
+        let value = value.into();
+        let size = Entry::size(&key, &value);
+        if let Some(max_memory) = self.max_memory {
+            if size > max_memory {
+                return Err(KvError::StorageErrr(
+                    "set",
+                    table.into(),
+                    key,
+                    format!("entry size {} exceeds max memory {}", size, max_memory),
+                ));
+            }
+            // 有序集合和列表不会被淘汰，淘汰所有的key也放不下时直接失败
+            let fixed = self.fixed_memory.load(Ordering::Relaxed);
+            if self.policy != EvictionPolicy::NoEviction && fixed + size > max_memory {
+                return Err(KvError::QuotaExceeded(format!(
+                    "table: {}, key: {}, memory of zsets and lists {} leaves no room for {}",
+                    table, key, fixed, size
+                )));
+            }
+        }
+
+        let now = match self.max_memory {
+            Some(_) => self.tick(),
+            None => 0,
+        };
+        let what = || format!("table: {}, key: {}", table, key);
+
+        // 大部分的set是更新已有的key，直接替换，不需要访问tables
+        // 持有entry的锁时按新旧值的差调整用量，同一个key的并发写入不会重复计算
+        let old = match self.entries.get_mut(&(table, key.as_str()) as &dyn AsKey) {
+            Some(mut entry) => {
+                let old_size = Entry::size(&key, &entry.value);
+                self.reserve(size.saturating_sub(old_size), what)?;
+                self.used_memory
+                    .fetch_sub(old_size.saturating_sub(size), Ordering::Relaxed);
+                Some(std::mem::replace(entry.value_mut(), Entry::new(value, now)))
+            }
+            None => {
+                self.reserve(size, what)?;
+                let table = self
+                    .tables
+                    .get(table)
+                    .map_or_else(|| Arc::from(table), |t| t.key().clone());
+                match self.entries.entry(EntryKey { table, key }) {
+                    // 并发插入同一个key时，只有一个算作新增
+                    MapEntry::Occupied(mut entry) => {
+                        let old = entry.insert(Entry::new(value, now));
+                        self.used_memory.fetch_sub(
+                            Entry::size(&entry.key().key, &old.value),
+                            Ordering::Relaxed,
+                        );
+                        Some(old)
+                    }
+                    // 持有entries分片的锁时更新索引，和remove的加锁顺序一致
+                    MapEntry::Vacant(entry) => {
+                        let EntryKey { table, key } = entry.key().clone();
+                        self.tables.entry(table).or_default().insert(key);
+                        entry.insert(Entry::new(value, now));
+                        None
+                    }
+                }
+            }
+        };
+        self.evict_if_needed();
+
+        Ok(old.map(|e| e.value))
     }
 
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.contains_key(key))
+        Ok(self.entries.contains_key(&(table, key) as &dyn AsKey))
     }
 
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table.remove(key).map(|(_k, v)| v))
+        Ok(self.remove(table, key).map(|(_, e)| e.value))
+    }
+
+    fn get_tables(&self) -> Result<Vec<String>, KvError> {
+        Ok(self.tables.iter().map(|t| t.key().to_string()).collect())
     }
 
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
-        let table = self.get_or_create_table(table);
-        Ok(table
+        // 先从索引中取出table的key，再逐个读取，读取期间被删除的key会被跳过
+        Ok(self
+            .table_keys(table)
             .iter()
-            .map(|v| Kvpair::new(v.key(), v.value().clone()))
+            .filter_map(|k| {
+                let v = self.entries.get(k)?;
+                Some(Kvpair::new(k.key.as_str(), v.value.clone()))
+            })
             .collect())
     }
 
-    fn get_iter(&self, _table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
-        todo!()
+    fn get_keys(&self, table: &str) -> Result<Vec<String>, KvError> {
+        Ok(self
+            .tables
+            .get(table)
+            .map_or_else(Vec::new, |keys| keys.iter().cloned().collect()))
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        // DashMap的迭代器持有分片的锁，不能直接返回，先收集起来
+        Ok(Box::new(self.get_all(table)?.into_iter()))
+    }
+
+    fn info(&self) -> Result<Vec<Kvpair>, KvError> {
+        Ok(vec![
+            Kvpair::new("used_memory", (self.used_memory() as i64).into()),
+            Kvpair::new("max_memory", (self.max_memory.unwrap_or(0) as i64).into()),
+            Kvpair::new("eviction_policy", self.policy.as_str().into()),
+            Kvpair::new("evicted_keys", (self.evicted_keys() as i64).into()),
+            Kvpair::new("tables", (self.tables.len() as i64).into()),
+            Kvpair::new("keys", (self.entries.len() as i64).into()),
+            Kvpair::new("zsets", (self.zsets.len() as i64).into()),
+            Kvpair::new("lists", (self.lists.len() as i64).into()),
+        ])
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        // 数据都在内存中，不需要持久化
+        Ok(())
+    }
+
+    fn zadd(&self, key: &str, members: Vec<(String, f64)>) -> Result<usize, KvError> {
+        if members.is_empty() {
+            return Ok(0);
+        }
+        let mut set = self.zsets.entry(key.into()).or_default();
+        // 空的有序集合会被删除，所以空集合一定是刚创建的
+        let created = set.is_empty();
+        let new: HashSet<_> = members
+            .iter()
+            .map(|(member, _)| member.as_str())
+            .filter(|member| !set.contains(member))
+            .collect();
+        let size =
+            new.iter().map(|m| member_size(m)).sum::<usize>() + if created { key.len() } else { 0 };
+        if let Err(e) = self.reserve_fixed(size, || format!("zset: {}", key)) {
+            drop(set);
+            self.zsets.remove_if(key, |_, set| set.is_empty());
+            return Err(e);
+        }
+
+        let added = members
+            .into_iter()
+            .filter(|(member, score)| set.insert(member.clone(), *score))
+            .count();
+        drop(set);
+        self.evict_if_needed();
+        Ok(added)
+    }
+
+    fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(String, f64)>, KvError> {
+        Ok(self
+            .zsets
+            .get(key)
+            .map(|set| set.range(start, stop))
+            .unwrap_or_default())
+    }
+
+    fn zrank(&self, key: &str, member: &str) -> Result<Option<usize>, KvError> {
+        Ok(self.zsets.get(key).and_then(|set| set.rank(member)))
+    }
+
+    fn zset_keys(&self) -> Result<Vec<String>, KvError> {
+        Ok(self.zsets.iter().map(|set| set.key().clone()).collect())
+    }
+
+    fn zrem(&self, key: &str, members: &[String]) -> Result<usize, KvError> {
+        let mut freed = 0;
+        let removed = match self.zsets.get_mut(key) {
+            Some(mut set) => members
+                .iter()
+                .filter(|m| set.remove(m))
+                .inspect(|m| freed += member_size(m))
+                .count(),
+            None => return Ok(0),
+        };
+        // 空的有序集合直接删除
+        if self.zsets.remove_if(key, |_, set| set.is_empty()).is_some() {
+            freed += key.len();
+        }
+        self.release_fixed(freed);
+        Ok(removed)
+    }
+
+    fn lpush(&self, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        self.push(key, values, |list, values| {
+            for value in values {
+                list.push_front(value);
+            }
+        })
+    }
+
+    fn rpush(&self, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        self.push(key, values, |list, values| list.extend(values))
+    }
+
+    fn rpop(&self, key: &str) -> Result<Option<Value>, KvError> {
+        let value = match self.lists.get_mut(key) {
+            Some(mut list) => list.pop_back(),
+            None => return Ok(None),
+        };
+        let mut freed = value.as_ref().map_or(0, |v| v.size());
+        // 空的列表直接删除
+        if self
+            .lists
+            .remove_if(key, |_, list| list.is_empty())
+            .is_some()
+        {
+            freed += key.len();
+        }
+        self.release_fixed(freed);
+        Ok(value)
+    }
+
+    fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Value>, KvError> {
+        Ok(match self.lists.get(key) {
+            Some(list) => list
+                .range(normalize_range(start, stop, list.len()))
+                .cloned()
+                .collect(),
+            None => vec![],
+        })
+    }
+
+    fn list_keys(&self) -> Result<Vec<String>, KvError> {
+        Ok(self.lists.iter().map(|list| list.key().clone()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry_size(key: &str, value: impl Into<Value>) -> usize {
+        Entry::size(key, &value.into())
+    }
+
+    #[test]
+    fn used_memory_should_be_tracked() {
+        let store = MemTable::new();
+        store.set("t1", "k1", "hello").unwrap();
+        store.set("t1", "k2", 10).unwrap();
+        assert_eq!(
+            store.used_memory(),
+            entry_size("k1", "hello") + entry_size("k2", 10)
+        );
+
+        // 覆盖旧的值会扣掉旧值的大小
+        store.set("t1", "k1", "hello world").unwrap();
+        assert_eq!(
+            store.used_memory(),
+            entry_size("k1", "hello world") + entry_size("k2", 10)
+        );
+
+        store.del("t1", "k1").unwrap();
+        store.del("t1", "k2").unwrap();
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn binary_value_size_should_be_counted() {
+        let store = MemTable::new();
+        let value = Value::from(bytes::Bytes::from(vec![0u8; 1024]));
+        store.set("t1", "k1", value).unwrap();
+        assert!(store.used_memory() > 1024);
+    }
+
+    #[test]
+    fn lru_should_evict_least_recently_used_key() {
+        let size = entry_size("k1", 1);
+        let store = MemTable::with_memory_limit(size * 2, EvictionPolicy::Lru);
+        store.set("t1", "k1", 1).unwrap();
+        store.set("t2", "k2", 2).unwrap();
+        store.get("t1", "k1").unwrap();
+
+        store.set("t1", "k3", 3).unwrap();
+        assert_eq!(store.evicted_keys(), 1);
+        assert_eq!(store.contains("t1", "k1"), Ok(true));
+        assert_eq!(store.contains("t2", "k2"), Ok(false));
+        assert_eq!(store.contains("t1", "k3"), Ok(true));
+    }
+
+    #[test]
+    fn lfu_should_evict_least_frequently_used_key() {
+        let size = entry_size("k1", 1);
+        let store = MemTable::with_memory_limit(size * 2, EvictionPolicy::Lfu);
+        store.set("t1", "k1", 1).unwrap();
+        store.set("t1", "k2", 2).unwrap();
+        for _ in 0..3 {
+            store.get("t1", "k1").unwrap();
+        }
+
+        // k2和k3访问次数一样，淘汰更旧的k2
+        store.set("t1", "k3", 3).unwrap();
+        assert_eq!(store.evicted_keys(), 1);
+        assert_eq!(store.contains("t1", "k1"), Ok(true));
+        assert_eq!(store.contains("t1", "k2"), Ok(false));
+        assert_eq!(store.contains("t1", "k3"), Ok(true));
+    }
+
+    #[test]
+    fn random_should_keep_memory_under_limit() {
+        let size = entry_size("k1", 1);
+        let store = MemTable::with_memory_limit(size * 10, EvictionPolicy::Random);
+        for i in 0..100 {
+            store.set("t1", format!("k{}", i % 10), i as i64).unwrap();
+            store.set("t2", format!("k{}", i % 10), i as i64).unwrap();
+        }
+        assert!(store.used_memory() <= size * 10);
+        assert!(store.evicted_keys() > 0);
+    }
+
+    #[test]
+    fn no_eviction_should_reject_writes_over_quota() {
+        let size = entry_size("k1", 1);
+        let store = MemTable::with_memory_limit(size * 2, EvictionPolicy::NoEviction);
+        store.set("t1", "k1", 1).unwrap();
+        store.set("t1", "k2", 2).unwrap();
+        let res = store.set("t1", "k3", 3);
+        assert!(matches!(res, Err(KvError::QuotaExceeded(_))));
+        assert_eq!(store.contains("t1", "k3"), Ok(false));
+        assert_eq!(store.evicted_keys(), 0);
+
+        // 覆盖同样大小的值不会增加用量，删除之后可以再写入
+        store.set("t1", "k1", 10).unwrap();
+        store.del("t1", "k2").unwrap();
+        store.set("t1", "k3", 3).unwrap();
+        assert_eq!(store.used_memory(), size * 2);
+    }
+
+    #[test]
+    fn no_eviction_quota_should_hold_under_concurrent_writes() {
+        let size = entry_size("k00", 1);
+        let store = Arc::new(MemTable::with_memory_limit(
+            size * 10,
+            EvictionPolicy::NoEviction,
+        ));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    (0..10)
+                        .filter(|i| store.set("t1", format!("k{}{}", t, i), 1).is_ok())
+                        .count()
+                })
+            })
+            .collect();
+        let written: usize = handles.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!(written, 10);
+        assert_eq!(store.used_memory(), size * 10);
+    }
+
+    #[test]
+    fn table_index_should_match_entries_under_concurrent_writes() {
+        let size = entry_size("k00", 1);
+        let store = Arc::new(MemTable::with_memory_limit(size * 20, EvictionPolicy::Lru));
+        let handles: Vec<_> = (0..4)
+            .map(|t| {
+                let store = store.clone();
+                std::thread::spawn(move || {
+                    for i in 0..1000 {
+                        let table = format!("t{}", i % 3);
+                        store.set(&table, format!("k{}", i % 50), 1).unwrap();
+                        if (i + t) % 7 == 0 {
+                            store.del(&table, &format!("k{}", i % 50)).unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles.into_iter().for_each(|h| h.join().unwrap());
+
+        let indexed: usize = store.tables.iter().map(|t| t.len()).sum();
+        assert_eq!(indexed, store.entries.len());
+        let all: usize = (0..3)
+            .map(|i| store.get_all(&format!("t{}", i)).unwrap().len())
+            .sum();
+        assert_eq!(all, store.entries.len());
+        assert!(store.used_memory() <= size * 20);
+    }
+
+    #[test]
+    fn entry_larger_than_max_memory_should_be_rejected() {
+        let store = MemTable::with_memory_limit(8, EvictionPolicy::Lru);
+        assert!(store.set("t1", "k1", "hello world").is_err());
+        assert_eq!(store.used_memory(), 0);
+    }
+
+    #[test]
+    fn zsets_and_lists_should_count_memory() {
+        let store = MemTable::new();
+        store
+            .zadd("z1", vec![("m1".into(), 1.0), ("m1".into(), 2.0)])
+            .unwrap();
+        store.lpush("l1", vec!["hello".into()]).unwrap();
+        let size = "z1".len() + member_size("m1") + "l1".len() + Value::from("hello").size();
+        assert_eq!(store.used_memory(), size);
+
+        // 更新score不增加用量
+        store.zadd("z1", vec![("m1".into(), 3.0)]).unwrap();
+        assert_eq!(store.used_memory(), size);
+
+        store.zrem("z1", &["m1".into()]).unwrap();
+        store.rpop("l1").unwrap();
+        assert_eq!(store.used_memory(), 0);
+
+        let info = store.info().unwrap();
+        assert!(info.contains(&Kvpair::new("zsets", 0.into())));
+        assert!(info.contains(&Kvpair::new("lists", 0.into())));
+    }
+
+    #[test]
+    fn no_eviction_should_reject_zsets_and_lists_over_quota() {
+        let store = MemTable::with_memory_limit(16, EvictionPolicy::NoEviction);
+        let res = store.lpush("l1", vec![Value::from("x".repeat(32))]);
+        assert!(matches!(res, Err(KvError::QuotaExceeded(_))));
+        let res = store.zadd("z1", vec![("m".repeat(32), 1.0)]);
+        assert!(matches!(res, Err(KvError::QuotaExceeded(_))));
+
+        // 失败时不留下空的有序集合和列表
+        assert_eq!(store.used_memory(), 0);
+        assert!(store.zset_keys().unwrap().is_empty());
+        assert!(store.list_keys().unwrap().is_empty());
+    }
+
+    #[test]
+    fn zsets_and_lists_should_stay_under_limit_with_eviction() {
+        let size = entry_size("k1", 1);
+        let max_memory = size * 4;
+        let store = MemTable::with_memory_limit(max_memory, EvictionPolicy::Lru);
+        store.set("t1", "k1", 1).unwrap();
+        store.set("t1", "k2", 2).unwrap();
+
+        // 列表可以淘汰hash key腾出空间，但不能超过上限
+        let res = loop {
+            if let Err(e) = store.rpush("l1", vec![1.into()]) {
+                break e;
+            }
+        };
+        assert!(matches!(res, KvError::QuotaExceeded(_)));
+        assert!(store.used_memory() <= max_memory);
+        assert_eq!(store.contains("t1", "k1"), Ok(false));
+        assert_eq!(store.contains("t1", "k2"), Ok(false));
+
+        // 剩下的空间放不下新的key
+        let res = store.set("t1", "k3", 3);
+        assert!(matches!(res, Err(KvError::QuotaExceeded(_))));
+        assert!(store.used_memory() <= max_memory);
+
+        // 删除列表中的值之后可以再写入
+        while store.rpop("l1").unwrap().is_some() {}
+        assert_eq!(store.used_memory(), 0);
+        store.set("t1", "k3", 3).unwrap();
+        assert_eq!(store.used_memory(), size);
+    }
+
+    #[test]
+    fn zsets_and_lists_should_trigger_eviction() {
+        let size = entry_size("k1", 1);
+        let store = MemTable::with_memory_limit(size * 2, EvictionPolicy::Lru);
+        store.set("t1", "k1", 1).unwrap();
+        store.set("t1", "k2", 2).unwrap();
+        store.rpush("l1", vec![1.into()]).unwrap();
+        assert!(store.evicted_keys() > 0);
+        assert!(store.used_memory() <= size * 2);
     }
 }
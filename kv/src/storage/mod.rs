@@ -1,5 +1,9 @@
 mod memery;
-pub use memery::MemTable;
+#[cfg(test)]
+mod model;
+mod sorted_set;
+pub use memery::{EvictionPolicy, MemTable, StorageConfig};
+pub use sorted_set::{normalize_range, SortedSet};
 
 use crate::{KvError, Kvpair, Value};
 
@@ -18,10 +22,39 @@ pub trait Storage {
     fn contains(&self, table: &str, key: &str) -> Result<bool, KvError>;
     /// 从HashTable删除一个key
     fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError>;
+    /// 返回所有HashTable的名字
+    fn get_tables(&self) -> Result<Vec<String>, KvError>;
     /// 遍历HashTable，返回所有kv pair（not good）
     fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError>;
+    /// 返回HashTable中所有的key，没有顺序
+    fn get_keys(&self, table: &str) -> Result<Vec<String>, KvError>;
     /// 遍历HashTable，返回kv pair的Iterator
     fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError>;
+    /// 返回存储的状态，比如使用的内存和淘汰的key数量
+    fn info(&self) -> Result<Vec<Kvpair>, KvError>;
+    /// 把还没有持久化的数据写入存储，服务器关闭前会调用
+    fn flush(&self) -> Result<(), KvError>;
+
+    /// 往有序集合里添加成员，已经存在的成员更新score，返回新增的成员数量
+    fn zadd(&self, key: &str, members: Vec<(String, f64)>) -> Result<usize, KvError>;
+    /// 按排名返回有序集合中[start, stop]之间的成员和score，负数表示从后往前数
+    fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(String, f64)>, KvError>;
+    /// 返回成员在有序集合中的排名，从0开始
+    fn zrank(&self, key: &str, member: &str) -> Result<Option<usize>, KvError>;
+    /// 返回所有有序集合的key
+    fn zset_keys(&self) -> Result<Vec<String>, KvError>;
+    /// 从有序集合中删除成员，返回删除的数量
+    fn zrem(&self, key: &str, members: &[String]) -> Result<usize, KvError>;
+    /// 从列表的左边依次插入，返回插入后列表的长度
+    fn lpush(&self, key: &str, values: Vec<Value>) -> Result<usize, KvError>;
+    /// 从列表的右边依次插入，返回插入后列表的长度
+    fn rpush(&self, key: &str, values: Vec<Value>) -> Result<usize, KvError>;
+    /// 从列表的右边取出一个值
+    fn rpop(&self, key: &str) -> Result<Option<Value>, KvError>;
+    /// 返回列表中[start, stop]之间的值，负数表示从后往前数
+    fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Value>, KvError>;
+    /// 返回所有列表的key
+    fn list_keys(&self) -> Result<Vec<String>, KvError>;
 }
 
 #[cfg(test)]
@@ -40,11 +73,11 @@ mod tests {
         test_get_all(store);
     }
 
-    // #[test]
-    // fn memtable_get_iter_should_work() {
-    //     let store = MemTable::new();
-    //     test_get_iter(store);
-    // }
+    #[test]
+    fn memtable_get_iter_should_work() {
+        let store = MemTable::new();
+        test_get_iter(store);
+    }
 
     fn test_basic_interface(store: impl Storage) {
         // 第一次set会创建table，插入key并返回None（之前没值）
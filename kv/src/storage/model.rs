@@ -0,0 +1,291 @@
+//! 基于模型的测试：随机生成一串命令，同时在Storage的实现和用BTreeMap实现的参考模型上执行，
+//! 每一步的响应都必须相同。新的Storage实现只需要在下面的proptest!中加一个测试
+
+use crate::{command_request::RequestData, *};
+use bytes::Bytes;
+use proptest::{collection::vec, prelude::*};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+};
+
+/// 参考模型，用最直接的方式实现Storage，不考虑性能
+#[derive(Debug, Default)]
+pub struct ModelStore {
+    inner: Mutex<ModelData>,
+}
+
+#[derive(Debug, Default)]
+struct ModelData {
+    tables: BTreeMap<String, BTreeMap<String, Value>>,
+    // 有序集合中的成员按插入顺序保存，读取的时候再排序
+    zsets: BTreeMap<String, Vec<(String, f64)>>,
+    lists: BTreeMap<String, VecDeque<Value>>,
+}
+
+impl Storage for ModelStore {
+    fn get(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let data = self.inner.lock().unwrap();
+        Ok(data.tables.get(table).and_then(|t| t.get(key)).cloned())
+    }
+
+    fn set(
+        &self,
+        table: &str,
+        key: impl Into<String>,
+        value: impl Into<Value>,
+    ) -> Result<Option<Value>, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        let table = data.tables.entry(table.into()).or_default();
+        Ok(table.insert(key.into(), value.into()))
+    }
+
+    fn contains(&self, table: &str, key: &str) -> Result<bool, KvError> {
+        Ok(self.get(table, key)?.is_some())
+    }
+
+    fn del(&self, table: &str, key: &str) -> Result<Option<Value>, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        let old = data.tables.get_mut(table).and_then(|t| t.remove(key));
+        // 没有key的table不再出现在get_tables中
+        if data.tables.get(table).is_some_and(|t| t.is_empty()) {
+            data.tables.remove(table);
+        }
+        Ok(old)
+    }
+
+    fn get_tables(&self) -> Result<Vec<String>, KvError> {
+        Ok(self.inner.lock().unwrap().tables.keys().cloned().collect())
+    }
+
+    fn get_all(&self, table: &str) -> Result<Vec<Kvpair>, KvError> {
+        let data = self.inner.lock().unwrap();
+        Ok(data
+            .tables
+            .get(table)
+            .map(|t| {
+                t.iter()
+                    .map(|(k, v)| Kvpair::new(k.as_str(), v.clone()))
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    fn get_keys(&self, table: &str) -> Result<Vec<String>, KvError> {
+        let data = self.inner.lock().unwrap();
+        Ok(data
+            .tables
+            .get(table)
+            .map(|t| t.keys().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    fn get_iter(&self, table: &str) -> Result<Box<dyn Iterator<Item = Kvpair>>, KvError> {
+        Ok(Box::new(self.get_all(table)?.into_iter()))
+    }
+
+    fn info(&self) -> Result<Vec<Kvpair>, KvError> {
+        Ok(vec![])
+    }
+
+    fn flush(&self) -> Result<(), KvError> {
+        Ok(())
+    }
+
+    fn zadd(&self, key: &str, members: Vec<(String, f64)>) -> Result<usize, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        let mut added = 0;
+        for (member, score) in members {
+            let set = data.zsets.entry(key.into()).or_default();
+            match set.iter_mut().find(|(m, _)| *m == member) {
+                Some(old) => old.1 = score,
+                None => {
+                    set.push((member, score));
+                    added += 1;
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(String, f64)>, KvError> {
+        Ok(select(&self.sorted(key), start, stop))
+    }
+
+    fn zrank(&self, key: &str, member: &str) -> Result<Option<usize>, KvError> {
+        Ok(self.sorted(key).iter().position(|(m, _)| m == member))
+    }
+
+    fn zset_keys(&self) -> Result<Vec<String>, KvError> {
+        Ok(self.inner.lock().unwrap().zsets.keys().cloned().collect())
+    }
+
+    fn zrem(&self, key: &str, members: &[String]) -> Result<usize, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        let set = match data.zsets.get_mut(key) {
+            Some(set) => set,
+            None => return Ok(0),
+        };
+        let len = set.len();
+        set.retain(|(m, _)| !members.contains(m));
+        let removed = len - set.len();
+        if set.is_empty() {
+            data.zsets.remove(key);
+        }
+        Ok(removed)
+    }
+
+    fn lpush(&self, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        let list = data.lists.entry(key.into()).or_default();
+        for value in values {
+            list.push_front(value);
+        }
+        Ok(list.len())
+    }
+
+    fn rpush(&self, key: &str, values: Vec<Value>) -> Result<usize, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        let list = data.lists.entry(key.into()).or_default();
+        list.extend(values);
+        Ok(list.len())
+    }
+
+    fn rpop(&self, key: &str) -> Result<Option<Value>, KvError> {
+        let mut data = self.inner.lock().unwrap();
+        Ok(data.lists.get_mut(key).and_then(|list| list.pop_back()))
+    }
+
+    fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Value>, KvError> {
+        let data = self.inner.lock().unwrap();
+        let list: Vec<_> = data.lists.get(key).into_iter().flatten().cloned().collect();
+        Ok(select(&list, start, stop))
+    }
+
+    fn list_keys(&self) -> Result<Vec<String>, KvError> {
+        let data = self.inner.lock().unwrap();
+        // 空的列表相当于不存在
+        Ok(data
+            .lists
+            .iter()
+            .filter(|(_, list)| !list.is_empty())
+            .map(|(key, _)| key.clone())
+            .collect())
+    }
+}
+
+impl ModelStore {
+    // 按score排序，score相同时按member排序
+    fn sorted(&self, key: &str) -> Vec<(String, f64)> {
+        let data = self.inner.lock().unwrap();
+        let mut set = data.zsets.get(key).cloned().unwrap_or_default();
+        set.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        set
+    }
+}
+
+// 返回下标在[start, stop]之间的元素，负数下标从后往前数
+fn select<T: Clone>(items: &[T], start: i64, stop: i64) -> Vec<T> {
+    let len = items.len() as i64;
+    let fix = |i: i64| if i < 0 { len + i } else { i };
+    let (start, stop) = (fix(start), fix(stop));
+    (0..len)
+        .filter(|i| *i >= start && *i <= stop)
+        .map(|i| items[i as usize].clone())
+        .collect()
+}
+
+/// 在store和模型上依次执行命令，比较每一步的响应
+pub fn assert_matches_model(
+    store: &impl Storage,
+    cmds: &[CommandRequest],
+) -> Result<(), TestCaseError> {
+    let model = ModelStore::default();
+    for (i, cmd) in cmds.iter().enumerate() {
+        let expected = normalize(cmd, dispatch(cmd.clone(), &model));
+        let actual = normalize(cmd, dispatch(cmd.clone(), store));
+        prop_assert_eq!(actual, expected, "step {}: {:?}", i, cmd);
+    }
+    Ok(())
+}
+
+// Hgetall和Tables返回的顺序是不确定的，排序之后再比较
+fn normalize(cmd: &CommandRequest, mut res: CommandResponse) -> CommandResponse {
+    match cmd.request_data {
+        Some(RequestData::Hgetall(_)) => res.pairs.sort_by(|a, b| a.key.cmp(&b.key)),
+        Some(RequestData::Tables(_)) => res.values.sort_by(|a, b| a.partial_cmp(b).unwrap()),
+        _ => {}
+    }
+    res
+}
+
+/// 随机的命令序列，key的范围很小，保证命令之间会互相影响
+pub fn commands() -> impl Strategy<Value = Vec<CommandRequest>> {
+    vec(command(), 1..64)
+}
+
+fn command() -> impl Strategy<Value = CommandRequest> {
+    let table = || prop::sample::select(vec!["t1", "t2", "t3"]);
+    let key = || (0..5).prop_map(|i| format!("k{}", i));
+    let zkey = || prop::sample::select(vec!["z1", "z2"]);
+    let member = || (0..4).prop_map(|i| format!("m{}", i));
+    // 整数的score更容易出现相同的score
+    let score = || (-3..3).prop_map(f64::from);
+    let lkey = || prop::sample::select(vec!["l1", "l2"]);
+    let index = || -6i64..6;
+
+    prop_oneof![
+        (table(), key()).prop_map(|(t, k)| CommandRequest::new_hget(t, k)),
+        table().prop_map(CommandRequest::new_hgetall),
+        (table(), key(), value()).prop_map(|(t, k, v)| CommandRequest::new_hset(t, k, v)),
+        (table(), vec((key(), value()), 0..4)).prop_map(|(t, pairs)| {
+            let pairs = pairs.into_iter().map(|(k, v)| Kvpair::new(k, v)).collect();
+            CommandRequest::new_hmset(t, pairs)
+        }),
+        (table(), key()).prop_map(|(t, k)| CommandRequest::new_hdel(t, k)),
+        (table(), key()).prop_map(|(t, k)| CommandRequest::new_hexist(t, k)),
+        Just(CommandRequest::new_tables()),
+        Just(CommandRequest::new_tables_of(KeyKind::Zset)),
+        Just(CommandRequest::new_tables_of(KeyKind::List)),
+        (zkey(), vec((member(), score()), 0..4)).prop_map(|(k, members)| {
+            let members = members
+                .into_iter()
+                .map(|(m, s)| ScoredMember::new(m, s))
+                .collect();
+            CommandRequest::new_zadd(k, members)
+        }),
+        (zkey(), index(), index())
+            .prop_map(|(k, start, stop)| { CommandRequest::new_zrange(k, start, stop) }),
+        (zkey(), member()).prop_map(|(k, m)| CommandRequest::new_zrank(k, m)),
+        (zkey(), vec(member(), 0..3)).prop_map(|(k, m)| CommandRequest::new_zrem(k, m)),
+        (lkey(), vec(value(), 0..4)).prop_map(|(k, v)| CommandRequest::new_lpush(k, v)),
+        lkey().prop_map(CommandRequest::new_rpop),
+        (lkey(), index(), index())
+            .prop_map(|(k, start, stop)| { CommandRequest::new_lrange(k, start, stop) }),
+    ]
+}
+
+fn value() -> impl Strategy<Value = Value> {
+    prop_oneof![
+        "[a-z]{0,4}".prop_map(Value::from),
+        vec(any::<u8>(), 0..8).prop_map(|b| Bytes::from(b).into()),
+        any::<i64>().prop_map(Value::from),
+        (-1e6..1e6).prop_map(Value::from),
+        any::<bool>().prop_map(Value::from),
+        Just(Value::default()),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn memtable_should_match_model(cmds in commands()) {
+        assert_matches_model(&MemTable::new(), &cmds)?;
+    }
+
+    #[test]
+    fn memtable_with_memory_limit_should_match_model(cmds in commands()) {
+        // 上限足够大，不会淘汰key，但会走记录访问时间和内存的代码
+        let store = MemTable::with_memory_limit(1 << 30, EvictionPolicy::Lfu);
+        assert_matches_model(&store, &cmds)?;
+    }
+}
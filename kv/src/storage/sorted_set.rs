@@ -0,0 +1,160 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    ops::Range,
+};
+
+/// 有序集合：按score从小到大排序，score相同时按member排序
+#[derive(Clone, Debug, Default)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+// f64没有实现Ord，用total_cmp定义全序
+#[derive(Clone, Copy, Debug)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+impl SortedSet {
+    /// 添加或者更新成员的score，返回是否是新的成员
+    pub fn insert(&mut self, member: String, score: f64) -> bool {
+        match self.scores.insert(member.clone(), score) {
+            Some(old) => {
+                self.ordered.remove(&(Score(old), member.clone()));
+                self.ordered.insert((Score(score), member));
+                false
+            }
+            None => {
+                self.ordered.insert((Score(score), member));
+                true
+            }
+        }
+    }
+
+    /// 删除成员，返回是否存在
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(Score(score), member.to_string())),
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, member: &str) -> bool {
+        self.scores.contains_key(member)
+    }
+
+    /// 成员的排名，从0开始。
+    /// BTreeSet不记录子树的大小，需要数出前面的成员，复杂度是O(n)，
+    /// 集合很大并且频繁调用时应该换成跳表之类的顺序统计结构
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        let score = *self.scores.get(member)?;
+        Some(
+            self.ordered
+                .range(..(Score(score), member.to_string()))
+                .count(),
+        )
+    }
+
+    /// 按排名返回[start, stop]之间的成员，负数表示从后往前数，和redis的ZRANGE一样
+    pub fn range(&self, start: i64, stop: i64) -> Vec<(String, f64)> {
+        let range = normalize_range(start, stop, self.len());
+        self.ordered
+            .iter()
+            .skip(range.start)
+            .take(range.len())
+            .map(|(score, member)| (member.clone(), score.0))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+}
+
+/// 把redis风格的闭区间[start, stop]转换成下标的范围，负数表示从后往前数
+pub fn normalize_range(start: i64, stop: i64, len: usize) -> Range<usize> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    match start <= stop {
+        true => start as usize..stop as usize + 1,
+        false => 0..0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_set_should_order_by_score() {
+        let mut set = SortedSet::default();
+        assert!(set.insert("alice".into(), 30.0));
+        assert!(set.insert("bob".into(), 10.0));
+        assert!(set.insert("carol".into(), 20.0));
+        // 更新score会改变排名
+        assert!(!set.insert("bob".into(), 40.0));
+
+        assert_eq!(set.rank("carol"), Some(0));
+        assert_eq!(set.rank("bob"), Some(2));
+        assert_eq!(set.rank("dave"), None);
+        assert_eq!(
+            set.range(0, -1),
+            [
+                ("carol".to_string(), 20.0),
+                ("alice".to_string(), 30.0),
+                ("bob".to_string(), 40.0)
+            ]
+        );
+        assert_eq!(set.range(-2, -2), [("alice".to_string(), 30.0)]);
+
+        assert!(set.remove("alice"));
+        assert!(!set.remove("alice"));
+        assert_eq!(set.len(), 2);
+        assert_eq!(set.rank("bob"), Some(1));
+    }
+
+    #[test]
+    fn normalize_range_should_work() {
+        assert_eq!(normalize_range(0, -1, 5), 0..5);
+        assert_eq!(normalize_range(1, 2, 5), 1..3);
+        assert_eq!(normalize_range(-3, -2, 5), 2..4);
+        assert_eq!(normalize_range(3, 100, 5), 3..5);
+        assert_eq!(normalize_range(-100, 0, 5), 0..1);
+        assert_eq!(normalize_range(4, 1, 5), 0..0);
+        assert_eq!(normalize_range(0, -1, 0), 0..0);
+        assert_eq!(normalize_range(5, 10, 5), 0..0);
+    }
+}
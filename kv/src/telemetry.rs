@@ -0,0 +1,86 @@
+use crate::KvError;
+use tracing_subscriber::{prelude::*, EnvFilter};
+
+/// tracing的配置
+#[derive(Clone, Debug)]
+pub struct TracingConfig {
+    /// OpenTelemetry collector的OTLP gRPC地址，比如http://127.0.0.1:4317
+    /// 设置之后span会同时导出到collector，需要打开otel feature
+    pub otlp_endpoint: Option<String>,
+    /// 导出到collector时使用的service.name
+    pub service_name: String,
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "kv".into(),
+        }
+    }
+}
+
+/// 程序退出之前drop，把还没有导出的span发送到collector
+#[must_use]
+pub struct TracingGuard {
+    _private: (),
+}
+
+impl Drop for TracingGuard {
+    fn drop(&mut self) {
+        // 没有导出到collector时这里什么也不做
+        #[cfg(feature = "otel")]
+        opentelemetry::global::shutdown_tracer_provider();
+    }
+}
+
+/// 初始化全局的tracing：日志输出到stdout，用RUST_LOG过滤，默认为info
+/// 导出到collector时需要在tokio运行时中调用
+pub fn init_tracing(config: &TracingConfig) -> Result<TracingGuard, KvError> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let otel = match &config.otlp_endpoint {
+        Some(endpoint) => Some(otel_layer(endpoint, &config.service_name)?),
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel)
+        .try_init()
+        .map_err(|e| KvError::Internal(e.to_string()))?;
+    Ok(TracingGuard { _private: () })
+}
+
+#[cfg(feature = "otel")]
+fn otel_layer<S>(
+    endpoint: &str,
+    service_name: &str,
+) -> Result<impl tracing_subscriber::Layer<S>, KvError>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    use opentelemetry::{sdk::trace, sdk::Resource, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+
+    let exporter = opentelemetry_otlp::new_exporter()
+        .tonic()
+        .with_endpoint(endpoint);
+    let resource = Resource::new(vec![KeyValue::new("service.name", service_name.to_owned())]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(exporter)
+        .with_trace_config(trace::config().with_resource(resource))
+        .install_batch(opentelemetry::runtime::Tokio)
+        .map_err(|e| KvError::Internal(e.to_string()))?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+#[cfg(not(feature = "otel"))]
+fn otel_layer(
+    _endpoint: &str,
+    _service_name: &str,
+) -> Result<tracing_subscriber::layer::Identity, KvError> {
+    Err(KvError::Internal(
+        "kv is built without the otel feature".into(),
+    ))
+}
@@ -1,5 +1,7 @@
+use std::io::Write;
+
 use anyhow::Result;
-use queryer::query;
+use queryer::{query, OutputFormat};
 use tracing::Level;
 
 #[tokio::main]
@@ -8,6 +10,13 @@ async fn main() -> Result<()> {
         .with_max_level(Level::DEBUG)
         .init();
 
+    // 第一个参数指定输出格式，比如 csv、json、markdown，默认是表格
+    let format: OutputFormat = std::env::args()
+        .nth(1)
+        .as_deref()
+        .unwrap_or("table")
+        .parse()?;
+
     // let url = "https://raw.githubusercontent.com/owid/covid-19-data/master/public/data/latest/owid-covid-latest.csv";
     let url = "file:///home/jojo/code/geektime/rust-first-lession/owid-covid-latest.csv";
 
@@ -18,7 +27,17 @@ async fn main() -> Result<()> {
     );
 
     let df1 = query(sql).await?;
-    println!("{:?}", df1);
+    std::io::stdout().write_all(&df1.write(format)?)?;
+
+    // 每个大洲的总死亡人数
+    let sql = format!(
+        "SELECT continent, SUM(total_deaths) deaths, COUNT(*) countries \
+        FROM {} WHERE continent IS NOT NULL GROUP BY continent ORDER BY deaths DESC",
+        url
+    );
+
+    let df2 = query(sql).await?;
+    std::io::stdout().write_all(&df2.write(format)?)?;
 
     Ok(())
 }
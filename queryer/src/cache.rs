@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    env, io,
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use lazy_static::lazy_static;
+use polars::prelude::DataFrame;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use tracing::warn;
+
+use crate::fetcher::Content;
+
+/// 内存中最多缓存的数据源个数，超过时去掉最久没有使用的
+const MAX_ENTRIES: usize = 16;
+
+/// 数据源缓存的配置
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// 缓存文件的目录，为 None 时只在内存中缓存。目录的权限会被改成 0700
+    pub dir: Option<PathBuf>,
+    /// 在这个时间内直接使用缓存，超过之后要向服务器确认数据有没有变化
+    pub ttl: Duration,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            dir: Some(default_dir()),
+            ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+/// 默认的缓存目录：$XDG_CACHE_HOME/queryer 或者 ~/.cache/queryer，
+/// 都没有时放在临时目录下，按用户名区分
+fn default_dir() -> PathBuf {
+    let var = |name| {
+        env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from)
+    };
+    if let Some(dir) = var("XDG_CACHE_HOME") {
+        return dir.join("queryer");
+    }
+    if let Some(home) = var("HOME") {
+        return home.join(".cache").join("queryer");
+    }
+    let user = env::var("USER").unwrap_or_default();
+    env::temp_dir().join(format!("queryer-{}", user))
+}
+
+/// 按最近使用时间淘汰的缓存
+type Entries<T> = Mutex<HashMap<String, (Instant, T)>>;
+
+lazy_static! {
+    static ref CONFIG: RwLock<CacheConfig> = RwLock::new(CacheConfig::default());
+    /// http 数据源的原始数据，key 是 URL
+    static ref CONTENTS: Entries<Cached> = Default::default();
+    /// 解析好的 DataFrame，key 是数据源，value 带有原始数据的版本
+    static ref FRAMES: Entries<(String, DataFrame)> = Default::default();
+}
+
+/// 修改数据源缓存的配置
+pub fn set_cache_config(config: CacheConfig) {
+    *CONFIG.write().unwrap() = config;
+}
+
+pub(crate) fn cache_config() -> CacheConfig {
+    CONFIG.read().unwrap().clone()
+}
+
+/// 缓存的 http 响应头，和数据分开保存在磁盘上
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Meta {
+    pub(crate) etag: Option<String>,
+    pub(crate) last_modified: Option<String>,
+    pub(crate) content_type: Option<String>,
+    /// 获取数据或者上次确认数据没有变化的时间，unix 时间戳（秒）
+    pub(crate) fetched_at: u64,
+}
+
+impl Meta {
+    /// 数据的版本，优先用 ETag 和 Last-Modified，数据没有变化时 304 不会改变版本
+    fn version(&self) -> String {
+        self.etag
+            .clone()
+            .or_else(|| self.last_modified.clone())
+            .unwrap_or_else(|| self.fetched_at.to_string())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Cached {
+    pub(crate) meta: Meta,
+    pub(crate) data: Arc<Vec<u8>>,
+}
+
+impl Cached {
+    pub(crate) fn is_fresh(&self, ttl: Duration) -> bool {
+        now().saturating_sub(self.meta.fetched_at) < ttl.as_secs()
+    }
+
+    pub(crate) fn content(&self) -> Content {
+        Content {
+            data: self.data.clone(),
+            content_type: self.meta.content_type.clone(),
+            version: Some(self.meta.version()),
+        }
+    }
+}
+
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// 先从内存中查找，找不到再读磁盘上的缓存文件
+pub(crate) async fn get(url: &str, config: &CacheConfig) -> Option<Cached> {
+    // 锁不能跨越 await，所以先在单独的语句中取出来
+    let hit = CONTENTS.lock().unwrap().get_mut(url).map(|(used, cached)| {
+        *used = Instant::now();
+        cached.clone()
+    });
+    if hit.is_some() {
+        return hit;
+    }
+
+    let dir = config.dir.as_ref()?;
+    // 不是自己的目录不能读，里面的数据可能被别人改过
+    private_dir(dir).await.ok()?;
+    let path = dir.join(file_name(url));
+    let meta = fs::read(path.with_extension("json")).await.ok()?;
+    let meta = serde_json::from_slice(&meta).ok()?;
+    let data = fs::read(path.with_extension("data")).await.ok()?;
+    let cached = Cached {
+        meta,
+        data: Arc::new(data),
+    };
+    insert(&mut CONTENTS.lock().unwrap(), url, cached.clone());
+    Some(cached)
+}
+
+/// 保存到内存和磁盘，数据没有变化时只需要更新磁盘上的响应头
+pub(crate) async fn store(url: &str, cached: &Cached, config: &CacheConfig, data_changed: bool) {
+    insert(&mut CONTENTS.lock().unwrap(), url, cached.clone());
+
+    let dir = match &config.dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    // 写缓存失败不影响查询
+    let path = dir.join(file_name(url));
+    let res = async {
+        private_dir(dir).await?;
+        if data_changed {
+            write_atomic(&path.with_extension("data"), cached.data.as_slice()).await?;
+        }
+        let meta = serde_json::to_vec(&cached.meta)?;
+        write_atomic(&path.with_extension("json"), &meta).await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    if let Err(e) = res.await {
+        warn!("failed to write cache for {}: {}", url, e);
+    }
+}
+
+/// 创建只有当前用户能访问的目录，已有的目录也改成 0700，别人的目录会改不了权限而失败
+async fn private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    builder.mode(0o700);
+    builder.create(dir).await?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700)).await?;
+    }
+    Ok(())
+}
+
+/// 先写到临时文件再改名，其它进程不会读到写了一半的文件
+async fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    static SEQ: AtomicU64 = AtomicU64::new(0);
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}-{}.tmp",
+        process::id(),
+        SEQ.fetch_add(1, Ordering::Relaxed)
+    ));
+    let res = async {
+        fs::write(&tmp, data).await?;
+        fs::rename(&tmp, path).await
+    };
+    if let Err(e) = res.await {
+        let _ = fs::remove_file(&tmp).await;
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// URL 的指纹，用作磁盘缓存的文件名
+fn fingerprint(data: &[u8]) -> u64 {
+    // FNV-1a，不同版本的 Rust 计算结果一样，可以用在磁盘缓存的文件名中
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// 数据源的版本没有变化时可以直接使用解析好的 DataFrame
+pub(crate) fn get_frame(source: &str, version: &str) -> Option<DataFrame> {
+    match FRAMES.lock().unwrap().get_mut(source) {
+        Some((used, (v, df))) if v == version => {
+            *used = Instant::now();
+            Some(df.clone())
+        }
+        _ => None,
+    }
+}
+
+pub(crate) fn put_frame(source: &str, version: &str, df: DataFrame) {
+    insert(
+        &mut FRAMES.lock().unwrap(),
+        source,
+        (version.to_owned(), df),
+    );
+}
+
+fn file_name(url: &str) -> String {
+    format!("{:016x}", fingerprint(url.as_bytes()))
+}
+
+fn insert<T>(map: &mut HashMap<String, (Instant, T)>, key: &str, value: T) {
+    if map.len() >= MAX_ENTRIES && !map.contains_key(key) {
+        let oldest = map
+            .iter()
+            .min_by_key(|(_, (used, _))| *used)
+            .map(|(k, _)| k.clone());
+        if let Some(k) = oldest {
+            map.remove(&k);
+        }
+    }
+    map.insert(key.to_owned(), (Instant::now(), value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::{df, prelude::*};
+
+    #[tokio::test]
+    async fn cache_should_be_stored_on_disk() {
+        let dir = std::env::temp_dir().join(format!("queryer-cache-{}", std::process::id()));
+        let config = CacheConfig {
+            dir: Some(dir.clone()),
+            ttl: Duration::from_secs(60),
+        };
+        let url = "http://abc.xyz/cache.csv";
+        let cached = Cached {
+            meta: Meta {
+                etag: Some("\"v1\"".into()),
+                last_modified: None,
+                content_type: Some("text/csv".into()),
+                fetched_at: now(),
+            },
+            data: Arc::new(b"a,b\n1,2\n".to_vec()),
+        };
+        store(url, &cached, &config, true).await;
+        assert!(get(url, &config).await.unwrap().is_fresh(config.ttl));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&dir).await.unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+        // 没有留下临时文件
+        let mut entries = fs::read_dir(&dir).await.unwrap();
+        while let Some(entry) = entries.next_entry().await.unwrap() {
+            assert!(!entry.file_name().to_string_lossy().ends_with(".tmp"));
+        }
+
+        // 内存中没有时从磁盘读取
+        CONTENTS.lock().unwrap().remove(url);
+        let res = get(url, &config).await.unwrap();
+        assert_eq!(res.meta, cached.meta);
+        assert_eq!(res.data, cached.data);
+        assert!(get("http://abc.xyz/none.csv", &config).await.is_none());
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test]
+    fn frame_cache_should_check_version() {
+        let df = df!("a" => &[1, 2]).unwrap();
+        put_frame("file:///frame.csv", "\"v1\"", df.clone());
+        assert!(get_frame("file:///frame.csv", "\"v1\"")
+            .unwrap()
+            .frame_equal(&df));
+        assert!(get_frame("file:///frame.csv", "\"v2\"").is_none());
+        assert!(get_frame("file:///other.csv", "\"v1\"").is_none());
+    }
+}
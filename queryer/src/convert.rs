@@ -1,17 +1,28 @@
 use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveDateTime};
 use polars::prelude::*;
+use regex::Regex;
 use sqlparser::ast::{
-    BinaryOperator as SqlBinaryOperator, Expr as SqlExpr, Offset as SqlOffset, OrderByExpr, Select,
-    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, Value as SqlValue,
+    BinaryOperator as SqlBinaryOperator, DataType as SqlDataType, Expr as SqlExpr,
+    Function as SqlFunction, FunctionArg, Ident, Offset as SqlOffset, OrderByExpr, Select,
+    SelectItem, SetExpr, Statement, TableFactor, TableWithJoins, UnaryOperator, Value as SqlValue,
 };
 use std::convert::{TryFrom, TryInto};
 
+/// COUNT(*) 计数用的列，分组之前加到 DataFrame 中
+pub(crate) const ROW_COUNT: &str = "__row_count";
+
 /// 解析出来的 SQL
 pub struct Sql<'a> {
     pub(crate) selection: Vec<Expr>,
     pub(crate) condition: Option<Expr>,
+    pub(crate) group_by: Vec<Expr>,
+    /// 聚合表达式，每个都 alias 成聚合函数在 SQL 中的文本，selection 和 having 通过这个名字引用
+    pub(crate) aggregation: Vec<Expr>,
+    pub(crate) having: Option<Expr>,
     pub(crate) source: &'a str,
-    pub(crate) order_by: Vec<(String, bool)>,
+    /// 排序用的表达式和是否倒序，表达式和 selection 在同一个 DataFrame 上计算
+    pub(crate) order_by: Vec<(Expr, bool)>,
     pub(crate) offset: Option<i64>,
     pub(crate) limit: Option<usize>,
 }
@@ -22,10 +33,14 @@ pub struct Expression(pub(crate) Box<SqlExpr>);
 pub struct Operation(pub(crate) SqlBinaryOperator);
 pub struct Projection<'a>(pub(crate) &'a SelectItem);
 pub struct Source<'a>(pub(crate) &'a [TableWithJoins]);
-pub struct Order<'a>(pub(crate) &'a OrderByExpr);
+/// ORDER BY 可以引用 select 中的别名和位置，所以需要 selection
+pub struct Order<'a>(pub(crate) &'a OrderByExpr, pub(crate) &'a [Expr]);
 pub struct Offset<'a>(pub(crate) &'a SqlOffset);
 pub struct Limit<'a>(pub(crate) &'a SqlExpr);
 pub struct Value(pub(crate) SqlValue);
+pub struct Aggregate<'a>(pub(crate) &'a SqlFunction);
+pub struct Function<'a>(pub(crate) &'a SqlFunction);
+pub struct Cast(pub(crate) SqlDataType);
 
 impl<'a> TryFrom<&'a Statement> for Sql<'a> {
     type Error = anyhow::Error;
@@ -41,8 +56,8 @@ impl<'a> TryFrom<&'a Statement> for Sql<'a> {
                     from: table_with_joins,
                     selection: where_clause,
                     projection,
-
-                    group_by: _,
+                    group_by,
+                    having,
                     ..
                 } = match &q.body {
                     SetExpr::Select(statement) => statement.as_ref(),
@@ -56,15 +71,42 @@ impl<'a> TryFrom<&'a Statement> for Sql<'a> {
                     None => None,
                 };
 
+                let mut aggregation = Vec::new();
                 let mut selection = Vec::with_capacity(8);
                 for p in projection {
-                    let expr = Projection(p).try_into()?;
+                    let mut item = p.clone();
+                    match &mut item {
+                        SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                            extract_aggregates(expr, &mut aggregation)?
+                        }
+                        _ => {}
+                    }
+                    let expr = Projection(&item).try_into()?;
                     selection.push(expr);
                 }
 
+                let group_by = group_by
+                    .iter()
+                    .map(|expr| Expression(Box::new(expr.to_owned())).try_into())
+                    .collect::<Result<Vec<_>>>()?;
+
+                let having = match having {
+                    Some(expr) => {
+                        let mut expr = expr.to_owned();
+                        extract_aggregates(&mut expr, &mut aggregation)?;
+                        Some(Expression(Box::new(expr)).try_into()?)
+                    }
+                    None => None,
+                };
+                if having.is_some() && group_by.is_empty() && aggregation.is_empty() {
+                    return Err(anyhow!("HAVING requires GROUP BY or aggregate functions"));
+                }
+
                 let mut order_by = Vec::new();
                 for expr in orders {
-                    order_by.push(Order(expr).try_into()?);
+                    let mut expr = expr.to_owned();
+                    extract_aggregates(&mut expr.expr, &mut aggregation)?;
+                    order_by.push(Order(&expr, &selection).try_into()?);
                 }
 
                 let offset = offset.map(|v| Offset(v).into());
@@ -73,6 +115,9 @@ impl<'a> TryFrom<&'a Statement> for Sql<'a> {
                 Ok(Sql {
                     selection,
                     condition,
+                    group_by,
+                    aggregation,
+                    having,
                     source,
                     order_by,
                     offset,
@@ -90,21 +135,485 @@ impl TryFrom<Expression> for Expr {
 
     fn try_from(expr: Expression) -> Result<Self, Self::Error> {
         match *expr.0 {
-            SqlExpr::BinaryOp { left, op, right } => Ok(Expr::BinaryExpr {
-                left: Box::new(Expression(left).try_into()?),
-                op: Operation(op).try_into()?,
-                right: Box::new(Expression(right).try_into()?),
-            }),
+            SqlExpr::BinaryOp { left, op, right } => match like_operator(&op) {
+                Some((negated, case_insensitive)) => like(
+                    Expression(left).try_into()?,
+                    &right,
+                    negated,
+                    case_insensitive,
+                ),
+                None => Ok(Expr::BinaryExpr {
+                    left: Box::new(Expression(left).try_into()?),
+                    op: Operation(op).try_into()?,
+                    right: Box::new(Expression(right).try_into()?),
+                }),
+            },
+            SqlExpr::UnaryOp { op, expr } => match (op, *expr) {
+                // 负数直接作为字面量，不需要计算
+                (UnaryOperator::Minus, SqlExpr::Value(SqlValue::Number(v, b))) => Ok(
+                    Self::Literal(Value(SqlValue::Number(format!("-{}", v), b)).try_into()?),
+                ),
+                (UnaryOperator::Minus, expr) => Ok(negate(Expression(Box::new(expr)).try_into()?)),
+                (UnaryOperator::Plus, expr) => Expression(Box::new(expr)).try_into(),
+                (UnaryOperator::Not, expr) => Ok(Expr::try_from(Expression(Box::new(expr)))?.not()),
+                (op, _) => Err(anyhow!("unary operator {} is not supported", op)),
+            },
+            SqlExpr::Nested(expr) => Expression(expr).try_into(),
             SqlExpr::Wildcard => Ok(Self::Wildcard),
             SqlExpr::IsNull(expr) => Ok(Self::IsNull(Box::new(Expression(expr).try_into()?))),
             SqlExpr::IsNotNull(expr) => Ok(Self::IsNotNull(Box::new(Expression(expr).try_into()?))),
+            SqlExpr::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let expr: Expr = Expression(expr).try_into()?;
+                let cond = list
+                    .into_iter()
+                    .map(|v| Ok(expr.clone().eq(Expression(Box::new(v)).try_into()?)))
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter()
+                    .reduce(|acc, v| acc.or(v))
+                    .ok_or_else(|| anyhow!("IN list should not be empty"))?;
+                Ok(if negated { cond.not() } else { cond })
+            }
+            SqlExpr::Between {
+                expr,
+                negated,
+                low,
+                high,
+            } => {
+                let expr: Expr = Expression(expr).try_into()?;
+                let low = Expression(low).try_into()?;
+                let high = Expression(high).try_into()?;
+                let cond = expr.clone().gt_eq(low).and(expr.lt_eq(high));
+                Ok(if negated { cond.not() } else { cond })
+            }
+            SqlExpr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                let operand = match operand {
+                    Some(v) => Some(Expr::try_from(Expression(v))?),
+                    None => None,
+                };
+                let otherwise = match else_result {
+                    Some(v) => Expression(v).try_into()?,
+                    None => Expr::Literal(LiteralValue::Null),
+                };
+                // 从最后一个分支开始，每个 WHEN 都作为前一个 WHEN 的 otherwise
+                conditions.into_iter().zip(results).rev().try_fold(
+                    otherwise,
+                    |acc, (cond, result)| -> Result<Expr> {
+                        let cond: Expr = Expression(Box::new(cond)).try_into()?;
+                        let cond = match &operand {
+                            Some(operand) => operand.clone().eq(cond),
+                            None => cond,
+                        };
+                        let result = Expression(Box::new(result)).try_into()?;
+                        Ok(when(cond).then(result).otherwise(acc))
+                    },
+                )
+            }
+            SqlExpr::Cast { expr, data_type } => {
+                let expr: Expr = Expression(expr).try_into()?;
+                Ok(expr.cast(Cast(data_type).try_into()?))
+            }
             SqlExpr::Identifier(id) => Ok(Self::Column(Arc::new(id.value))),
             SqlExpr::Value(v) => Ok(Self::Literal(Value(v).try_into()?)),
+            SqlExpr::TypedString { data_type, value } => typed_string(data_type, &value),
+            SqlExpr::Function(f) if is_aggregate(&f) => Err(anyhow!(
+                "aggregate function {} is only allowed in select and having",
+                f
+            )),
+            SqlExpr::Function(f) => Function(&f).try_into(),
             v => Err(anyhow!("expr {:#?} is not supported", v)),
         }
     }
 }
 
+/// 把 SqlParser 的聚合函数转换成 DataFrame 的聚合表达式
+impl<'a> TryFrom<Aggregate<'a>> for Expr {
+    type Error = anyhow::Error;
+
+    fn try_from(agg: Aggregate<'a>) -> Result<Self, Self::Error> {
+        let f = agg.0;
+        let name = f.name.to_string().to_lowercase();
+        let arg = match f.args.as_slice() {
+            [FunctionArg::Unnamed(arg)] => arg,
+            _ => return Err(anyhow!("aggregate function {} takes one argument", f)),
+        };
+        let expr = match arg {
+            SqlExpr::Wildcard if name == "count" && !f.distinct => {
+                return Ok(col(ROW_COUNT).count())
+            }
+            arg => Expr::try_from(Expression(Box::new(arg.to_owned())))?,
+        };
+
+        match (name.as_str(), f.distinct) {
+            // n_unique 会把 NULL 也算作一个值，先去掉 NULL
+            ("count", true) => Ok(expr.clone().filter(expr.is_not_null()).n_unique()),
+            ("count", false) => Ok(expr.count()),
+            ("sum", false) => Ok(expr.sum()),
+            ("avg", false) => Ok(expr.mean()),
+            ("min", false) => Ok(expr.min()),
+            ("max", false) => Ok(expr.max()),
+            _ => Err(anyhow!("aggregate function {} is not supported", f)),
+        }
+    }
+}
+
+fn is_aggregate(f: &SqlFunction) -> bool {
+    let name = f.name.to_string().to_lowercase();
+    ["count", "sum", "avg", "min", "max"].contains(&name.as_str())
+}
+
+/// 把表达式中的聚合函数替换成以它的文本为名的列，聚合函数本身转换后放进 aggregation
+/// 分组聚合之后，select 和 having 就可以像普通的列一样使用聚合的结果
+fn extract_aggregates(expr: &mut SqlExpr, aggregation: &mut Vec<Expr>) -> Result<()> {
+    match expr {
+        SqlExpr::Function(f) if is_aggregate(f) => {
+            let name = f.to_string();
+            let exists = aggregation
+                .iter()
+                .any(|e| matches!(e, Expr::Alias(_, n) if n.as_str() == name));
+            if !exists {
+                let agg: Expr = Aggregate(f).try_into()?;
+                aggregation.push(agg.alias(&name));
+            }
+            *expr = SqlExpr::Identifier(Ident::new(name));
+        }
+        SqlExpr::BinaryOp { left, right, .. } => {
+            extract_aggregates(left, aggregation)?;
+            extract_aggregates(right, aggregation)?;
+        }
+        SqlExpr::UnaryOp { expr, .. }
+        | SqlExpr::Nested(expr)
+        | SqlExpr::IsNull(expr)
+        | SqlExpr::IsNotNull(expr)
+        | SqlExpr::Cast { expr, .. } => extract_aggregates(expr, aggregation)?,
+        SqlExpr::Between {
+            expr, low, high, ..
+        } => {
+            extract_aggregates(expr, aggregation)?;
+            extract_aggregates(low, aggregation)?;
+            extract_aggregates(high, aggregation)?;
+        }
+        SqlExpr::InList { expr, list, .. } => {
+            extract_aggregates(expr, aggregation)?;
+            for expr in list {
+                extract_aggregates(expr, aggregation)?;
+            }
+        }
+        SqlExpr::Case {
+            operand,
+            conditions,
+            results,
+            else_result,
+        } => {
+            let exprs = operand.iter_mut().chain(else_result.iter_mut());
+            for expr in exprs {
+                extract_aggregates(expr, aggregation)?;
+            }
+            for expr in conditions.iter_mut().chain(results.iter_mut()) {
+                extract_aggregates(expr, aggregation)?;
+            }
+        }
+        SqlExpr::Function(f) => {
+            for arg in &mut f.args {
+                match arg {
+                    FunctionArg::Named { arg, .. } | FunctionArg::Unnamed(arg) => {
+                        extract_aggregates(arg, aggregation)?
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// 把 SqlParser 的标量函数转换成 DataFrame 的 Expr
+impl<'a> TryFrom<Function<'a>> for Expr {
+    type Error = anyhow::Error;
+
+    fn try_from(func: Function<'a>) -> Result<Self, Self::Error> {
+        let f = func.0;
+        let name = f.name.to_string().to_lowercase();
+        let mut args = f
+            .args
+            .iter()
+            .map(|arg| match arg {
+                FunctionArg::Unnamed(arg) => Expression(Box::new(arg.to_owned())).try_into(),
+                FunctionArg::Named { .. } => {
+                    Err(anyhow!("named argument in {} is not supported", f))
+                }
+            })
+            .collect::<Result<Vec<Expr>>>()?;
+
+        match (name.as_str(), args.len()) {
+            ("lower", 1) => Ok(map_utf8(args.remove(0), str::to_lowercase)),
+            ("upper", 1) => Ok(map_utf8(args.remove(0), str::to_uppercase)),
+            ("length", 1) => Ok(args.remove(0).map(
+                |s: Series| {
+                    let mut ca: UInt32Chunked = s
+                        .utf8()?
+                        .into_iter()
+                        .map(|v| v.map(|v| v.chars().count() as u32))
+                        .collect();
+                    ca.rename(s.name());
+                    Ok(ca.into_series())
+                },
+                Some(DataType::UInt32),
+            )),
+            ("abs", 1) => {
+                let expr = args.remove(0);
+                Ok(when(expr.clone().lt(lit(0)))
+                    .then(negate(expr.clone()))
+                    .otherwise(expr))
+            }
+            ("round", 1) | ("round", 2) => {
+                // 保留的小数位数只能是整数字面量
+                let decimals = match f.args.get(1) {
+                    Some(FunctionArg::Unnamed(SqlExpr::Value(SqlValue::Number(v, _)))) => v
+                        .parse::<i32>()
+                        .map_err(|_| anyhow!("round() decimals should be an integer, got {}", v))?,
+                    Some(v) => {
+                        return Err(anyhow!("round() decimals should be an integer, got {}", v))
+                    }
+                    None => 0,
+                };
+                let scale = 10f64.powi(decimals);
+                Ok(args.remove(0).cast(DataType::Float64).map(
+                    move |s: Series| {
+                        let ca = s.f64()?.apply(|v| (v * scale).round() / scale);
+                        Ok(ca.into_series())
+                    },
+                    Some(DataType::Float64),
+                ))
+            }
+            ("coalesce", n) if n > 0 => {
+                // 从最后一个参数开始，前面的参数为空时取后面的
+                let last = args.pop().unwrap();
+                Ok(args.into_iter().rev().fold(last, |acc, v| {
+                    when(v.clone().is_not_null()).then(v).otherwise(acc)
+                }))
+            }
+            _ => Err(anyhow!("function {} is not supported", f)),
+        }
+    }
+}
+
+/// 对字符串列的每个值调用 f
+fn map_utf8(expr: Expr, f: fn(&str) -> String) -> Expr {
+    expr.map(
+        move |s: Series| {
+            let mut ca: Utf8Chunked = s.utf8()?.into_iter().map(|v| v.map(f)).collect();
+            ca.rename(s.name());
+            Ok(ca.into_series())
+        },
+        Some(DataType::Utf8),
+    )
+}
+
+/// 计算 0 - expr
+fn negate(expr: Expr) -> Expr {
+    Expr::BinaryExpr {
+        left: Box::new(lit(0)),
+        op: Operator::Minus,
+        right: Box::new(expr),
+    }
+}
+
+/// LIKE 类的运算符返回 (是否取反, 是否忽略大小写)
+fn like_operator(op: &SqlBinaryOperator) -> Option<(bool, bool)> {
+    match op {
+        SqlBinaryOperator::Like => Some((false, false)),
+        SqlBinaryOperator::NotLike => Some((true, false)),
+        SqlBinaryOperator::ILike => Some((false, true)),
+        SqlBinaryOperator::NotILike => Some((true, true)),
+        _ => None,
+    }
+}
+
+/// 把 LIKE 的模式转换成正则表达式，`%` 匹配任意多个字符，`_` 匹配一个字符
+fn like(expr: Expr, pattern: &SqlExpr, negated: bool, case_insensitive: bool) -> Result<Expr> {
+    let pattern = match pattern {
+        SqlExpr::Value(SqlValue::SingleQuotedString(v)) => v,
+        v => return Err(anyhow!("LIKE pattern should be a string, got {}", v)),
+    };
+    let mut re = String::from(if case_insensitive { "(?is)^" } else { "(?s)^" });
+    for c in pattern.chars() {
+        match c {
+            '%' => re.push_str(".*"),
+            '_' => re.push('.'),
+            c => re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    re.push('$');
+    let re = Regex::new(&re)?;
+
+    let expr = expr.map(
+        move |s: Series| {
+            let mut ca: BooleanChunked = s
+                .utf8()?
+                .into_iter()
+                .map(|v| v.map(|v| re.is_match(v)))
+                .collect();
+            ca.rename(s.name());
+            Ok(ca.into_series())
+        },
+        Some(DataType::Boolean),
+    );
+    Ok(if negated { expr.not() } else { expr })
+}
+
+/// 把 `DATE '2021-01-01'`、`TIMESTAMP '2021-01-01 10:00:00'` 之类的字面量转换成对应类型的值
+fn typed_string(data_type: SqlDataType, value: &str) -> Result<Expr> {
+    match data_type {
+        SqlDataType::Date => {
+            let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map_err(|e| anyhow!("invalid date {}: {}", value, e))?;
+            let days = date.signed_duration_since(NaiveDate::from_ymd(1970, 1, 1));
+            Ok(lit(days.num_days() as i32).cast(DataType::Date32))
+        }
+        SqlDataType::Timestamp => {
+            let datetime = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"]
+                .iter()
+                .find_map(|fmt| NaiveDateTime::parse_from_str(value, fmt).ok())
+                .ok_or_else(|| anyhow!("invalid timestamp {}", value))?;
+            Ok(lit(datetime.timestamp_millis()).cast(DataType::Date64))
+        }
+        data_type => Ok(lit(value).cast(Cast(data_type).try_into()?)),
+    }
+}
+
+/// 根据数据源的 schema，把和列比较的字面量转换成列的类型，
+/// 比如日期列和 `'2021-01-01'` 比较时，字符串会转换成日期
+pub(crate) fn cast_literals(expr: Expr, schema: &Schema) -> Expr {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            mut op,
+            right,
+        } => {
+            let (left, right) = match (*left, *right) {
+                (Expr::Column(name), Expr::Literal(v)) => cast_literal(name, v, schema),
+                // polars 在字面量在左边时比较的结果不对，交换成列在左边
+                (Expr::Literal(v), Expr::Column(name)) => match flip(op) {
+                    Some(flipped) => {
+                        op = flipped;
+                        cast_literal(name, v, schema)
+                    }
+                    None => {
+                        let (column, v) = cast_literal(name, v, schema);
+                        (v, column)
+                    }
+                },
+                (left, right) => (cast_literals(left, schema), cast_literals(right, schema)),
+            };
+            Expr::BinaryExpr {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            }
+        }
+        Expr::Not(expr) => Expr::Not(Box::new(cast_literals(*expr, schema))),
+        Expr::Ternary {
+            predicate,
+            truthy,
+            falsy,
+        } => Expr::Ternary {
+            predicate: Box::new(cast_literals(*predicate, schema)),
+            truthy,
+            falsy,
+        },
+        expr => expr,
+    }
+}
+
+/// 交换左右两边之后等价的比较运算符
+fn flip(op: Operator) -> Option<Operator> {
+    match op {
+        Operator::Lt => Some(Operator::Gt),
+        Operator::LtEq => Some(Operator::GtEq),
+        Operator::Gt => Some(Operator::Lt),
+        Operator::GtEq => Some(Operator::LtEq),
+        Operator::Eq | Operator::NotEq => Some(op),
+        _ => None,
+    }
+}
+
+/// 返回转换之后的列和字面量。字面量在列类型的范围内时转换字面量，
+/// 否则把列转换成能容纳字面量的类型，比如 Int32 列和 3000000000 比较时按 Int64 比较
+fn cast_literal(name: Arc<String>, v: LiteralValue, schema: &Schema) -> (Expr, Expr) {
+    let dtype = match schema.field_with_name(&name) {
+        Ok(field) => field.data_type().clone(),
+        Err(_) => return (Expr::Column(name), Expr::Literal(v)),
+    };
+    let column = Expr::Column(name);
+    match (v, dtype) {
+        // 解析不了的字符串还是交给 polars 转换
+        (LiteralValue::Utf8(s), DataType::Date32) => {
+            let v = typed_string(SqlDataType::Date, &s);
+            (column, v.unwrap_or_else(|_| lit(s).cast(DataType::Date32)))
+        }
+        (LiteralValue::Utf8(s), DataType::Date64) => {
+            let v = typed_string(SqlDataType::Timestamp, &s)
+                .or_else(|_| typed_string(SqlDataType::Timestamp, &format!("{} 00:00:00", s)));
+            (column, v.unwrap_or_else(|_| lit(s).cast(DataType::Date64)))
+        }
+        (LiteralValue::Int64(n), dtype @ DataType::Int32) if i32::try_from(n).is_ok() => {
+            (column, lit(n).cast(dtype))
+        }
+        (LiteralValue::Int64(n), dtype @ DataType::UInt32) if u32::try_from(n).is_ok() => {
+            (column, lit(n).cast(dtype))
+        }
+        (LiteralValue::Int64(n), dtype @ DataType::UInt64) if n >= 0 => {
+            (column, lit(n).cast(dtype))
+        }
+        (LiteralValue::Int64(n), DataType::Int32 | DataType::UInt32) => {
+            (column.cast(DataType::Int64), lit(n))
+        }
+        // UInt64 转换成 Int64 可能溢出，和负数比较时都按浮点数比较
+        (LiteralValue::Int64(n), DataType::UInt64) => (
+            column.cast(DataType::Float64),
+            lit(n).cast(DataType::Float64),
+        ),
+        (v @ LiteralValue::Int64(_), dtype @ DataType::Float64) => {
+            (column, Expr::Literal(v).cast(dtype))
+        }
+        (v @ LiteralValue::Int64(_), dtype @ DataType::Float32) => {
+            (column, Expr::Literal(v).cast(dtype))
+        }
+        (LiteralValue::Float64(f), dtype @ DataType::Float32) if f.abs() <= f32::MAX as f64 => {
+            (column, lit(f).cast(dtype))
+        }
+        (LiteralValue::Float64(f), DataType::Float32) => (column.cast(DataType::Float64), lit(f)),
+        // 整数列和小数比较时还是按浮点数比较，不能把小数转换成整数
+        (v, _) => (column, Expr::Literal(v)),
+    }
+}
+
+/// 把 SqlParser 的 DataType 转换成 DataFrame 的 DataType
+impl TryFrom<Cast> for DataType {
+    type Error = anyhow::Error;
+
+    fn try_from(cast: Cast) -> Result<Self, Self::Error> {
+        match cast.0 {
+            SqlDataType::SmallInt | SqlDataType::Int => Ok(Self::Int32),
+            SqlDataType::BigInt => Ok(Self::Int64),
+            SqlDataType::Real | SqlDataType::Float(_) => Ok(Self::Float32),
+            SqlDataType::Double | SqlDataType::Decimal(..) => Ok(Self::Float64),
+            SqlDataType::Boolean => Ok(Self::Boolean),
+            SqlDataType::Char(_) | SqlDataType::Varchar(_) | SqlDataType::Text => Ok(Self::Utf8),
+            v => Err(anyhow!("cast to {} is not supported", v)),
+        }
+    }
+}
+
 /// 把 SqlParser 的 BinaryOperator 转换成 DatFrame 的 Operator
 impl TryFrom<Operation> for Operator {
     type Error = anyhow::Error;
@@ -136,16 +645,17 @@ impl<'a> TryFrom<Projection<'a>> for Expr {
     fn try_from(value: Projection<'a>) -> Result<Self, Self::Error> {
         match value.0 {
             SelectItem::UnnamedExpr(SqlExpr::Identifier(id)) => Ok(col(&id.to_string())),
-            SelectItem::ExprWithAlias {
-                expr: SqlExpr::Identifier(id),
-                alias,
-            } => Ok(Expr::Alias(
-                Box::new(Expr::Column(Arc::new(id.to_string()))),
-                Arc::new(alias.to_string()),
-            )),
+            // 没有别名的表达式用它在 SQL 中的文本作为列名
+            SelectItem::UnnamedExpr(expr) => {
+                let name = expr.to_string();
+                Ok(Expr::try_from(Expression(Box::new(expr.to_owned())))?.alias(&name))
+            }
+            SelectItem::ExprWithAlias { expr, alias } => {
+                let expr: Expr = Expression(Box::new(expr.to_owned())).try_into()?;
+                Ok(expr.alias(&alias.to_string()))
+            }
             SelectItem::QualifiedWildcard(v) => Ok(col(&v.to_string())),
             SelectItem::Wildcard => Ok(col("*")),
-            item => Err(anyhow!("projection {} not supported", item)),
         }
     }
 }
@@ -158,11 +668,8 @@ impl<'a> TryFrom<Source<'a>> for &'a str {
             return Err(anyhow!("We only support single data source at the moment"));
         }
 
+        // JOIN 的数据源在 load_sources 中处理，这里只返回第一个数据源
         let table = &source.0[0];
-        if !table.joins.is_empty() {
-            return Err(anyhow!("We do not support join data source at the moment"));
-        }
-
         match &table.relation {
             TableFactor::Table { name, .. } => Ok(&name.0.first().unwrap().value),
             _ => Err(anyhow!("We only support table")),
@@ -171,21 +678,45 @@ impl<'a> TryFrom<Source<'a>> for &'a str {
 }
 
 /// 把 SqlParser 的 order by expr 转换成 (列名，排序方法)
-impl<'a> TryFrom<Order<'a>> for (String, bool) {
+impl<'a> TryFrom<Order<'a>> for (Expr, bool) {
     type Error = anyhow::Error;
 
     fn try_from(o: Order<'a>) -> Result<Self, Self::Error> {
-        let name = match &o.0.expr {
-            SqlExpr::Identifier(id) => id.to_string(),
-            expr => {
-                return Err(anyhow!(
-                    "We only support identifier for order by, got {}",
-                    expr
-                ));
+        let Order(order, selection) = o;
+        let expr = match &order.expr {
+            // ORDER BY 1 表示按 select 中的第一列排序
+            SqlExpr::Value(SqlValue::Number(v, _)) => {
+                let expr = v
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| selection.get(i.checked_sub(1)?))
+                    .ok_or_else(|| anyhow!("order by position {} is out of range", v))?;
+                if matches!(expr, Expr::Wildcard)
+                    || matches!(expr, Expr::Column(name) if name.as_str() == "*")
+                {
+                    return Err(anyhow!("order by position {} refers to *", v));
+                }
+                unalias(expr)
             }
+            // select 中的别名优先于数据源中的列
+            SqlExpr::Identifier(id) => selection
+                .iter()
+                .find_map(|e| match e {
+                    Expr::Alias(expr, name) if name.as_str() == id.value => Some(*expr.clone()),
+                    _ => None,
+                })
+                .unwrap_or_else(|| col(&id.to_string())),
+            expr => Expression(Box::new(expr.to_owned())).try_into()?,
         };
 
-        Ok((name, !o.0.asc.unwrap_or(true)))
+        Ok((expr, !order.asc.unwrap_or(true)))
+    }
+}
+
+fn unalias(expr: &Expr) -> Expr {
+    match expr {
+        Expr::Alias(expr, _) => *expr.clone(),
+        expr => expr.clone(),
     }
 }
 
@@ -218,7 +749,15 @@ impl TryFrom<Value> for LiteralValue {
 
     fn try_from(v: Value) -> Result<Self, Self::Error> {
         match v.0 {
-            SqlValue::Number(v, _) => Ok(LiteralValue::Float64(v.parse().unwrap())),
+            // 没有小数点和指数的数字是整数，这样和整数列比较时不需要转换成浮点数
+            SqlValue::Number(v, _) => match v.parse::<i64>() {
+                Ok(n) => Ok(LiteralValue::Int64(n)),
+                Err(_) => v
+                    .parse()
+                    .map(LiteralValue::Float64)
+                    .map_err(|_| anyhow!("invalid number {}", v)),
+            },
+            SqlValue::SingleQuotedString(v) => Ok(LiteralValue::Utf8(v)),
             SqlValue::Boolean(v) => Ok(LiteralValue::Boolean(v)),
             SqlValue::Null => Ok(LiteralValue::Null),
             v => Err(anyhow!("Value {} is not supported", v)),
@@ -233,6 +772,7 @@ mod tests {
     use std::convert::TryInto;
 
     use super::*;
+    use polars::df;
 
     #[test]
     fn parse_sql_works() {
@@ -248,7 +788,179 @@ mod tests {
         assert_eq!(sql.source, url);
         assert_eq!(sql.limit, Some(5));
         assert_eq!(sql.offset, Some(10));
-        assert_eq!(sql.order_by, vec![("c".into(), true)]);
+        assert_eq!(sql.order_by, vec![(col("c"), true)]);
         assert_eq!(sql.selection, vec![col("a"), col("b"), col("c")]);
     }
+
+    #[test]
+    fn parse_group_by_works() {
+        let url = "http://abc.xyz/abc?a=1&b=2";
+        let sql = format!(
+            "select continent, sum(total_deaths) deaths, count(*) from {} \
+            group by continent having sum(total_deaths) > 1000",
+            url
+        );
+
+        let statement = &Parser::parse_sql(&JoDialect::default(), sql.as_ref()).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+
+        assert_eq!(sql.group_by, vec![col("continent")]);
+        assert_eq!(
+            sql.aggregation,
+            vec![
+                col("total_deaths").sum().alias("sum(total_deaths)"),
+                col(ROW_COUNT).count().alias("count(*)"),
+            ]
+        );
+        assert_eq!(
+            sql.selection,
+            vec![
+                col("continent"),
+                col("sum(total_deaths)").alias("deaths"),
+                col("count(*)"),
+            ]
+        );
+        assert_eq!(sql.having, Some(col("sum(total_deaths)").gt(lit(1000i64))));
+    }
+
+    #[test]
+    fn parse_projection_works() {
+        let sql = "select location name, new_deaths / total_cases AS ratio, total_cases + 1 \
+            from file:///a.csv order by 2 desc, name, abs(new_deaths)";
+
+        let statement = &Parser::parse_sql(&JoDialect::default(), sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+
+        let ratio = Expr::BinaryExpr {
+            left: Box::new(col("new_deaths")),
+            op: Operator::Divide,
+            right: Box::new(col("total_cases")),
+        };
+        let total = Expr::BinaryExpr {
+            left: Box::new(col("total_cases")),
+            op: Operator::Plus,
+            right: Box::new(lit(1i64)),
+        };
+        assert_eq!(
+            sql.selection,
+            vec![
+                col("location").alias("name"),
+                ratio.clone().alias("ratio"),
+                total.alias("total_cases + 1"),
+            ]
+        );
+        assert_eq!(sql.order_by[0], (ratio, true));
+        assert_eq!(sql.order_by[1], (col("location"), false));
+        assert_eq!(sql.order_by.len(), 3);
+
+        let sql = "select * from file:///a.csv order by 1";
+        let statement = &Parser::parse_sql(&JoDialect::default(), sql).unwrap()[0];
+        assert!(Sql::try_from(statement).is_err());
+    }
+
+    fn filter(condition: &str) -> usize {
+        let df = df!(
+            "name" => &[Some("Asia"), Some("Europe"), Some("north america"), None],
+            "total" => &[10.0, -2.5, 3.0, 7.0]
+        )
+        .unwrap();
+        let sql = format!("select * from file:///a.csv where {}", condition);
+        let statement = &Parser::parse_sql(&JoDialect::default(), &sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        let df = df.lazy().filter(sql.condition.unwrap()).collect().unwrap();
+        df.height()
+    }
+
+    #[test]
+    fn scalar_expressions_works() {
+        assert_eq!(filter("name = 'Asia'"), 1);
+        assert_eq!(filter("name LIKE '%a'"), 2);
+        assert_eq!(filter("name NOT LIKE 'A%'"), 2);
+        assert_eq!(filter("name ILIKE 'north_america'"), 1);
+        assert_eq!(filter("name IN ('Asia', 'Europe')"), 2);
+        assert_eq!(filter("total NOT BETWEEN -3 AND 5"), 2);
+        assert_eq!(filter("-total > 0 OR NOT (total < 8)"), 2);
+        assert_eq!(
+            filter("CASE WHEN total > 5 THEN 'big' ELSE 'small' END = 'big'"),
+            2
+        );
+        assert_eq!(filter("CAST(total AS INT) = 3"), 1);
+        assert_eq!(filter("upper(name) = 'EUROPE' OR length(name) = 13"), 2);
+        assert_eq!(filter("abs(total) = 2.5 AND round(total, 1) = -2.5"), 1);
+        assert_eq!(filter("coalesce(name, 'none') = 'none'"), 1);
+    }
+
+    #[test]
+    fn literal_types_works() {
+        let number = |v: &str| LiteralValue::try_from(Value(SqlValue::Number(v.into(), false)));
+        assert_eq!(number("42").unwrap(), LiteralValue::Int64(42));
+        assert_eq!(number("4.5").unwrap(), LiteralValue::Float64(4.5));
+        assert!(number("4.5.1").is_err());
+
+        let sql = "select * from file:///a.csv where d >= DATE '2021-01-02' \
+            and t < TIMESTAMP '1970-01-01 00:00:01'";
+        let statement = &Parser::parse_sql(&JoDialect::default(), sql).unwrap()[0];
+        let sql: Sql = statement.try_into().unwrap();
+        let expected = col("d")
+            .gt_eq(lit(18629).cast(DataType::Date32))
+            .and(col("t").lt(lit(1000i64).cast(DataType::Date64)));
+        assert_eq!(sql.condition, Some(expected));
+    }
+
+    #[test]
+    fn cast_literals_works() {
+        let schema = Schema::new(vec![
+            Field::new("d", DataType::Date32),
+            Field::new("n", DataType::Int32),
+        ]);
+        let expr = col("d").gt(lit("2021-01-02")).and(lit(3i64).lt(col("n")));
+        let expected = col("d")
+            .gt(lit(18629).cast(DataType::Date32))
+            .and(col("n").gt(lit(3i64).cast(DataType::Int32)));
+        assert_eq!(cast_literals(expr, &schema), expected);
+
+        // 整数列和小数比较时不能转换成整数
+        let expr = col("n").gt(lit(2.5));
+        assert_eq!(cast_literals(expr.clone(), &schema), expr);
+
+        // 超出范围的整数不转换，而是把列转换成 Int64
+        let expr = col("n").lt(lit(3_000_000_000i64));
+        let expected = col("n").cast(DataType::Int64).lt(lit(3_000_000_000i64));
+        assert_eq!(cast_literals(expr, &schema), expected);
+    }
+
+    #[test]
+    fn cast_literals_should_filter_by_column_type() {
+        let df = DataFrame::new(vec![
+            Series::new("d", &[18628i32, 18629, 18630])
+                .cast::<Date32Type>()
+                .unwrap(),
+            Series::new("i", &[-1i32, 0, 5]),
+            Series::new("u", &[0u32, 5, u32::MAX]),
+        ])
+        .unwrap();
+        let count = |expr: Expr| {
+            let expr = cast_literals(expr, &df.schema());
+            df.clone().lazy().filter(expr).collect().unwrap().height()
+        };
+
+        assert_eq!(count(col("d").eq(lit("2021-01-02"))), 1);
+        assert_eq!(count(col("d").gt(lit("2021-01-01"))), 2);
+        assert_eq!(count(lit("2021-01-02").gt_eq(col("d"))), 2);
+        assert_eq!(count(lit("2021-01-02").lt(col("d"))), 1);
+
+        // 超出 Int32 范围的整数
+        assert_eq!(count(col("i").lt(lit(3_000_000_000i64))), 3);
+        assert_eq!(count(col("i").gt(lit(-3_000_000_000i64))), 3);
+        assert_eq!(count(col("i").eq(lit(3_000_000_000i64))), 0);
+        assert_eq!(count(col("i").lt(lit(0i64))), 1);
+        assert_eq!(count(lit(1i64).gt_eq(col("i"))), 2);
+
+        // 无符号的列和负数、超出 UInt32 范围的整数比较
+        assert_eq!(count(col("u").gt(lit(-1i64))), 3);
+        assert_eq!(count(col("u").lt(lit(-1i64))), 0);
+        assert_eq!(count(lit(-1i64).lt(col("u"))), 3);
+        assert_eq!(count(col("u").lt(lit(5_000_000_000i64))), 3);
+        assert_eq!(count(col("u").eq(lit(u32::MAX as i64))), 1);
+    }
 }
@@ -1,33 +1,53 @@
+use std::{sync::Arc, time::UNIX_EPOCH};
+
 use anyhow::{anyhow, Result};
 use async_trait::async_trait;
+use reqwest::{
+    header::{CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    StatusCode,
+};
 use tokio::fs;
 use tracing::info;
 
+use crate::cache::{self, CacheConfig, Cached, Meta};
+
+/// 获取到的原始数据，以及 http 源返回的 Content-Type，用来判断数据的格式
+/// 数据不一定是文本，parquet 之类的格式是二进制的
+#[derive(Debug, Default)]
+pub struct Content {
+    /// 和缓存共享同一份数据，不需要复制
+    pub(crate) data: Arc<Vec<u8>>,
+    pub(crate) content_type: Option<String>,
+    /// 数据的版本，版本相同时数据没有变化，为 None 时不缓存解析好的 DataFrame
+    pub(crate) version: Option<String>,
+}
+
 // Rust 的 async trait 还没有稳定，可以用 async_trait 宏
 #[async_trait]
 pub trait Fetch {
     type Error;
-    async fn fetch(&self) -> Result<String, Self::Error>;
+    async fn fetch(&self) -> Result<Content, Self::Error>;
 }
 
 /// 从文件源或者 http 源中获取数据，组成 DataFrame
-pub async fn retrieve_data(source: impl AsRef<str>) -> Result<String> {
+pub async fn retrieve_data(source: impl AsRef<str>) -> Result<Content> {
     let name = source.as_ref();
-    match &name[..4] {
-        "http" => UrlFetcher(name).fetch().await,
-        "file" => FileFetcher(name).fetch().await,
+    match name.get(..4) {
+        Some("http") => UrlFetcher(name, cache::cache_config()).fetch().await,
+        Some("file") => FileFetcher(name).fetch().await,
         _ => return Err(anyhow!("We only support http/https/file at the moment")),
     }
 }
 
-struct UrlFetcher<'a>(pub(crate) &'a str);
+/// http 源和使用的缓存配置
+struct UrlFetcher<'a>(pub(crate) &'a str, pub(crate) CacheConfig);
 struct FileFetcher<'a>(pub(crate) &'a str);
 
 #[async_trait]
 impl<'a> Fetch for UrlFetcher<'a> {
     type Error = anyhow::Error;
 
-    async fn fetch(&self) -> Result<String, Self::Error> {
+    async fn fetch(&self) -> Result<Content, Self::Error> {
         // Ok(reqwest::Client::builder()
         //     .proxy(reqwest::Proxy::all("http://172.27.64.1:7890")?)
         //     .build()?
@@ -37,7 +57,60 @@ impl<'a> Fetch for UrlFetcher<'a> {
         //     .text()
         //     .await?)
 
-        Ok(reqwest::get(self.0).await?.text().await?)
+        let (url, config) = (self.0, &self.1);
+        let cached = cache::get(url, config).await;
+        if let Some(cached) = &cached {
+            if cached.is_fresh(config.ttl) {
+                info!("using cached data: {}", url);
+                return Ok(cached.content());
+            }
+        }
+
+        // 缓存过期之后带上 ETag 和 Last-Modified，让服务器判断数据有没有变化
+        let mut req = reqwest::Client::new().get(url);
+        if let Some(meta) = cached.as_ref().map(|c| &c.meta) {
+            if let Some(etag) = &meta.etag {
+                req = req.header(IF_NONE_MATCH, etag.as_str());
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified.as_str());
+            }
+        }
+        let res = req.send().await?;
+
+        let status = res.status();
+        match cached {
+            Some(mut cached) if status == StatusCode::NOT_MODIFIED => {
+                info!("cached data is not modified: {}", url);
+                cached.meta.fetched_at = cache::now();
+                cache::store(url, &cached, config, false).await;
+                return Ok(cached.content());
+            }
+            _ => {}
+        }
+        // 出错的响应不能当成数据
+        if !status.is_success() {
+            return Err(anyhow!("failed to fetch {}: {}", url, status));
+        }
+
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned())
+        };
+        let meta = Meta {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            content_type: header(CONTENT_TYPE),
+            fetched_at: cache::now(),
+        };
+        let cached = Cached {
+            meta,
+            data: Arc::new(res.bytes().await?.to_vec()),
+        };
+        cache::store(url, &cached, config, true).await;
+        Ok(cached.content())
     }
 }
 
@@ -45,8 +118,144 @@ impl<'a> Fetch for UrlFetcher<'a> {
 impl<'a> Fetch for FileFetcher<'a> {
     type Error = anyhow::Error;
 
-    async fn fetch(&self) -> Result<String, Self::Error> {
-        info!("reading: {}", &self.0[7..]);
-        Ok(fs::read_to_string(&self.0[7..]).await?)
+    async fn fetch(&self) -> Result<Content, Self::Error> {
+        let path = &self.0[7..];
+        info!("reading: {}", path);
+        // 用修改时间和大小作为版本，先取版本再读数据，读的过程中文件变了下次会重新解析
+        let meta = fs::metadata(path).await?;
+        let modified = meta
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        Ok(Content {
+            data: Arc::new(fs::read(path).await?),
+            content_type: None,
+            version: Some(format!("{}-{}", modified, meta.len())),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    const LAST_MODIFIED: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    /// 简单的 http 服务器：带着匹配的 If-None-Match 返回 304，/missing 返回 404，
+    /// 否则返回数据。收到的请求头记录在返回的 Vec 中
+    async fn serve() -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut buf = Vec::new();
+                while !buf.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0u8];
+                    if stream.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    buf.push(byte[0]);
+                }
+                let req = String::from_utf8_lossy(&buf).to_lowercase();
+                log.lock().unwrap().push(req.clone());
+
+                let res = if req.starts_with("get /missing") {
+                    "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n".to_string()
+                } else if req.contains("if-none-match: \"v1\"") {
+                    "HTTP/1.1 304 Not Modified\r\n\r\n".to_string()
+                } else {
+                    let body = "a,b\n1,2\n";
+                    format!(
+                        "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nLast-Modified: {}\r\n\
+                         Content-Type: text/csv\r\nContent-Length: {}\r\n\r\n{}",
+                        LAST_MODIFIED,
+                        body.len(),
+                        body
+                    )
+                };
+                stream.write_all(res.as_bytes()).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+        });
+        (format!("http://{}", addr), requests)
+    }
+
+    async fn fetch(url: &str, config: &CacheConfig) -> Result<Content> {
+        UrlFetcher(url, config.clone()).fetch().await
+    }
+
+    #[tokio::test]
+    async fn url_fetcher_should_revalidate_expired_cache() {
+        let (base, requests) = serve().await;
+        let url = format!("{}/data.csv", base);
+        // 不修改全局的配置，避免影响同时运行的其他测试
+        let config = CacheConfig {
+            dir: None,
+            ttl: Duration::from_secs(3600),
+        };
+
+        let content = fetch(&url, &config).await.unwrap();
+        assert_eq!(content.data.as_slice(), b"a,b\n1,2\n");
+        assert_eq!(content.content_type.as_deref(), Some("text/csv"));
+        assert_eq!(content.version.as_deref(), Some("\"v1\""));
+        assert!(!requests.lock().unwrap()[0].contains("if-none-match"));
+
+        // 没有过期时不发请求，和缓存共享同一份数据
+        let cached = fetch(&url, &config).await.unwrap();
+        assert_eq!(requests.lock().unwrap().len(), 1);
+        assert!(Arc::ptr_eq(&cached.data, &content.data));
+
+        // 过期之后带上 ETag 和 Last-Modified 确认，304 时继续使用缓存的数据
+        let config = CacheConfig {
+            ttl: Duration::ZERO,
+            ..config
+        };
+        let before = cache::now();
+        let revalidated = fetch(&url, &config).await.unwrap();
+        {
+            let requests = requests.lock().unwrap();
+            assert_eq!(requests.len(), 2);
+            assert!(requests[1].contains("if-none-match: \"v1\""));
+            let since = format!("if-modified-since: {}", LAST_MODIFIED.to_lowercase());
+            assert!(requests[1].contains(&since));
+        }
+        assert!(Arc::ptr_eq(&revalidated.data, &content.data));
+        assert_eq!(revalidated.version, content.version);
+        let cached = cache::get(&url, &config).await.unwrap();
+        assert!(cached.meta.fetched_at >= before);
+
+        // 出错的响应返回错误，也不会被缓存
+        let missing = format!("{}/missing.csv", base);
+        assert!(fetch(&missing, &config).await.is_err());
+        assert!(cache::get(&missing, &config).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn file_fetcher_should_change_version_with_file() {
+        let path = std::env::temp_dir().join(format!("queryer-fetch-{}.csv", std::process::id()));
+        let url = format!("file://{}", path.display());
+        fs::write(&path, "a\n1\n").await.unwrap();
+        let v1 = FileFetcher(&url).fetch().await.unwrap().version;
+        assert_eq!(v1, FileFetcher(&url).fetch().await.unwrap().version);
+
+        // 大小和修改时间都变了
+        fs::write(&path, "a\n1\n2\n").await.unwrap();
+        let file = std::fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + Duration::from_secs(10))
+            .unwrap();
+        assert_ne!(v1, FileFetcher(&url).fetch().await.unwrap().version);
+
+        fs::remove_file(path).await.unwrap();
     }
 }
@@ -8,15 +8,26 @@ use polars::prelude::*;
 use sqlparser::parser::Parser;
 use tracing::info;
 
-use crate::{convert::Sql, fetcher::retrieve_data, loader::detect_content};
+use crate::{
+    convert::{cast_literals, Sql, ROW_COUNT},
+    source::{load, load_sources},
+};
 
+mod cache;
 mod convert;
 mod dialect;
 mod fetcher;
 mod loader;
+mod output;
+mod source;
 
+pub use cache::{set_cache_config, CacheConfig};
 pub use dialect::example_sql;
 pub use dialect::JoDialect;
+pub use output::OutputFormat;
+
+/// ORDER BY 的表达式计算成的临时列
+const ORDER_BY_PREFIX: &str = "__order_by_";
 
 #[derive(Debug)]
 pub struct DataSet(DataFrame);
@@ -47,45 +58,163 @@ impl DataSet {
     }
 }
 
+/// 读入数据源，返回每一列的名字和类型
+pub async fn schema(source: impl AsRef<str>) -> Result<DataSet> {
+    let df = load(source.as_ref()).await?;
+    let (names, types): (Vec<_>, Vec<_>) = df
+        .schema()
+        .fields()
+        .iter()
+        .map(|f| (f.name().to_owned(), f.data_type().to_string()))
+        .unzip();
+    Ok(DataSet(DataFrame::new(vec![
+        Series::new("column", names),
+        Series::new("type", types),
+    ])?))
+}
+
 /// 从 from 中获取数据，从 where 中过滤，最后选取需要返回的列
 pub async fn query<T: AsRef<str>>(sql: T) -> Result<DataSet> {
-    let ast = Parser::parse_sql(&JoDialect::default(), sql.as_ref())?;
+    let mut ast = Parser::parse_sql(&JoDialect, sql.as_ref())?;
 
     if ast.len() != 1 {
         return Err(anyhow!("Only support single sql at the moment"));
     }
 
+    // 先读入 FROM 和 JOIN 中的数据源，JOIN 之后的列名需要根据读入的数据确定，
+    // load_sources 会把 SQL 中引用的列名改成合并之后的列名
+    let ds = load_sources(&mut ast[0]).await?;
     let sql = &ast[0];
+    execute(ds, sql.try_into()?)
+}
 
+/// 在读入的数据上执行 SQL
+fn execute(ds: DataSet, sql: Sql) -> Result<DataSet> {
     // 整个 SQL AST 转换成我们定义的 Sql 结构的细节都 隐藏在 try_into() 中
     // 我们只需要关注数据结构的使用，怎么转换可以之后需要的时候才关注，这是
     // 关注点分离，是我们控制软件复杂度的法宝。
     let Sql {
         source,
         condition,
-        selection,
+        group_by,
+        aggregation,
+        having,
+        mut selection,
         offset,
         limit,
         order_by,
-    } = sql.try_into()?;
-
-    info!("retrieving data from source: {}", source);
+    } = sql;
 
-    // 从 source 读入一个 DataSet
-    // detect_content，怎么 detect 不重要，重要的是它能根据内容返回 DataSet
-    let ds = detect_content(retrieve_data(source).await?).load()?;
+    info!("querying data from source: {}", source);
 
+    // 字面量转换成和它比较的列的类型，避免日期、整数等都按照浮点数比较
+    let schema = ds.schema();
     let mut filtered = match condition {
-        Some(expr) => ds.0.lazy().filter(expr),
+        Some(expr) => ds.0.lazy().filter(cast_literals(expr, &schema)),
         None => ds.0.lazy(),
     };
 
-    filtered = order_by
-        .into_iter()
-        .fold(filtered, |acc, (col, desc)| acc.sort(&col, desc));
+    // 有 GROUP BY 或者聚合函数时先聚合再选择
+    if !group_by.is_empty() || !aggregation.is_empty() {
+        filtered = filtered.with_column(lit(1).alias(ROW_COUNT));
+        filtered = match group_by.is_empty() {
+            true => filtered.select(aggregation),
+            false => filtered.groupby(group_by).agg(aggregation),
+        };
+        if let Some(expr) = having {
+            filtered = filtered.filter(expr);
+        }
+    }
+
+    // 排序的表达式和 selection 一起计算成临时的列，排序之后再去掉
+    // 多个排序的列一次排序，前面的列优先
+    let mut order_columns = Vec::with_capacity(order_by.len());
+    let mut reverse = Vec::with_capacity(order_by.len());
+    for (i, (expr, desc)) in order_by.into_iter().enumerate() {
+        let name = format!("{}{}", ORDER_BY_PREFIX, i);
+        selection.push(expr.alias(&name));
+        order_columns.push(name);
+        reverse.push(desc);
+    }
+    filtered = filtered.select(selection);
+    let by = order_columns.iter().map(|name| col(name)).collect();
+    filtered = filtered.sort_by_exprs(by, reverse);
 
     if offset.is_some() || limit.is_some() {
         filtered = filtered.slice(offset.unwrap_or(0), limit.unwrap_or(usize::MAX));
     }
-    Ok(DataSet(filtered.select(selection).collect()?))
+
+    let mut df = filtered.collect()?;
+    for name in order_columns {
+        df.drop_in_place(&name)?;
+    }
+    Ok(DataSet(df))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    #[tokio::test]
+    async fn order_by_should_sort_by_all_keys() {
+        let path = std::env::temp_dir().join(format!("queryer-order-{}.csv", std::process::id()));
+        let data = "name,team,score\na,1,3\nb,2,1\nc,1,5\nd,2,4\ne,1,4\n";
+        tokio::fs::write(&path, data).await.unwrap();
+
+        let sql = format!(
+            "SELECT name FROM file://{} ORDER BY team, score DESC",
+            path.display()
+        );
+        let ds = query(sql).await.unwrap();
+        let names: Vec<_> = ds
+            .column("name")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(names, ["c", "e", "a", "d", "b"]);
+        assert_eq!(ds.width(), 1);
+
+        tokio::fs::remove_file(path).await.unwrap();
+    }
+
+    #[test]
+    fn group_by_should_count_and_filter_by_having() {
+        let df = df!(
+            "team" => &["a", "a", "a", "b", "b", "c"],
+            "player" => &[Some("x"), Some("x"), None, Some("y"), Some("z"), None]
+        )
+        .unwrap();
+        let sql = "SELECT team, count(distinct player) players, count(*) \
+            FROM t GROUP BY team HAVING count(*) > 1 ORDER BY team";
+        let ast = Parser::parse_sql(&JoDialect, sql).unwrap();
+        let ds = execute(DataSet(df), (&ast[0]).try_into().unwrap()).unwrap();
+
+        assert_eq!(ds.get_column_names(), ["team", "players", "count(*)"]);
+        let teams: Vec<_> = ds
+            .column("team")
+            .unwrap()
+            .utf8()
+            .unwrap()
+            .into_no_null_iter()
+            .collect();
+        assert_eq!(teams, ["a", "b"]);
+        // NULL 不算作不同的值
+        let players = ds.column("players").unwrap().cast::<UInt32Type>().unwrap();
+        assert_eq!(
+            players
+                .u32()
+                .unwrap()
+                .into_no_null_iter()
+                .collect::<Vec<_>>(),
+            [1, 2]
+        );
+        let count = ds.column("count(*)").unwrap().cast::<UInt32Type>().unwrap();
+        assert_eq!(
+            count.u32().unwrap().into_no_null_iter().collect::<Vec<_>>(),
+            [3, 2]
+        );
+    }
 }
@@ -1,9 +1,9 @@
-use std::io::Cursor;
+use std::{io::Cursor, str::FromStr, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use polars::prelude::*;
 
-use crate::DataSet;
+use crate::{fetcher::Content, DataSet};
 
 pub trait Load {
     type Error;
@@ -14,33 +14,185 @@ pub trait Load {
 #[non_exhaustive]
 pub enum Loader {
     Csv(CsvLoader),
+    Tsv(TsvLoader),
     Json(JsonLoader),
+    NdJson(NdJsonLoader),
+    Parquet(ParquetLoader),
+    Ipc(IpcLoader),
 }
 
 #[derive(Debug, Default)]
-pub struct CsvLoader(pub(crate) String);
+pub struct CsvLoader(pub(crate) Arc<Vec<u8>>);
 #[derive(Debug, Default)]
-pub struct JsonLoader(pub(crate) String);
+pub struct TsvLoader(pub(crate) Arc<Vec<u8>>);
+/// JSON 数组，内容不是数组时按 NDJSON 处理
+#[derive(Debug, Default)]
+pub struct JsonLoader(pub(crate) Arc<Vec<u8>>);
+/// 每行一个 JSON 对象
+#[derive(Debug, Default)]
+pub struct NdJsonLoader(pub(crate) Arc<Vec<u8>>);
+#[derive(Debug, Default)]
+pub struct ParquetLoader(pub(crate) Arc<Vec<u8>>);
+/// Arrow IPC 文件，也就是 feather v2
+#[derive(Debug, Default)]
+pub struct IpcLoader(pub(crate) Arc<Vec<u8>>);
+
+/// parquet 文件以 PAR1 开头
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+/// Arrow IPC 文件以 ARROW1 开头
+const IPC_MAGIC: &[u8] = b"ARROW1";
+
+/// 数据源的格式，可以在 SQL 中用 `格式:` 前缀显式指定，比如 `FROM tsv:file:///data.txt`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Tsv,
+    Json,
+    NdJson,
+    Parquet,
+    Ipc,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "tsv" => Ok(Self::Tsv),
+            "json" => Ok(Self::Json),
+            "ndjson" | "jsonl" => Ok(Self::NdJson),
+            "parquet" => Ok(Self::Parquet),
+            "ipc" | "arrow" | "feather" => Ok(Self::Ipc),
+            v => Err(anyhow!("format {} is not supported", v)),
+        }
+    }
+}
+
+impl Format {
+    /// 根据 Content-Type 判断格式，text/plain 之类的通用类型返回 None
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+        match mime.as_str() {
+            "text/csv" | "application/csv" => Some(Self::Csv),
+            "text/tab-separated-values" => Some(Self::Tsv),
+            "application/json" | "text/json" => Some(Self::Json),
+            "application/x-ndjson" | "application/jsonl" | "application/x-jsonlines" => {
+                Some(Self::NdJson)
+            }
+            "application/vnd.apache.parquet" | "application/x-parquet" => Some(Self::Parquet),
+            "application/vnd.apache.arrow.file" => Some(Self::Ipc),
+            _ => None,
+        }
+    }
+
+    /// 根据文件或者 URL 路径的扩展名判断格式，忽略 URL 的 query 和 fragment
+    fn from_path(path: &str) -> Option<Self> {
+        let path = path.split(['?', '#']).next()?;
+        let name = path.rsplit('/').next()?;
+        let (_, ext) = name.rsplit_once('.')?;
+        match ext.to_ascii_lowercase().as_str() {
+            "csv" => Some(Self::Csv),
+            "tsv" | "tab" => Some(Self::Tsv),
+            "json" => Some(Self::Json),
+            "ndjson" | "jsonl" => Some(Self::NdJson),
+            "parquet" | "pq" => Some(Self::Parquet),
+            "arrow" | "ipc" | "feather" => Some(Self::Ipc),
+            _ => None,
+        }
+    }
+
+    /// 根据内容的开头判断格式：二进制格式看文件头，
+    /// 文本中 `[` 是 JSON 数组，`{` 是 NDJSON，否则看第一行 tab 和逗号哪个多
+    fn sniff(data: &[u8]) -> Self {
+        if data.starts_with(PARQUET_MAGIC) {
+            return Self::Parquet;
+        }
+        if data.starts_with(IPC_MAGIC) {
+            return Self::Ipc;
+        }
+
+        // 只需要看开头的一部分，截断的 UTF-8 字符不影响判断
+        let head = String::from_utf8_lossy(&data[..data.len().min(4096)]);
+        let data = head.trim_start_matches('\u{feff}').trim_start();
+        match data.as_bytes().first() {
+            Some(b'[') => Self::Json,
+            Some(b'{') => Self::NdJson,
+            _ => {
+                let line = data.lines().next().unwrap_or_default();
+                let tabs = line.matches('\t').count();
+                match tabs > line.matches(',').count() {
+                    true => Self::Tsv,
+                    false => Self::Csv,
+                }
+            }
+        }
+    }
+}
+
+/// 拆出 source 中显式指定的格式，比如 `ndjson:https://...` 返回 (Some(NdJson), "https://...")
+pub fn split_format(source: &str) -> Result<(Option<Format>, &str)> {
+    match source.split_once(':') {
+        Some((prefix, rest)) if rest.starts_with("http") || rest.starts_with("file") => {
+            Ok((Some(prefix.parse()?), rest))
+        }
+        _ => Ok((None, source)),
+    }
+}
+
+/// 判断数据的格式，优先级依次是：显式指定的格式，Content-Type，扩展名，内容
+pub fn detect_content(format: Option<Format>, source: &str, content: Content) -> Loader {
+    let format = format
+        .or_else(|| {
+            content
+                .content_type
+                .as_deref()
+                .and_then(Format::from_content_type)
+        })
+        .or_else(|| Format::from_path(source))
+        .unwrap_or_else(|| Format::sniff(&content.data));
+
+    let data = content.data;
+    match format {
+        Format::Csv => Loader::Csv(CsvLoader(data)),
+        Format::Tsv => Loader::Tsv(TsvLoader(data)),
+        Format::Json => Loader::Json(JsonLoader(data)),
+        Format::NdJson => Loader::NdJson(NdJsonLoader(data)),
+        Format::Parquet => Loader::Parquet(ParquetLoader(data)),
+        Format::Ipc => Loader::Ipc(IpcLoader(data)),
+    }
+}
 
 impl Loader {
     pub fn load(self) -> Result<DataSet> {
         match self {
             Loader::Csv(csv) => csv.load(),
+            Loader::Tsv(tsv) => tsv.load(),
             Loader::Json(json) => json.load(),
+            Loader::NdJson(ndjson) => ndjson.load(),
+            Loader::Parquet(parquet) => parquet.load(),
+            Loader::Ipc(ipc) => ipc.load(),
         }
     }
 }
 
-pub fn detect_content(data: String) -> Loader {
-    // TODO 内容检测
-    Loader::Csv(CsvLoader(data))
+impl Load for CsvLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let df = CsvReader::new(Cursor::new(self.0.as_slice()))
+            .infer_schema(Some(16))
+            .finish()?;
+        Ok(DataSet(df))
+    }
 }
 
-impl Load for CsvLoader {
+impl Load for TsvLoader {
     type Error = anyhow::Error;
 
     fn load(self) -> Result<DataSet, Self::Error> {
-        let df = CsvReader::new(Cursor::new(self.0))
+        let df = CsvReader::new(Cursor::new(self.0.as_slice()))
+            .with_delimiter(b'\t')
             .infer_schema(Some(16))
             .finish()?;
         Ok(DataSet(df))
@@ -51,9 +203,159 @@ impl Load for JsonLoader {
     type Error = anyhow::Error;
 
     fn load(self) -> Result<DataSet, Self::Error> {
-        let df = JsonReader::new(Cursor::new(self.0))
+        // polars 的 JsonReader 读的是每行一个对象，JSON 数组需要先拆成行
+        let is_array = self.0.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'[');
+        if !is_array {
+            return NdJsonLoader(self.0).load();
+        }
+
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&self.0)?;
+        let data = rows
+            .iter()
+            .map(|row| row.to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        NdJsonLoader(Arc::new(data.into_bytes())).load()
+    }
+}
+
+impl Load for NdJsonLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let df = JsonReader::new(Cursor::new(self.0.as_slice()))
             .infer_schema(Some(16))
             .finish()?;
         Ok(DataSet(df))
     }
 }
+
+impl Load for ParquetLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        // parquet 需要随机读取文件的末尾，要用实现了 ChunkReader 的 SliceableCursor
+        let df = ParquetReader::new(SliceableCursor::new(self.0)).finish()?;
+        Ok(DataSet(df))
+    }
+}
+
+impl Load for IpcLoader {
+    type Error = anyhow::Error;
+
+    fn load(self) -> Result<DataSet, Self::Error> {
+        let df = IpcReader::new(Cursor::new(self.0.as_slice())).finish()?;
+        Ok(DataSet(df))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    fn detect(
+        format: Option<Format>,
+        source: &str,
+        content_type: Option<&str>,
+        data: &str,
+    ) -> Format {
+        let content = Content {
+            data: Arc::new(data.as_bytes().to_vec()),
+            content_type: content_type.map(|v| v.into()),
+            version: None,
+        };
+        match detect_content(format, source, content) {
+            Loader::Csv(_) => Format::Csv,
+            Loader::Tsv(_) => Format::Tsv,
+            Loader::Json(_) => Format::Json,
+            Loader::NdJson(_) => Format::NdJson,
+            Loader::Parquet(_) => Format::Parquet,
+            Loader::Ipc(_) => Format::Ipc,
+        }
+    }
+
+    #[test]
+    fn split_format_works() {
+        let url = "https://abc.xyz/data?a=1";
+        assert_eq!(split_format(url).unwrap(), (None, url));
+        let source = format!("tsv:{}", url);
+        assert_eq!(split_format(&source).unwrap(), (Some(Format::Tsv), url));
+        assert!(split_format("xml:file:///data.xml").is_err());
+    }
+
+    #[test]
+    fn detect_content_works() {
+        let csv = "a,b\n1,2\n";
+        let tsv = "a\tb\n1\t2\n";
+        // 显式指定的格式优先于其它的判断
+        assert_eq!(
+            detect(Some(Format::Tsv), "file:///a.csv", None, csv),
+            Format::Tsv
+        );
+        assert_eq!(
+            detect(
+                None,
+                "http://x/a.csv",
+                Some("text/tab-separated-values"),
+                csv
+            ),
+            Format::Tsv
+        );
+        // 通用的 Content-Type 不能说明格式
+        assert_eq!(
+            detect(None, "http://x/a.tsv?v=1", Some("text/plain"), csv),
+            Format::Tsv
+        );
+        assert_eq!(detect(None, "file:///a.txt", None, tsv), Format::Tsv);
+        assert_eq!(detect(None, "file:///a.txt", None, csv), Format::Csv);
+        assert_eq!(
+            detect(None, "file:///a", None, " [{\"a\": 1}]"),
+            Format::Json
+        );
+        assert_eq!(
+            detect(None, "file:///a", None, "{\"a\": 1}\n"),
+            Format::NdJson
+        );
+        assert_eq!(detect(None, "file:///a", None, "PAR1...."), Format::Parquet);
+        assert_eq!(detect(None, "http://x/a.feather", None, "..."), Format::Ipc);
+    }
+
+    // 不带扩展名和 Content-Type，只能根据内容判断格式
+    fn round_trip(data: Vec<u8>) -> Loader {
+        let content = Content {
+            data: Arc::new(data),
+            content_type: None,
+            version: None,
+        };
+        detect_content(None, "file:///data", content)
+    }
+
+    fn frame() -> DataFrame {
+        df!(
+            "name" => &["a", "b", "c"],
+            "total" => &[Some(1.5), None, Some(3.0)],
+            "count" => &[1i64, 2, 3]
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parquet_should_round_trip() {
+        let df = frame();
+        let data = DataSet(df.clone()).to_parquet().unwrap();
+        let loader = round_trip(data);
+        assert!(matches!(loader, Loader::Parquet(_)));
+        assert!(loader.load().unwrap().frame_equal_missing(&df));
+    }
+
+    #[test]
+    fn ipc_should_round_trip() {
+        let df = frame();
+        let mut data = Vec::new();
+        IpcWriter::new(&mut data).finish(&df).unwrap();
+        let loader = round_trip(data);
+        assert!(matches!(loader, Loader::Ipc(_)));
+        assert!(loader.load().unwrap().frame_equal_missing(&df));
+    }
+}
@@ -0,0 +1,151 @@
+use std::{fs, io::Write, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use queryer::{query, schema, DataSet, OutputFormat};
+use rustyline::{error::ReadlineError, Editor};
+
+/// 用 SQL 查询 csv/json/parquet 等数据源，不带 -e 时进入交互模式
+#[derive(Parser, Debug)]
+#[clap(version = "1.0", author = "JoJo")]
+struct Opts {
+    /// 执行一条 SQL 后退出
+    #[clap(short, long)]
+    execute: Option<String>,
+    /// 结果写入的文件，默认输出到终端
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+    /// 输出格式：csv、json、json-columns、ndjson、parquet、markdown、table，
+    /// 写入文件时默认根据扩展名判断
+    #[clap(short, long)]
+    format: Option<OutputFormat>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let opts = Opts::parse();
+    match opts.execute {
+        Some(sql) => {
+            let format = match (opts.format, &opts.output) {
+                (Some(format), Some(_)) => format,
+                (Some(format), None) => terminal_format(format)?,
+                (None, Some(path)) => path
+                    .extension()
+                    .and_then(|ext| ext.to_str()?.parse().ok())
+                    .ok_or_else(|| anyhow!("cannot infer output format of {:?}", path))?,
+                (None, None) => OutputFormat::Table,
+            };
+            let data = query(sql).await?.write(format)?;
+            match opts.output {
+                Some(path) => fs::write(path, data)?,
+                None => std::io::stdout().write_all(&data)?,
+            }
+            Ok(())
+        }
+        None => repl(terminal_format(opts.format.unwrap_or(OutputFormat::Table))?).await,
+    }
+}
+
+const PROMPT: &str = "queryer> ";
+const CONTINUATION: &str = "     ...> ";
+
+/// 交互模式，SQL 以 `;` 结束，可以跨越多行，以 `.` 开头的是元命令
+async fn repl(mut format: OutputFormat) -> Result<()> {
+    let history = std::env::var("HOME")
+        .ok()
+        .map(|home| PathBuf::from(home).join(".queryer_history"));
+    let mut rl = Editor::<()>::new();
+    if let Some(path) = &history {
+        // 第一次运行时历史文件还不存在
+        let _ = rl.load_history(path);
+    }
+
+    let mut buf = String::new();
+    loop {
+        let prompt = if buf.is_empty() { PROMPT } else { CONTINUATION };
+        let line = match rl.readline(prompt) {
+            Ok(line) => line,
+            // Ctrl-C 放弃正在输入的 SQL
+            Err(ReadlineError::Interrupted) => {
+                buf.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let line = line.trim();
+        if buf.is_empty() && line.starts_with('.') {
+            rl.add_history_entry(line);
+            match meta_command(line, &mut format).await {
+                Ok(true) => continue,
+                Ok(false) => break,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    continue;
+                }
+            }
+        }
+
+        if !buf.is_empty() {
+            buf.push('\n');
+        }
+        buf.push_str(line);
+        if !buf.ends_with(';') {
+            continue;
+        }
+
+        let sql = std::mem::take(&mut buf);
+        rl.add_history_entry(sql.as_str());
+        match query(sql.trim_end_matches(';')).await {
+            Ok(ds) => print(&ds, format),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+    }
+
+    if let Some(path) = &history {
+        rl.save_history(path)?;
+    }
+    Ok(())
+}
+
+/// 执行元命令，返回 false 表示退出
+async fn meta_command(line: &str, format: &mut OutputFormat) -> Result<bool> {
+    let (cmd, arg) = match line.split_once(char::is_whitespace) {
+        Some((cmd, arg)) => (cmd, arg.trim()),
+        None => (line, ""),
+    };
+    match (cmd, arg) {
+        (".quit" | ".exit", _) => return Ok(false),
+        (".help", _) => println!(
+            ".schema <source>    show columns of a source\n\
+             .format <format>    set output format: csv, json, json-columns, ndjson, markdown, table\n\
+             .quit               exit"
+        ),
+        (".format", "") => println!("{:?}", format),
+        (".format", v) => *format = terminal_format(v.parse()?)?,
+        (".schema", "") => return Err(anyhow!("usage: .schema <source>")),
+        (".schema", source) => print(&schema(source).await?, *format),
+        _ => return Err(anyhow!("unknown command {}, try .help", cmd)),
+    }
+    Ok(true)
+}
+
+/// 输出到终端的格式，parquet 是二进制格式，不适合输出到终端
+fn terminal_format(format: OutputFormat) -> Result<OutputFormat> {
+    match format {
+        OutputFormat::Parquet => Err(anyhow!("cannot print parquet to terminal, use -o <file>")),
+        v => Ok(v),
+    }
+}
+
+fn print(ds: &DataSet, format: OutputFormat) {
+    match ds.write(format) {
+        Ok(data) => {
+            let _ = std::io::stdout().write_all(&data);
+        }
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
@@ -0,0 +1,249 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Result};
+use parquet::file::writer::InMemoryWriteableCursor;
+use polars::prelude::*;
+use serde_json::{Map, Number, Value};
+
+use crate::DataSet;
+
+/// 查询结果的输出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    /// 每行一个对象组成的 JSON 数组
+    Json,
+    /// 每列一个数组组成的 JSON 对象
+    JsonColumns,
+    NdJson,
+    Parquet,
+    Markdown,
+    /// 在终端中显示的表格
+    Table,
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            "json-columns" | "columnar" => Ok(Self::JsonColumns),
+            "ndjson" | "jsonl" => Ok(Self::NdJson),
+            "parquet" => Ok(Self::Parquet),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "table" => Ok(Self::Table),
+            v => Err(anyhow!("output format {} is not supported", v)),
+        }
+    }
+}
+
+impl DataSet {
+    /// 按照指定的格式输出，parquet 是二进制的，所以统一返回字节
+    pub fn write(&self, format: OutputFormat) -> Result<Vec<u8>> {
+        match format {
+            OutputFormat::Csv => self.to_csv().map(String::into_bytes),
+            OutputFormat::Json => self.to_json().map(String::into_bytes),
+            OutputFormat::JsonColumns => self.to_json_columns().map(String::into_bytes),
+            OutputFormat::NdJson => self.to_ndjson().map(String::into_bytes),
+            OutputFormat::Parquet => self.to_parquet(),
+            OutputFormat::Markdown => Ok(self.to_markdown_table().into_bytes()),
+            OutputFormat::Table => Ok(self.to_table().into_bytes()),
+        }
+    }
+
+    /// 从 DataSet 转换成 JSON 数组，每行是一个对象
+    pub fn to_json(&self) -> Result<String> {
+        let rows: Vec<_> = self.records().map(Value::Object).collect();
+        Ok(serde_json::to_string(&rows)?)
+    }
+
+    /// 从 DataSet 转换成 JSON 对象，每列是一个数组
+    pub fn to_json_columns(&self) -> Result<String> {
+        let columns: Map<_, _> = self
+            .get_columns()
+            .iter()
+            .map(|s| {
+                let values = (0..s.len()).map(|i| to_json_value(s.get(i))).collect();
+                (s.name().to_owned(), Value::Array(values))
+            })
+            .collect();
+        Ok(serde_json::to_string(&columns)?)
+    }
+
+    /// 从 DataSet 转换成 NDJSON，每行一个对象
+    pub fn to_ndjson(&self) -> Result<String> {
+        let mut buf = String::new();
+        for row in self.records() {
+            buf.push_str(&serde_json::to_string(&row)?);
+            buf.push('\n');
+        }
+        Ok(buf)
+    }
+
+    /// 从 DataSet 转换成 parquet
+    pub fn to_parquet(&self) -> Result<Vec<u8>> {
+        // ParquetWriter 要求 writer 可以 seek 和 clone，写完之后只剩下 cursor 持有数据
+        let cursor = InMemoryWriteableCursor::default();
+        ParquetWriter::new(cursor.clone()).finish(&self.0)?;
+        cursor
+            .into_inner()
+            .ok_or_else(|| anyhow!("parquet writer is still alive"))
+    }
+
+    /// 从 DataSet 转换成 markdown 表格
+    pub fn to_markdown_table(&self) -> String {
+        let escape = |v: &str| v.replace('|', "\\|");
+        let header: Vec<_> = self.get_column_names().into_iter().map(escape).collect();
+        let mut lines = vec![
+            format!("| {} |", header.join(" | ")),
+            format!("|{}", "---|".repeat(header.len())),
+        ];
+        for row in self.cells() {
+            let row: Vec<_> = row.iter().map(|v| escape(v)).collect();
+            lines.push(format!("| {} |", row.join(" | ")));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// 从 DataSet 转换成终端中显示的表格，每列按最长的值对齐
+    pub fn to_table(&self) -> String {
+        let header: Vec<_> = self
+            .get_column_names()
+            .into_iter()
+            .map(|v| v.to_owned())
+            .collect();
+        let rows = self.cells();
+        let widths: Vec<_> = header
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                rows.iter()
+                    .map(|row| row[i].chars().count())
+                    .fold(name.chars().count(), usize::max)
+            })
+            .collect();
+
+        let border = widths
+            .iter()
+            .map(|w| "-".repeat(w + 2))
+            .collect::<Vec<_>>()
+            .join("+");
+        let border = format!("+{}+", border);
+        let line = |row: &[String]| {
+            let cells: Vec<_> = row
+                .iter()
+                .zip(&widths)
+                .map(|(v, w)| format!(" {}{} ", v, " ".repeat(w - v.chars().count())))
+                .collect();
+            format!("|{}|", cells.join("|"))
+        };
+
+        let mut lines = vec![border.clone(), line(&header), border.clone()];
+        lines.extend(rows.iter().map(|row| line(row)));
+        lines.push(border);
+        lines.push(format!("({} rows)", self.height()));
+        lines.join("\n") + "\n"
+    }
+
+    /// 逐行生成 JSON 对象
+    fn records(&self) -> impl Iterator<Item = Map<String, Value>> + '_ {
+        let columns = self.get_columns();
+        (0..self.height()).map(move |i| {
+            columns
+                .iter()
+                .map(|s| (s.name().to_owned(), to_json_value(s.get(i))))
+                .collect()
+        })
+    }
+
+    /// 把所有的值转换成字符串，用来显示表格
+    fn cells(&self) -> Vec<Vec<String>> {
+        let columns = self.get_columns();
+        (0..self.height())
+            .map(|i| columns.iter().map(|s| to_cell(s.get(i))).collect())
+            .collect()
+    }
+}
+
+fn to_json_value(v: AnyValue) -> Value {
+    match v {
+        AnyValue::Null => Value::Null,
+        AnyValue::Boolean(v) => Value::Bool(v),
+        AnyValue::Utf8(v) => Value::String(v.to_owned()),
+        AnyValue::Int32(v) => Value::from(v),
+        AnyValue::Int64(v) => Value::from(v),
+        AnyValue::UInt32(v) => Value::from(v),
+        AnyValue::UInt64(v) => Value::from(v),
+        // NaN 和无穷大在 JSON 中没有对应的值
+        AnyValue::Float32(v) => Number::from_f64(v as f64).map_or(Value::Null, Value::Number),
+        AnyValue::Float64(v) => Number::from_f64(v).map_or(Value::Null, Value::Number),
+        v => Value::String(v.to_string()),
+    }
+}
+
+fn to_cell(v: AnyValue) -> String {
+    match v {
+        AnyValue::Null => String::new(),
+        // AnyValue 的 Display 会给字符串加上引号
+        AnyValue::Utf8(v) => v.to_owned(),
+        v => v.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::df;
+
+    fn dataset() -> DataSet {
+        let df = df!(
+            "name" => &["a|b", "c"],
+            "total" => &[Some(1.5), None],
+            "count" => &[1, 20]
+        )
+        .unwrap();
+        DataSet(df)
+    }
+
+    #[test]
+    fn to_json_works() {
+        let ds = dataset();
+        assert_eq!(
+            ds.to_json().unwrap(),
+            r#"[{"name":"a|b","total":1.5,"count":1},{"name":"c","total":null,"count":20}]"#
+        );
+        assert_eq!(
+            ds.to_json_columns().unwrap(),
+            r#"{"name":["a|b","c"],"total":[1.5,null],"count":[1,20]}"#
+        );
+        assert_eq!(ds.to_ndjson().unwrap().lines().count(), 2);
+    }
+
+    #[test]
+    fn to_table_works() {
+        let ds = dataset();
+        assert_eq!(
+            ds.to_markdown_table(),
+            "| name | total | count |\n|---|---|---|\n| a\\|b | 1.5 | 1 |\n| c |  | 20 |\n"
+        );
+        let table = ds.to_table();
+        assert!(table.starts_with("+------+-------+-------+\n| name | total | count |\n"));
+        assert!(table.contains("| c    |       | 20    |"));
+        assert!(table.ends_with("(2 rows)\n"));
+    }
+
+    #[test]
+    fn output_format_should_be_parsed() {
+        assert_eq!(
+            "MD".parse::<OutputFormat>().unwrap(),
+            OutputFormat::Markdown
+        );
+        assert_eq!(
+            "jsonl".parse::<OutputFormat>().unwrap(),
+            OutputFormat::NdJson
+        );
+        assert!("xml".parse::<OutputFormat>().is_err());
+    }
+}
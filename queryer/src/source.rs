@@ -0,0 +1,523 @@
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+
+use anyhow::{anyhow, Result};
+use futures::future::try_join_all;
+use polars::prelude::*;
+use sqlparser::ast::{
+    BinaryOperator as SqlBinaryOperator, Expr as SqlExpr, FunctionArg, JoinConstraint,
+    JoinOperator, SelectItem, SetExpr, Statement, TableFactor,
+};
+use tracing::info;
+
+use crate::{
+    cache,
+    fetcher::retrieve_data,
+    loader::{detect_content, split_format},
+    DataSet,
+};
+
+/// FROM 或者 JOIN 中的一个数据源
+struct Table {
+    source: String,
+    alias: Option<String>,
+}
+
+impl Table {
+    /// 限定列名时使用的表名，没有别名时就是数据源本身
+    fn name(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.source)
+    }
+}
+
+impl<'a> TryFrom<&'a TableFactor> for Table {
+    type Error = anyhow::Error;
+
+    fn try_from(table: &'a TableFactor) -> Result<Self, Self::Error> {
+        match table {
+            TableFactor::Table { name, alias, .. } => Ok(Table {
+                source: name.0.first().unwrap().value.clone(),
+                alias: alias.as_ref().map(|a| a.name.value.clone()),
+            }),
+            _ => Err(anyhow!("We only support table")),
+        }
+    }
+}
+
+/// JOIN 的方式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum JoinKind {
+    Inner,
+    Left,
+    Right,
+    Full,
+}
+
+/// 每个表的列在合并之后的名字
+struct Columns(Vec<(String, HashMap<String, String>)>);
+
+impl Columns {
+    /// 只在一个表中出现的列保持原名，多个表中都有的列改名为 `表名.列名`
+    /// 表名不能重复，同一个数据源出现多次时需要用别名区分
+    fn new(tables: &[Table], frames: &[DataFrame]) -> Result<Self> {
+        for (i, table) in tables.iter().enumerate() {
+            if tables[..i].iter().any(|t| t.name() == table.name()) {
+                return Err(anyhow!(
+                    "table {} is used more than once, please give each one an alias",
+                    table.name()
+                ));
+            }
+        }
+
+        let mut counts = HashMap::new();
+        for df in frames {
+            for name in df.get_column_names() {
+                *counts.entry(name.to_owned()).or_insert(0) += 1;
+            }
+        }
+
+        let columns = tables
+            .iter()
+            .zip(frames)
+            .map(|(table, df)| {
+                let columns = df
+                    .get_column_names()
+                    .into_iter()
+                    .map(|name| {
+                        let renamed = match counts[name] {
+                            1 => name.to_owned(),
+                            _ => format!("{}.{}", table.name(), name),
+                        };
+                        (name.to_owned(), renamed)
+                    })
+                    .collect();
+                (table.name().to_owned(), columns)
+            })
+            .collect();
+        Ok(Self(columns))
+    }
+
+    /// 把 SQL 中引用的列名解析成合并之后的列名，不是任何表的列时返回 None，比如 select 中的别名
+    fn resolve(&self, name: &str) -> Result<Option<&str>> {
+        // 没有别名的 url 中也有 '.'，按 `表名.` 前缀匹配，取最长的表名
+        let qualified = self
+            .0
+            .iter()
+            .filter_map(|(table, columns)| {
+                let column = name.strip_prefix(table.as_str())?.strip_prefix('.')?;
+                Some((table, column, columns))
+            })
+            .max_by_key(|(table, ..)| table.len());
+        if let Some((table, column, columns)) = qualified {
+            return match columns.get(column) {
+                Some(c) => Ok(Some(c.as_str())),
+                None => Err(anyhow!("column {} not found in table {}", column, table)),
+            };
+        }
+
+        let mut found = self.0.iter().filter_map(|(_, columns)| columns.get(name));
+        match (found.next(), found.next()) {
+            (Some(c), None) => Ok(Some(c.as_str())),
+            (Some(_), Some(_)) => Err(anyhow!("column {} is ambiguous", name)),
+            _ => Ok(None),
+        }
+    }
+
+    /// 把表达式中引用的列名改成合并之后的列名
+    fn rename(&self, expr: &mut SqlExpr) -> Result<()> {
+        match expr {
+            SqlExpr::Identifier(id) => {
+                if let Some(name) = self.resolve(&id.value)? {
+                    id.value = name.to_owned();
+                }
+            }
+            SqlExpr::BinaryOp { left, right, .. } => {
+                self.rename(left)?;
+                self.rename(right)?;
+            }
+            SqlExpr::UnaryOp { expr, .. }
+            | SqlExpr::Nested(expr)
+            | SqlExpr::IsNull(expr)
+            | SqlExpr::IsNotNull(expr)
+            | SqlExpr::Cast { expr, .. } => self.rename(expr)?,
+            SqlExpr::Between {
+                expr, low, high, ..
+            } => {
+                self.rename(expr)?;
+                self.rename(low)?;
+                self.rename(high)?;
+            }
+            SqlExpr::InList { expr, list, .. } => {
+                self.rename(expr)?;
+                for expr in list {
+                    self.rename(expr)?;
+                }
+            }
+            SqlExpr::Case {
+                operand,
+                conditions,
+                results,
+                else_result,
+            } => {
+                let exprs = operand.iter_mut().chain(else_result.iter_mut());
+                for expr in exprs {
+                    self.rename(expr)?;
+                }
+                for expr in conditions.iter_mut().chain(results.iter_mut()) {
+                    self.rename(expr)?;
+                }
+            }
+            SqlExpr::Function(f) => {
+                for arg in &mut f.args {
+                    match arg {
+                        FunctionArg::Named { arg, .. } | FunctionArg::Unnamed(arg) => {
+                            self.rename(arg)?
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// 解析 JOIN 的条件，只支持用 AND 连接的列相等，返回 (左表的列, 右表的列)
+    fn join_keys(
+        &self,
+        expr: &SqlExpr,
+        left: &[String],
+        right: &[String],
+        keys: &mut Vec<(String, String)>,
+    ) -> Result<()> {
+        match expr {
+            SqlExpr::BinaryOp {
+                left: l,
+                op: SqlBinaryOperator::And,
+                right: r,
+            } => {
+                self.join_keys(l, left, right, keys)?;
+                self.join_keys(r, left, right, keys)
+            }
+            SqlExpr::BinaryOp {
+                left: l,
+                op: SqlBinaryOperator::Eq,
+                right: r,
+            } => {
+                let (a, b) = (self.join_column(l)?, self.join_column(r)?);
+                if left.contains(&a) && right.contains(&b) {
+                    keys.push((a, b));
+                } else if left.contains(&b) && right.contains(&a) {
+                    keys.push((b, a));
+                } else {
+                    return Err(anyhow!("join condition {} must compare both tables", expr));
+                }
+                Ok(())
+            }
+            SqlExpr::Nested(expr) => self.join_keys(expr, left, right, keys),
+            expr => Err(anyhow!("join condition {} is not supported", expr)),
+        }
+    }
+
+    fn join_column(&self, expr: &SqlExpr) -> Result<String> {
+        match expr {
+            SqlExpr::Identifier(id) => match self.resolve(&id.value)? {
+                Some(name) => Ok(name.to_owned()),
+                None => Err(anyhow!("column {} not found", id.value)),
+            },
+            expr => Err(anyhow!(
+                "join condition only supports columns, got {}",
+                expr
+            )),
+        }
+    }
+}
+
+/// 加载 FROM 和 JOIN 中的所有数据源，按 JOIN 的条件合并成一个 DataSet
+/// 同时把语句中引用的列名改写成合并之后的列名，之后就可以像单个数据源一样处理
+pub async fn load_sources(statement: &mut Statement) -> Result<DataSet> {
+    let query = match statement {
+        Statement::Query(q) => q,
+        _ => return Err(anyhow!("We only support Select Query as the moment")),
+    };
+    let select = match &mut query.body {
+        SetExpr::Select(select) => select,
+        _ => return Err(anyhow!("We only support Select Query as the moment")),
+    };
+    if select.from.len() != 1 {
+        return Err(anyhow!("We only support single data source at the moment"));
+    }
+
+    let from = &select.from[0];
+    let mut tables = vec![Table::try_from(&from.relation)?];
+    let mut joins = Vec::with_capacity(from.joins.len());
+    for join in &from.joins {
+        tables.push((&join.relation).try_into()?);
+        let (kind, constraint) = match &join.join_operator {
+            JoinOperator::Inner(c) => (JoinKind::Inner, c),
+            JoinOperator::LeftOuter(c) => (JoinKind::Left, c),
+            JoinOperator::RightOuter(c) => (JoinKind::Right, c),
+            JoinOperator::FullOuter(c) => (JoinKind::Full, c),
+            _ => return Err(anyhow!("We only support INNER/LEFT/RIGHT/FULL JOIN")),
+        };
+        match constraint {
+            JoinConstraint::On(expr) => joins.push((kind, expr)),
+            _ => return Err(anyhow!("We only support JOIN ... ON")),
+        }
+    }
+
+    // 所有的数据源同时获取
+    let frames = try_join_all(tables.iter().map(|t| load(&t.source))).await?;
+    let columns = Columns::new(&tables, &frames)?;
+
+    let mut frames = frames.into_iter().zip(&columns.0);
+    let rename = |(mut df, (_, names)): (DataFrame, &(String, HashMap<String, String>))| {
+        for (name, renamed) in names.iter().filter(|(n, r)| n != r) {
+            df.rename(name, renamed)?;
+        }
+        Ok::<_, anyhow::Error>(df)
+    };
+    let mut df = rename(frames.next().unwrap())?;
+    for ((kind, on), right) in joins.into_iter().zip(frames) {
+        let right = rename(right)?;
+        let mut keys = Vec::new();
+        columns.join_keys(on, &names(&df), &names(&right), &mut keys)?;
+        df = join(df, right, keys, kind)?;
+    }
+
+    for item in &mut select.projection {
+        match item {
+            SelectItem::UnnamedExpr(expr) | SelectItem::ExprWithAlias { expr, .. } => {
+                columns.rename(expr)?
+            }
+            _ => {}
+        }
+    }
+    let exprs = select
+        .selection
+        .iter_mut()
+        .chain(select.group_by.iter_mut())
+        .chain(select.having.iter_mut())
+        .chain(query.order_by.iter_mut().map(|o| &mut o.expr));
+    for expr in exprs {
+        columns.rename(expr)?;
+    }
+
+    Ok(DataSet(df))
+}
+
+/// 从 source 读入一个 DataFrame
+/// detect_content，怎么 detect 不重要，重要的是它能根据内容返回 DataSet
+pub(crate) async fn load(source: &str) -> Result<DataFrame> {
+    info!("retrieving data from source: {}", source);
+
+    // source 可以用 `格式:` 前缀指定格式，比如 `tsv:file:///data.txt`
+    let (format, url) = split_format(source)?;
+    let content = retrieve_data(url).await?;
+
+    // 数据的版本没有变化时直接使用之前解析好的 DataFrame
+    let version = content.version.clone();
+    if let Some(df) = version.as_deref().and_then(|v| cache::get_frame(source, v)) {
+        info!("using cached dataframe: {}", source);
+        return Ok(df);
+    }
+    let ds = detect_content(format, url, content).load()?;
+    if let Some(v) = &version {
+        cache::put_frame(source, v, ds.0.clone());
+    }
+    Ok(ds.0)
+}
+
+fn names(df: &DataFrame) -> Vec<String> {
+    df.get_column_names()
+        .into_iter()
+        .map(|name| name.to_owned())
+        .collect()
+}
+
+/// 按 keys 合并两个 DataFrame，结果中左表的列在前
+fn join(
+    left: DataFrame,
+    right: DataFrame,
+    keys: Vec<(String, String)>,
+    kind: JoinKind,
+) -> Result<DataFrame> {
+    let mut order = names(&left);
+    order.extend(names(&right));
+
+    // RIGHT JOIN 就是交换左右两边的 LEFT JOIN
+    let (mut left, mut right, keys, how) = match kind {
+        JoinKind::Inner => (left, right, keys, JoinType::Inner),
+        JoinKind::Left => (left, right, keys, JoinType::Left),
+        JoinKind::Full => (left, right, keys, JoinType::Outer),
+        JoinKind::Right => {
+            let keys = keys.into_iter().map(|(l, r)| (r, l)).collect();
+            (right, left, keys, JoinType::Left)
+        }
+    };
+
+    // polars 会去掉右表中用来连接的列，FULL JOIN 时还会用右表的值填充左表的列，
+    // 两边都用拷贝来连接，保留原来的列，只在一边出现的行另一边的列都是 null
+    let mut left_on = Vec::with_capacity(keys.len());
+    let mut right_on = Vec::with_capacity(keys.len());
+    for (i, (l, r)) in keys.iter().enumerate() {
+        left_on.push(with_key_copy(&mut left, l, format!("__left_key_{}", i))?);
+        right_on.push(with_key_copy(&mut right, r, format!("__right_key_{}", i))?);
+    }
+
+    let left_on: Vec<_> = left_on.iter().map(|key| key.as_str()).collect();
+    let right_on: Vec<_> = right_on.iter().map(|key| key.as_str()).collect();
+    let df = left.join(&right, left_on, right_on, how)?;
+    let order: Vec<_> = order.iter().map(|name| name.as_str()).collect();
+    Ok(df.select(order)?)
+}
+
+/// 把 key 列拷贝一份，命名为 name
+fn with_key_copy(df: &mut DataFrame, key: &str, name: String) -> Result<String> {
+    let mut column = df.column(key)?.clone();
+    column.rename(&name);
+    df.with_column(column)?;
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::JoDialect;
+    use polars::df;
+    use sqlparser::parser::Parser;
+
+    fn columns() -> Columns {
+        let tables = [
+            Table {
+                source: "file:///a.csv".into(),
+                alias: Some("a".into()),
+            },
+            Table {
+                source: "file:///b.csv".into(),
+                alias: Some("b".into()),
+            },
+        ];
+        let a = df!("id" => &[1, 2], "name" => &["x", "y"]).unwrap();
+        let b = df!("id" => &[1, 3], "a_id" => &[2, 1]).unwrap();
+        Columns::new(&tables, &[a, b]).unwrap()
+    }
+
+    #[test]
+    fn resolve_columns_works() {
+        let columns = columns();
+        assert_eq!(columns.resolve("a.id").unwrap(), Some("a.id"));
+        assert_eq!(columns.resolve("b.id").unwrap(), Some("b.id"));
+        assert_eq!(columns.resolve("a.name").unwrap(), Some("name"));
+        assert_eq!(columns.resolve("name").unwrap(), Some("name"));
+        assert_eq!(columns.resolve("total").unwrap(), None);
+        assert!(columns.resolve("id").is_err());
+        assert!(columns.resolve("a.a_id").is_err());
+    }
+
+    #[test]
+    fn join_keys_works() {
+        let columns = columns();
+        let sql = "select * from a where b.a_id = a.id and (name = b.id)";
+        let statement = &Parser::parse_sql(&JoDialect::default(), sql).unwrap()[0];
+        let expr = match statement {
+            Statement::Query(q) => match &q.body {
+                SetExpr::Select(s) => s.selection.clone().unwrap(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        };
+
+        let left = vec!["a.id".to_owned(), "name".to_owned()];
+        let right = vec!["b.id".to_owned(), "a_id".to_owned()];
+        let mut keys = Vec::new();
+        columns.join_keys(&expr, &left, &right, &mut keys).unwrap();
+        assert_eq!(
+            keys,
+            vec![
+                ("a.id".to_owned(), "a_id".to_owned()),
+                ("name".to_owned(), "b.id".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_should_match_unaliased_url() {
+        let tables = [
+            Table {
+                source: "file:///a.csv".into(),
+                alias: None,
+            },
+            Table {
+                source: "file:///b.csv".into(),
+                alias: None,
+            },
+        ];
+        let a = df!("id" => &[1], "name" => &["x"]).unwrap();
+        let b = df!("id" => &[1]).unwrap();
+        let columns = Columns::new(&tables, &[a, b]).unwrap();
+        assert_eq!(
+            columns.resolve("file:///a.csv.id").unwrap(),
+            Some("file:///a.csv.id")
+        );
+        assert_eq!(columns.resolve("file:///a.csv.name").unwrap(), Some("name"));
+        assert!(columns.resolve("file:///b.csv.name").is_err());
+    }
+
+    #[test]
+    fn self_join_without_alias_should_fail() {
+        let tables = [
+            Table {
+                source: "file:///a.csv".into(),
+                alias: None,
+            },
+            Table {
+                source: "file:///a.csv".into(),
+                alias: None,
+            },
+        ];
+        let a = df!("id" => &[1]).unwrap();
+        assert!(Columns::new(&tables, &[a.clone(), a]).is_err());
+    }
+
+    /// 把每一行转换成字符串并排序，方便比较结果
+    fn rows(df: &DataFrame) -> Vec<String> {
+        let mut rows: Vec<_> = (0..df.height())
+            .map(|i| {
+                let row: Vec<_> = df.get(i).unwrap().iter().map(|v| v.to_string()).collect();
+                row.join(",")
+            })
+            .collect();
+        rows.sort();
+        rows
+    }
+
+    #[test]
+    fn join_works() {
+        let left = df!("id" => &[1, 2], "name" => &["x", "y"]).unwrap();
+        let right = df!("b.id" => &[1, 3], "a_id" => &[2, 5]).unwrap();
+        let keys = vec![("id".to_owned(), "a_id".to_owned())];
+        let join = |kind| join(left.clone(), right.clone(), keys.clone(), kind).unwrap();
+
+        let df = join(JoinKind::Inner);
+        assert_eq!(df.get_column_names(), &["id", "name", "b.id", "a_id"]);
+        assert_eq!(rows(&df), ["2,\"y\",1,2"]);
+
+        let df = join(JoinKind::Left);
+        assert_eq!(df.get_column_names(), &["id", "name", "b.id", "a_id"]);
+        assert_eq!(rows(&df), ["1,\"x\",null,null", "2,\"y\",1,2"]);
+
+        let df = join(JoinKind::Right);
+        assert_eq!(df.get_column_names(), &["id", "name", "b.id", "a_id"]);
+        assert_eq!(rows(&df), ["2,\"y\",1,2", "null,null,3,5"]);
+
+        // 只在右表中出现的行，左表的 key 也是 null
+        let df = join(JoinKind::Full);
+        assert_eq!(df.get_column_names(), &["id", "name", "b.id", "a_id"]);
+        assert_eq!(
+            rows(&df),
+            ["1,\"x\",null,null", "2,\"y\",1,2", "null,null,3,5"]
+        );
+    }
+}